@@ -1,28 +1,96 @@
-use std::net::TcpStream;
-
-use reqwest::{blocking::Client, StatusCode};
+use std::{
+    fmt,
+    sync::{Arc, Mutex},
+};
+
+use reqwest::{
+    blocking::{Client, ClientBuilder},
+    header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, RETRY_AFTER},
+    StatusCode,
+};
+use rustls::ClientConfig;
 use serde_json::{json, Value};
-use tungstenite::{connect, stream::MaybeTlsStream, Message, WebSocket};
-
-use crate::CliError;
+use url::Url;
 
-const PROTOCOL_VERSION: u64 = 3;
-const CONNECT_REQUEST_ID: &str = "connect-1";
-const RPC_REQUEST_ID: &str = "rpc-1";
-
-type WsSocket = WebSocket<MaybeTlsStream<TcpStream>>;
+use crate::{
+    retry::{self, connection_failure, rpc_failure, Verdict},
+    timeout::{remaining, Deadline, Limit, TimeoutPhase},
+    CliError, CredentialStore, DeviceIdentity, DeviceToken, EventSubscription, GatewaySession,
+    HelloOk, ProtocolRange, ProxySettings, RetryPolicy, Timeouts, TlsConfig, TokenStore,
+};
 
 pub trait GatewayClient {
     fn healthz(&self) -> Result<Value, CliError>;
     fn info(&self) -> Result<Value, CliError>;
     fn rpc(&self, method: &str, params: Value) -> Result<Value, CliError>;
+    fn hello(&self) -> Result<HelloOk, CliError>;
+    fn subscribe(&self, patterns: &[String]) -> Result<EventSubscription, CliError>;
+}
+
+/// Connection settings beyond the server URL and credentials.
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    pub protocol: ProtocolRange,
+    pub timeouts: Timeouts,
+    pub retry: RetryPolicy,
+    /// Signs the `connect` request, answering the gateway's
+    /// `connect.challenge` when it sends one.
+    pub device: Option<DeviceIdentity>,
+    /// Where device tokens issued in `hello-ok` are kept. With a `device`,
+    /// a stored token is presented whenever no explicit credentials are
+    /// configured.
+    pub tokens: Option<TokenStore>,
+    /// Asked once for a password when the gateway rejects a connect made
+    /// without credentials. Without it such a rejection is `InvalidAuth`.
+    pub password_prompt: Option<PasswordPrompt>,
+    /// Credentials saved by `login`, used when neither a token nor a
+    /// password is passed explicitly.
+    pub credentials: Option<CredentialStore>,
+    /// TLS settings for both the HTTP `GET`s and the WebSocket; the
+    /// libraries' built-in web PKI defaults when `None`.
+    pub tls: Option<TlsConfig>,
+    /// Proxies for both the HTTP `GET`s and the WebSocket. The process
+    /// environment is not consulted; see [`ProxySettings::from_env`].
+    pub proxy: ProxySettings,
+    /// Extra headers for the HTTP requests and the WebSocket upgrade. An
+    /// `Authorization` header here replaces the one built from the
+    /// configured credentials.
+    pub headers: Vec<(String, String)>,
+    /// A `ws://` or `wss://` URL to open the session at instead of `ws`
+    /// under the server URL. Over a `unix://` server it only sets the
+    /// upgrade's path and query.
+    pub ws_url: Option<String>,
+}
+
+/// Asks the user for the gateway password, e.g. on a terminal without echo.
+#[derive(Clone)]
+pub struct PasswordPrompt(Arc<dyn Fn() -> Result<String, CliError> + Send + Sync>);
+
+impl PasswordPrompt {
+    pub fn new(ask: impl Fn() -> Result<String, CliError> + Send + Sync + 'static) -> Self {
+        Self(Arc::new(ask))
+    }
+
+    pub fn ask(&self) -> Result<String, CliError> {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for PasswordPrompt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PasswordPrompt")
+    }
 }
 
 pub struct HttpGatewayClient {
     base_url: String,
+    options: ClientOptions,
+    deadline: Deadline,
     auth_token: Option<String>,
     auth_password: Option<String>,
     client: Client,
+    prompted_password: Mutex<Option<String>>,
+    session: Mutex<Option<Arc<GatewaySession>>>,
 }
 
 impl HttpGatewayClient {
@@ -34,96 +102,281 @@ impl HttpGatewayClient {
         base_url: impl Into<String>,
         auth_token: Option<String>,
         auth_password: Option<String>,
+    ) -> Result<Self, CliError> {
+        Self::new_with_options(
+            base_url,
+            auth_token,
+            auth_password,
+            ClientOptions::default(),
+        )
+    }
+
+    pub fn new_with_options(
+        base_url: impl Into<String>,
+        auth_token: Option<String>,
+        auth_password: Option<String>,
+        options: ClientOptions,
     ) -> Result<Self, CliError> {
         let base_url = normalize_base_url(base_url.into())?;
-        let auth_token = normalize_optional_secret(auth_token);
-        let auth_password = normalize_optional_secret(auth_password);
+        if let Some(ws_url) = &options.ws_url {
+            normalize_ws_url(ws_url)?;
+        }
+        let mut auth_token = normalize_optional_secret(auth_token);
+        let mut auth_password = normalize_optional_secret(auth_password);
+        if let (None, None, Some(store)) = (&auth_token, &auth_password, &options.credentials) {
+            if let Some(credential) = store.get(&base_url)? {
+                auth_token = credential.token();
+                auth_password = credential.password();
+            }
+        }
         if auth_token.is_some() && auth_password.is_some() {
             return Err(CliError::InvalidAuth(
                 "provide only one of --auth-token or --auth-password".to_owned(),
             ));
         }
 
-        let client = Client::builder()
+        let mut builder = Client::builder()
+            .timeout(None)
+            .no_proxy()
+            .default_headers(http_headers(
+                &options.headers,
+                auth_token.as_ref().or(auth_password.as_ref()),
+            )?);
+        if let Some(connect_timeout) = options.timeouts.connect {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+        if let Some(path) = unix_socket_path(&base_url) {
+            builder = unix_socket(builder, path);
+        } else if let Some(proxy) = options.proxy.route(
+            &url_host(&parse_url(&base_url)?),
+            base_url.starts_with("https://"),
+        ) {
+            let proxy = reqwest::Proxy::all(proxy.url())
+                .map_err(|error| CliError::InvalidOption(format!("invalid proxy: {error}")))?;
+            builder = builder.proxy(proxy);
+        }
+        if let Some(tls) = &options.tls {
+            builder = builder.use_preconfigured_tls(ClientConfig::clone(&tls.rustls()));
+        }
+        let client = builder
             .build()
             .map_err(|error| CliError::Transport(error.to_string()))?;
 
         Ok(Self {
             base_url,
+            deadline: Deadline::start(options.timeouts.deadline),
+            options,
             auth_token,
             auth_password,
             client,
+            prompted_password: Mutex::new(None),
+            session: Mutex::new(None),
         })
     }
 
     fn get(&self, path: &str) -> Result<Value, CliError> {
-        let path = normalize_path(path);
-        let url = format!("{}{}", self.base_url, path);
+        retry::run(&self.options.retry, self.deadline, || self.get_once(path))
+    }
 
-        let response = self
-            .client
-            .get(&url)
-            .send()
-            .map_err(|error| CliError::Transport(error.to_string()))?;
+    fn get_once(&self, path: &str) -> Result<Value, (CliError, Verdict)> {
+        let url = endpoint_url(&self.base_url, path).map_err(connection_failure)?;
 
-        if response.status() != StatusCode::OK {
-            return Err(CliError::Protocol(format!(
-                "unexpected status {} for GET {path}",
-                response.status()
-            )));
+        let limit = self
+            .deadline
+            .limit(self.options.timeouts.request, TimeoutPhase::Request)
+            .map_err(connection_failure)?;
+        let mut request = self.client.get(url);
+        if let Some(timeout) = remaining(limit).map_err(connection_failure)? {
+            request = request.timeout(timeout);
+        }
+        let response = request
+            .send()
+            .map_err(|error| connection_failure(self.http_error(error, limit)))?;
+
+        let status = response.status();
+        if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+            // What a reverse proxy answers while the gateway behind it restarts.
+            let after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(retry::parse_retry_after);
+            let error = CliError::Transport(format!("status {status} for GET {path}"));
+            return Err((error, Verdict::Retry { after }));
+        }
+        if matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) {
+            return Err(connection_failure(CliError::Unauthorized(format!(
+                "status {status} for GET {path}"
+            ))));
+        }
+        if status != StatusCode::OK {
+            return Err(connection_failure(CliError::Protocol(format!(
+                "unexpected status {status} for GET {path}"
+            ))));
         }
 
         response
             .json::<Value>()
-            .map_err(|error| CliError::Protocol(error.to_string()))
+            .map_err(|error| {
+                if error.is_timeout() {
+                    self.http_error(error, limit)
+                } else {
+                    CliError::Protocol(error.to_string())
+                }
+            })
+            .map_err(connection_failure)
     }
 
-    fn post_rpc(&self, method: &str, params: Value) -> Result<Value, CliError> {
-        let ws_url = websocket_url(&self.base_url);
-        let (mut socket, _) = connect(ws_url.as_str())
-            .map_err(|error| CliError::Transport(format!("websocket connect failed: {error}")))?;
+    fn http_error(&self, error: reqwest::Error, limit: Option<Limit>) -> CliError {
+        if error.is_timeout() {
+            if let (true, Some(after)) = (error.is_connect(), self.options.timeouts.connect) {
+                return CliError::Timeout {
+                    phase: TimeoutPhase::Connect,
+                    after,
+                };
+            }
+            if let Some(limit) = limit {
+                return limit.error();
+            }
+        }
 
-        let auth = match (&self.auth_token, &self.auth_password) {
-            (Some(token), None) => json!({ "token": token }),
-            (None, Some(password)) => json!({ "password": password }),
-            _ => Value::Null,
+        CliError::Transport(error.to_string())
+    }
+
+    /// Opens a dedicated gateway session that performs the `connect`
+    /// handshake once and can carry many concurrent requests.
+    pub fn connect_session(&self) -> Result<GatewaySession, CliError> {
+        let session = match self.connect_with_stored_token() {
+            Err(CliError::Gateway(error)) if error.is_auth_failure() && !self.has_credentials() => {
+                let Some(prompt) = &self.options.password_prompt else {
+                    return Err(CliError::InvalidAuth(format!(
+                        "gateway requires credentials ({error}); pass --auth-token, --auth-password or another credential source"
+                    )));
+                };
+                let password = normalize_optional_secret(Some(prompt.ask()?))
+                    .ok_or_else(|| CliError::InvalidAuth("empty password".to_owned()))?;
+                *self
+                    .prompted_password
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(password);
+                self.open_session(None)?
+            }
+            result => result?,
         };
 
-        send_json(
-            &mut socket,
-            &json!({
-                "type": "req",
-                "id": CONNECT_REQUEST_ID,
-                "method": "connect",
-                "params": {
-                    "minProtocol": PROTOCOL_VERSION,
-                    "maxProtocol": PROTOCOL_VERSION,
-                    "role": "operator",
-                    "client": {
-                        "id": "reclaw-cli",
-                        "version": env!("CARGO_PKG_VERSION"),
-                        "platform": "cli",
-                        "mode": "operator"
-                    },
-                    "auth": auth,
+        self.remember_device_token(&session)?;
+        Ok(session)
+    }
+
+    fn connect_with_stored_token(&self) -> Result<GatewaySession, CliError> {
+        let stored = self.stored_device_token()?;
+        match self.open_session(stored.as_ref()) {
+            Err(CliError::Gateway(error)) if stored.is_some() && error.is_auth_failure() => {
+                // The gateway no longer honours the stored token; forget it
+                // and connect as an unpaired device.
+                if let Some((tokens, device)) = self.device_tokens() {
+                    tokens.remove(&self.base_url, device.id())?;
                 }
-            }),
-        )?;
-        let _ = read_response_payload(&mut socket, CONNECT_REQUEST_ID)?;
-
-        send_json(
-            &mut socket,
-            &json!({
-                "type": "req",
-                "id": RPC_REQUEST_ID,
-                "method": method,
-                "params": params,
-            }),
-        )?;
-        let payload = read_response_payload(&mut socket, RPC_REQUEST_ID)?;
-
-        let _ = socket.close(None);
-        Ok(payload)
+                self.open_session(None)
+            }
+            result => result,
+        }
+    }
+
+    /// The configured password, or the one the user typed at the prompt.
+    fn password(&self) -> Option<String> {
+        self.auth_password.clone().or_else(|| {
+            self.prompted_password
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner())
+                .clone()
+        })
+    }
+
+    fn has_credentials(&self) -> bool {
+        self.auth_token.is_some() || self.password().is_some()
+    }
+
+    fn open_session(&self, device_token: Option<&DeviceToken>) -> Result<GatewaySession, CliError> {
+        GatewaySession::open(
+            &websocket_url(&self.base_url, self.options.ws_url.as_deref())?,
+            &self.options,
+            self.deadline,
+            self.connect_params(device_token),
+        )
+    }
+
+    fn device_tokens(&self) -> Option<(&TokenStore, &DeviceIdentity)> {
+        Some((self.options.tokens.as_ref()?, self.options.device.as_ref()?))
+    }
+
+    fn stored_device_token(&self) -> Result<Option<DeviceToken>, CliError> {
+        if self.has_credentials() {
+            return Ok(None);
+        }
+        match self.device_tokens() {
+            Some((tokens, device)) => tokens.find(&self.base_url, device.id()),
+            None => Ok(None),
+        }
+    }
+
+    fn remember_device_token(&self, session: &GatewaySession) -> Result<(), CliError> {
+        let (Some((tokens, device)), Some(auth)) = (self.device_tokens(), &session.hello().auth)
+        else {
+            return Ok(());
+        };
+        let Some(token) = &auth.device_token else {
+            return Ok(());
+        };
+
+        tokens.save(DeviceToken {
+            server: self.base_url.clone(),
+            device_id: device.id().to_owned(),
+            token: token.clone(),
+            role: auth.role.clone(),
+            scopes: auth.scopes.clone(),
+            issued_at_ms: auth.issued_at_ms,
+        })
+    }
+
+    fn shared_session(&self) -> Result<Arc<GatewaySession>, CliError> {
+        retry::run(&self.options.retry, self.deadline, || {
+            self.shared_session_once().map_err(connection_failure)
+        })
+    }
+
+    fn shared_session_once(&self) -> Result<Arc<GatewaySession>, CliError> {
+        let mut cached = self
+            .session
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(session) = cached.as_ref().filter(|session| !session.is_closed()) {
+            return Ok(Arc::clone(session));
+        }
+
+        let session = Arc::new(self.connect_session()?);
+        *cached = Some(Arc::clone(&session));
+        Ok(session)
+    }
+
+    fn connect_params(&self, device_token: Option<&DeviceToken>) -> Value {
+        let auth = match (&self.auth_token, &self.password(), device_token) {
+            (Some(token), None, _) => json!({ "token": token }),
+            (None, Some(password), _) => json!({ "password": password }),
+            (None, None, Some(stored)) => json!({ "token": stored.token }),
+            _ => Value::Null,
+        };
+
+        json!({
+            "role": "operator",
+            "client": {
+                "id": "reclaw-cli",
+                "version": env!("CARGO_PKG_VERSION"),
+                "platform": "cli",
+                "mode": "operator"
+            },
+            "auth": auth,
+        })
     }
 }
 
@@ -137,93 +390,159 @@ impl GatewayClient for HttpGatewayClient {
     }
 
     fn rpc(&self, method: &str, params: Value) -> Result<Value, CliError> {
-        self.post_rpc(method, params)
+        let safe_method = self.options.retry.is_safe_method(method);
+        retry::run(&self.options.retry, self.deadline, || {
+            let session = self.shared_session_once().map_err(connection_failure)?;
+            let pending = session
+                .send(method, params.clone())
+                .map_err(connection_failure)?;
+            pending
+                .wait()
+                .map_err(|error| rpc_failure(error, safe_method))
+        })
     }
-}
 
-fn send_json(socket: &mut WsSocket, payload: &Value) -> Result<(), CliError> {
-    let encoded = serde_json::to_string(payload).map_err(|error| {
-        CliError::Protocol(format!("failed to encode websocket frame: {error}"))
-    })?;
-    socket
-        .send(Message::Text(encoded.into()))
-        .map_err(|error| CliError::Transport(format!("websocket send failed: {error}")))
+    fn hello(&self) -> Result<HelloOk, CliError> {
+        Ok(self.shared_session()?.hello().clone())
+    }
+
+    fn subscribe(&self, patterns: &[String]) -> Result<EventSubscription, CliError> {
+        let session = self.shared_session()?;
+        Ok(session.subscribe(patterns)?.keep_alive(session))
+    }
 }
 
-fn read_response_payload(socket: &mut WsSocket, expected_id: &str) -> Result<Value, CliError> {
-    loop {
-        let frame = read_json_frame(socket)?;
+pub(crate) fn normalize_base_url(input: String) -> Result<String, CliError> {
+    let trimmed = input.trim();
+    if trimmed.is_empty() {
+        return Err(CliError::InvalidServer(
+            "server URL cannot be empty".to_owned(),
+        ));
+    }
 
-        if frame.get("type").and_then(Value::as_str) != Some("res") {
-            continue;
+    if let Some(path) = unix_socket_path(trimmed) {
+        let path = path.trim_end_matches('/');
+        if !cfg!(unix) {
+            return Err(CliError::InvalidServer(
+                "unix sockets are not supported on this platform".to_owned(),
+            ));
         }
-
-        if frame.get("id").and_then(Value::as_str) != Some(expected_id) {
-            continue;
+        if !path.starts_with('/') || path.len() == 1 {
+            return Err(CliError::InvalidServer(
+                "unix socket URLs need an absolute path, e.g. unix:///run/reclaw/gateway.sock"
+                    .to_owned(),
+            ));
         }
+        return Ok(format!("unix://{path}"));
+    }
+    let mut url = Url::parse(trimmed)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+        .ok_or_else(|| {
+            CliError::InvalidServer(
+                "server URL must start with http://, https:// or unix://".to_owned(),
+            )
+        })?;
+    if url.host().is_none() {
+        return Err(CliError::InvalidServer(format!(
+            "server URL has no host: {trimmed}"
+        )));
+    }
+    url.set_fragment(None);
+    let path = url.path().trim_end_matches('/').to_owned();
+    url.set_path(&path);
+    // `Url` always keeps a `/` path; drop it so `http://host/` and
+    // `http://host` name the same server.
+    let mut normalized = url.to_string();
+    if url.query().is_none() && url.path() == "/" {
+        normalized.pop();
+    }
+    Ok(normalized)
+}
 
-        if frame.get("ok").and_then(Value::as_bool).unwrap_or(false) {
-            return Ok(frame.get("payload").cloned().unwrap_or(Value::Null));
-        }
+/// Checks a `--ws-url` value: an absolute `ws://` or `wss://` URL.
+fn normalize_ws_url(input: &str) -> Result<Url, CliError> {
+    let url = Url::parse(input.trim())
+        .map_err(|error| CliError::InvalidServer(format!("invalid websocket URL: {error}")))?;
+    if !matches!(url.scheme(), "ws" | "wss") || url.host().is_none() {
+        return Err(CliError::InvalidServer(
+            "websocket URL must start with ws:// or wss:// and name a host".to_owned(),
+        ));
+    }
+    Ok(url)
+}
+
+fn parse_url(url: &str) -> Result<Url, CliError> {
+    Url::parse(url).map_err(|error| CliError::InvalidServer(format!("{url}: {error}")))
+}
 
-        let message = frame
-            .get("error")
-            .and_then(|error| error.get("message"))
-            .and_then(Value::as_str)
-            .unwrap_or("rpc request failed");
-        return Err(CliError::Protocol(message.to_owned()));
+/// The URL's host without the brackets around an IPv6 literal.
+fn url_host(url: &Url) -> String {
+    match url.host() {
+        Some(url::Host::Ipv6(address)) => address.to_string(),
+        Some(host) => host.to_string(),
+        None => String::new(),
     }
 }
 
-fn read_json_frame(socket: &mut WsSocket) -> Result<Value, CliError> {
-    loop {
-        let message = socket
-            .read()
-            .map_err(|error| CliError::Transport(format!("websocket read failed: {error}")))?;
-
-        match message {
-            Message::Text(text) => {
-                return serde_json::from_str(text.as_ref()).map_err(|error| {
-                    CliError::Protocol(format!("invalid websocket frame JSON: {error}"))
-                });
-            }
-            Message::Binary(_) => {
-                return Err(CliError::Protocol(
-                    "unexpected binary websocket frame".to_owned(),
-                ));
-            }
-            Message::Ping(payload) => {
-                socket.send(Message::Pong(payload)).map_err(|error| {
-                    CliError::Transport(format!("websocket pong failed: {error}"))
-                })?;
-            }
-            Message::Pong(_) => continue,
-            Message::Close(_) => {
-                return Err(CliError::Protocol(
-                    "websocket closed before response".to_owned(),
-                ));
-            }
-            Message::Frame(_) => continue,
-        }
+/// `path` resolved under the server URL: any base path is kept, the
+/// endpoint is appended below it and the server URL's query is carried
+/// over. A `unix://` server is addressed as `http://localhost`.
+fn endpoint_url(base_url: &str, path: &str) -> Result<Url, CliError> {
+    let mut base = match unix_socket_path(base_url) {
+        Some(_) => parse_url("http://localhost/")?,
+        None => parse_url(base_url)?,
+    };
+    if !base.path().ends_with('/') {
+        let path = format!("{}/", base.path());
+        base.set_path(&path);
     }
+    let mut url = base
+        .join(path.trim_start_matches('/'))
+        .map_err(|error| CliError::InvalidServer(format!("{base_url}: {error}")))?;
+    url.set_query(base.query());
+    Ok(url)
 }
 
-fn normalize_base_url(input: String) -> Result<String, CliError> {
-    let trimmed = input.trim();
-    if trimmed.is_empty() {
-        return Err(CliError::InvalidServer(
-            "server URL cannot be empty".to_owned(),
-        ));
+/// The headers sent with every HTTP request: `options.headers`, plus the
+/// token or password as a bearer `Authorization`, as the `connect` params
+/// carry it, unless one was given.
+fn http_headers(
+    headers: &[(String, String)],
+    secret: Option<&String>,
+) -> Result<HeaderMap, CliError> {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| CliError::InvalidOption(format!("invalid header name `{name}`")))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| CliError::InvalidOption(format!("invalid value for header `{name}`")))?;
+        map.append(name, value);
     }
 
-    let without_trailing = trimmed.trim_end_matches('/').to_owned();
-    if !(without_trailing.starts_with("http://") || without_trailing.starts_with("https://")) {
-        return Err(CliError::InvalidServer(
-            "server URL must start with http:// or https://".to_owned(),
-        ));
+    if let (false, Some(secret)) = (map.contains_key(AUTHORIZATION), secret) {
+        let mut value = HeaderValue::from_str(&format!("Bearer {secret}")).map_err(|_| {
+            CliError::InvalidAuth("credentials cannot be sent in an HTTP header".to_owned())
+        })?;
+        value.set_sensitive(true);
+        map.insert(AUTHORIZATION, value);
     }
+    Ok(map)
+}
+
+/// The socket path of a `unix://` gateway URL.
+fn unix_socket_path(base_url: &str) -> Option<&str> {
+    base_url.strip_prefix("unix://")
+}
 
-    Ok(without_trailing)
+#[cfg(unix)]
+fn unix_socket(builder: ClientBuilder, path: &str) -> ClientBuilder {
+    builder.unix_socket(path.to_owned())
+}
+
+#[cfg(not(unix))]
+fn unix_socket(builder: ClientBuilder, _path: &str) -> ClientBuilder {
+    builder
 }
 
 fn normalize_optional_secret(value: Option<String>) -> Option<String> {
@@ -237,34 +556,51 @@ fn normalize_optional_secret(value: Option<String>) -> Option<String> {
     })
 }
 
-fn normalize_path(path: &str) -> String {
-    if path.starts_with('/') {
-        path.to_owned()
-    } else {
-        format!("/{path}")
+/// The session URL: `ws_url` when given, otherwise `ws` under the server
+/// URL with the scheme switched to `ws`/`wss`.
+fn websocket_url(base_url: &str, ws_url: Option<&str>) -> Result<String, CliError> {
+    if unix_socket_path(base_url).is_some() {
+        // The session dials the socket itself and upgrades at `/ws` or the
+        // path of `ws_url`.
+        return Ok(base_url.to_owned());
     }
-}
-
-fn websocket_url(base_url: &str) -> String {
-    if let Some(host) = base_url.strip_prefix("http://") {
-        format!("ws://{host}/ws")
-    } else if let Some(host) = base_url.strip_prefix("https://") {
-        format!("wss://{host}/ws")
-    } else {
-        format!("{base_url}/ws")
+    if let Some(ws_url) = ws_url {
+        return normalize_ws_url(ws_url).map(String::from);
     }
+
+    let mut url = endpoint_url(base_url, "ws")?;
+    let scheme = if url.scheme() == "https" { "wss" } else { "ws" };
+    url.set_scheme(scheme).map_err(|()| {
+        CliError::InvalidServer(format!("cannot derive a websocket URL from {base_url}"))
+    })?;
+    Ok(url.into())
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{net::TcpListener, thread};
+    use std::{
+        io::{self, Read, Write},
+        net::{Shutdown, TcpListener, TcpStream},
+        thread,
+        time::Duration,
+    };
 
     use serde_json::{json, Value};
-    use tungstenite::{accept, Message};
+    use tungstenite::{
+        accept,
+        handshake::server::{Callback, ErrorResponse, Request, Response},
+        Message,
+    };
+    use url::Url;
 
     use crate::{
-        client::{normalize_base_url, normalize_optional_secret, websocket_url, HttpGatewayClient},
-        CliError, GatewayClient,
+        client::{
+            endpoint_url, normalize_base_url, normalize_optional_secret, url_host, websocket_url,
+            HttpGatewayClient,
+        },
+        CliError, ClientOptions, Credential, CredentialStore, DeviceIdentity, GatewayClient,
+        KeySource, PasswordPrompt, Proxy, ProxySettings, RetryPolicy, StateDir, TimeoutPhase,
+        Timeouts, TokenStore,
     };
 
     #[test]
@@ -279,13 +615,216 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn http_and_upgrade_requests_carry_configured_headers() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let answer_info = || {
+                let (mut stream, _) = listener.accept().expect("connection should arrive");
+                let mut head = Vec::new();
+                let mut byte = [0_u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).expect("request should arrive");
+                    head.push(byte[0]);
+                }
+                let body = r#"{"runtime":"reclaw-core"}"#;
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                )
+                .expect("response should be sent");
+                String::from_utf8_lossy(&head).to_lowercase()
+            };
+            let head = answer_info();
+
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut upgrade_headers = Vec::new();
+            let mut ws = tungstenite::accept_hdr(stream, RecordHeaders(&mut upgrade_headers))
+                .expect("websocket handshake should succeed");
+            let connect_frame = read_frame(&mut ws);
+            ws.send(Message::Text(
+                json!({ "type": "res", "id": "connect-1", "ok": true, "payload": { "type": "hello-ok" } })
+                    .to_string()
+                    .into(),
+            ))
+            .expect("connect response should be sent");
+            let _ = ws.read();
+            drop(ws);
+            (head, upgrade_headers, connect_frame, answer_info())
+        });
+
+        let options = ClientOptions {
+            headers: vec![("X-Forwarded-User".to_owned(), "alice".to_owned())],
+            ..ClientOptions::default()
+        };
+        let client = HttpGatewayClient::new_with_options(
+            format!("http://{addr}"),
+            Some("token-123".to_owned()),
+            None,
+            options,
+        )
+        .expect("client should build");
+        client.info().expect("info should succeed");
+        client.hello().expect("hello should succeed");
+        drop(client);
+        let client = HttpGatewayClient::new_with_options(
+            format!("http://{addr}"),
+            None,
+            Some("hunter2".to_owned()),
+            ClientOptions::default(),
+        )
+        .expect("client should build");
+        client.info().expect("info should succeed");
+        drop(client);
+
+        let (http_head, upgrade_headers, connect_frame, password_head) =
+            server.join().expect("server should finish");
+        assert!(http_head.contains("authorization: bearer token-123\r\n"));
+        assert!(password_head.contains("authorization: bearer hunter2\r\n"));
+        assert!(http_head.contains("x-forwarded-user: alice\r\n"));
+        assert!(upgrade_headers.contains(&"x-forwarded-user: alice".to_owned()));
+        assert!(!upgrade_headers
+            .iter()
+            .any(|header| header.starts_with("authorization")));
+        assert_eq!(connect_frame["params"]["auth"]["token"], "token-123");
+
+        assert!(matches!(
+            HttpGatewayClient::new_with_options(
+                "http://127.0.0.1:18789",
+                None,
+                None,
+                ClientOptions {
+                    headers: vec![("Bad Name".to_owned(), "x".to_owned())],
+                    ..ClientOptions::default()
+                },
+            ),
+            Err(CliError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn normalize_base_url_accepts_absolute_unix_socket_paths() {
+        if cfg!(unix) {
+            assert_eq!(
+                normalize_base_url("unix:///run/reclaw/gateway.sock".to_owned())
+                    .expect("unix URL should normalize"),
+                "unix:///run/reclaw/gateway.sock"
+            );
+        }
+        assert!(matches!(
+            normalize_base_url("unix://gateway.sock".to_owned()),
+            Err(CliError::InvalidServer(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn http_gets_and_websocket_use_the_unix_socket() {
+        use std::os::unix::net::UnixListener;
+
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let socket = dir.path().join("gateway.sock");
+        let listener = UnixListener::bind(&socket).expect("socket should bind");
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("connection should arrive");
+            let mut head = Vec::new();
+            let mut byte = [0_u8; 1];
+            while !head.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).expect("request should arrive");
+                head.push(byte[0]);
+            }
+            let body = r#"{"ok":true}"#;
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            )
+            .expect("response should be sent");
+            drop(stream);
+
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            let _ = read_frame(&mut ws);
+            ws.send(Message::Text(
+                json!({ "type": "res", "id": "connect-1", "ok": true, "payload": { "type": "hello-ok" } })
+                    .to_string()
+                    .into(),
+            ))
+            .expect("connect response should be sent");
+            let _ = ws.read();
+            String::from_utf8_lossy(&head)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_owned()
+        });
+
+        let client = HttpGatewayClient::new(format!("unix://{}", socket.display()))
+            .expect("client should build");
+        assert_eq!(
+            client.healthz().expect("healthz should use the socket")["ok"],
+            true
+        );
+        client.hello().expect("hello should use the socket");
+        drop(client);
+
+        let request_line = server.join().expect("server should finish");
+        assert_eq!(request_line, "GET /healthz HTTP/1.1");
+    }
+
     #[test]
     fn websocket_url_maps_http_to_ws() {
         assert_eq!(
-            websocket_url("http://127.0.0.1:18789"),
+            websocket_url("http://127.0.0.1:18789", None).expect("URL should map"),
             "ws://127.0.0.1:18789/ws"
         );
-        assert_eq!(websocket_url("https://example.com"), "wss://example.com/ws");
+        assert_eq!(
+            websocket_url("https://example.com", None).expect("URL should map"),
+            "wss://example.com/ws"
+        );
+    }
+
+    #[test]
+    fn urls_keep_base_paths_queries_and_ipv6_hosts() {
+        let base = normalize_base_url("HTTPS://Example.com:443/reclaw/?tenant=ops#top".to_owned())
+            .expect("URL should normalize");
+        assert_eq!(base, "https://example.com/reclaw?tenant=ops");
+        assert_eq!(
+            endpoint_url(&base, "/healthz")
+                .expect("endpoint should resolve")
+                .as_str(),
+            "https://example.com/reclaw/healthz?tenant=ops"
+        );
+        assert_eq!(
+            websocket_url(&base, None).expect("URL should map"),
+            "wss://example.com/reclaw/ws?tenant=ops"
+        );
+        assert_eq!(
+            websocket_url(&base, Some("wss://example.com/")).expect("override should apply"),
+            "wss://example.com/"
+        );
+        assert!(matches!(
+            websocket_url(&base, Some("https://example.com/ws")),
+            Err(CliError::InvalidServer(_))
+        ));
+
+        let ipv6 = normalize_base_url("http://[::1]:18789/".to_owned())
+            .expect("IPv6 URL should normalize");
+        assert_eq!(ipv6, "http://[::1]:18789");
+        assert_eq!(
+            websocket_url(&ipv6, None).expect("URL should map"),
+            "ws://[::1]:18789/ws"
+        );
+        assert_eq!(
+            url_host(&Url::parse(&ipv6).expect("URL should parse")),
+            "::1"
+        );
     }
 
     #[test]
@@ -309,6 +848,210 @@ mod tests {
         assert!(matches!(result, Err(CliError::InvalidAuth(_))));
     }
 
+    #[test]
+    fn get_reports_request_timeout_when_gateway_hangs() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            thread::sleep(Duration::from_millis(500));
+            drop(stream);
+        });
+
+        let options = ClientOptions {
+            timeouts: Timeouts {
+                request: Some(Duration::from_millis(100)),
+                ..Timeouts::default()
+            },
+            ..ClientOptions::default()
+        };
+        let client =
+            HttpGatewayClient::new_with_options(format!("http://{addr}"), None, None, options)
+                .expect("client should build");
+        let result = client.info();
+        assert!(
+            matches!(
+                result,
+                Err(CliError::Timeout {
+                    phase: TimeoutPhase::Request,
+                    ..
+                })
+            ),
+            "{result:?}"
+        );
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn get_retries_when_connection_drops() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("first connection should arrive");
+            drop(stream);
+
+            let (mut stream, _) = listener.accept().expect("retry should arrive");
+            let mut buffer = [0_u8; 1024];
+            let _ = stream.read(&mut buffer);
+            let body = r#"{"ok":true}"#;
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                body.len()
+            );
+        });
+
+        let options = ClientOptions {
+            retry: RetryPolicy {
+                base_delay: Duration::from_millis(10),
+                ..RetryPolicy::default()
+            },
+            ..ClientOptions::default()
+        };
+        let client =
+            HttpGatewayClient::new_with_options(format!("http://{addr}"), None, None, options)
+                .expect("client should build");
+        let result = client.healthz().expect("retried healthz should succeed");
+        assert_eq!(result["ok"], true);
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn get_retries_unavailable_responses_after_retry_after() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let responses = [
+                (
+                    "503 Service Unavailable",
+                    "retry-after: 1\r\n",
+                    "restarting",
+                ),
+                ("200 OK", "", r#"{"ok":true}"#),
+            ];
+            for (status, extra, body) in responses {
+                let (mut stream, _) = listener.accept().expect("request should arrive");
+                let mut buffer = [0_u8; 1024];
+                let _ = stream.read(&mut buffer);
+                let _ = write!(
+                    stream,
+                    "HTTP/1.1 {status}\r\n{extra}content-length: {}\r\nconnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+            }
+        });
+
+        let options = ClientOptions {
+            retry: RetryPolicy {
+                base_delay: Duration::from_millis(10),
+                ..RetryPolicy::default()
+            },
+            ..ClientOptions::default()
+        };
+        let client =
+            HttpGatewayClient::new_with_options(format!("http://{addr}"), None, None, options)
+                .expect("client should build");
+        let started = std::time::Instant::now();
+        let result = client.healthz().expect("retried healthz should succeed");
+        assert_eq!(result["ok"], true);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+        drop(client);
+        let _ = server.join();
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("request should arrive");
+            let mut buffer = [0_u8; 1024];
+            let _ = stream.read(&mut buffer);
+            let _ = write!(
+                stream,
+                "HTTP/1.1 502 Bad Gateway\r\ncontent-length: 0\r\nconnection: close\r\n\r\n"
+            );
+        });
+        let options = ClientOptions {
+            retry: RetryPolicy::none(),
+            ..ClientOptions::default()
+        };
+        let client =
+            HttpGatewayClient::new_with_options(format!("http://{addr}"), None, None, options)
+                .expect("client should build");
+        match client.info() {
+            Err(error @ CliError::Transport(_)) => assert_eq!(error.exit_code(), 10),
+            other => panic!("expected a transport failure, got {other:?}"),
+        }
+        drop(client);
+        let _ = server.join();
+    }
+
+    #[test]
+    fn rpc_retries_errors_the_gateway_marks_retryable() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            let _ = read_frame(&mut ws);
+            ws.send(Message::Text(
+                json!({ "type": "res", "id": "connect-1", "ok": true, "payload": {} })
+                    .to_string()
+                    .into(),
+            ))
+            .expect("connect response should be sent");
+
+            let first = read_frame(&mut ws);
+            ws.send(Message::Text(
+                json!({
+                    "type": "res",
+                    "id": first["id"],
+                    "ok": false,
+                    "error": {
+                        "code": "UNAVAILABLE",
+                        "message": "warming up",
+                        "retryable": true,
+                        "retryAfterMs": 5
+                    }
+                })
+                .to_string()
+                .into(),
+            ))
+            .expect("retryable error should be sent");
+
+            let second = read_frame(&mut ws);
+            ws.send(Message::Text(
+                json!({ "type": "res", "id": second["id"], "ok": true, "payload": { "ok": true } })
+                    .to_string()
+                    .into(),
+            ))
+            .expect("rpc response should be sent");
+        });
+
+        let client = HttpGatewayClient::new(format!("http://{addr}")).expect("client should build");
+        let result = client
+            .rpc("sessions.delete", json!({}))
+            .expect("retryable error should be retried");
+        assert_eq!(result["ok"], true);
+
+        drop(client);
+        let _ = server.join();
+    }
+
     #[test]
     fn rpc_uses_websocket_handshake_and_returns_payload() {
         let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
@@ -360,7 +1103,7 @@ mod tests {
     }
 
     #[test]
-    fn rpc_returns_protocol_error_from_gateway_frame() {
+    fn rpc_returns_gateway_error_from_gateway_frame() {
         let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
         let addr = listener
             .local_addr()
@@ -401,13 +1144,183 @@ mod tests {
         let result = client.rpc("health", json!({}));
 
         match result {
-            Err(CliError::Protocol(message)) => assert!(message.contains("bad params")),
-            other => panic!("expected protocol error, got {other:?}"),
+            Err(CliError::Gateway(error)) => {
+                assert_eq!(error.code.as_deref(), Some("INVALID_REQUEST"));
+                assert_eq!(error.message, "bad params");
+            }
+            other => panic!("expected gateway error, got {other:?}"),
         }
 
         let _ = server.join();
     }
 
+    #[test]
+    fn device_token_from_hello_ok_is_stored_and_presented_later() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let mut presented = Vec::new();
+            for _ in 0..2 {
+                let (stream, _) = listener.accept().expect("connection should arrive");
+                let mut ws = accept(stream).expect("websocket handshake should succeed");
+                ws.send(Message::Text(
+                    json!({
+                        "type": "event",
+                        "event": "connect.challenge",
+                        "payload": { "nonce": "n" }
+                    })
+                    .to_string()
+                    .into(),
+                ))
+                .expect("challenge should be sent");
+
+                let connect_frame = read_frame(&mut ws);
+                presented.push(connect_frame["params"]["auth"]["token"].clone());
+                ws.send(Message::Text(
+                    json!({
+                        "type": "res",
+                        "id": "connect-1",
+                        "ok": true,
+                        "payload": {
+                            "type": "hello-ok",
+                            "auth": { "deviceToken": "device-token-1", "role": "operator" }
+                        }
+                    })
+                    .to_string()
+                    .into(),
+                ))
+                .expect("connect response should be sent");
+                let _ = ws.read();
+            }
+            presented
+        });
+
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = dir.path();
+        let tokens = TokenStore::new(StateDir::new(root));
+        let options = ClientOptions {
+            device: Some(DeviceIdentity::from_secret_key([9; 32])),
+            tokens: Some(tokens.clone()),
+            ..ClientOptions::default()
+        };
+        for _ in 0..2 {
+            let client = HttpGatewayClient::new_with_options(
+                format!("http://{addr}"),
+                None,
+                None,
+                options.clone(),
+            )
+            .expect("client should build");
+            let hello = client.hello().expect("hello should succeed");
+            assert!(hello.auth.is_some());
+        }
+
+        let presented = server.join().expect("server should finish");
+        assert_eq!(presented, vec![Value::Null, json!("device-token-1")]);
+        let stored = tokens.list().expect("token store should read");
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].server, format!("http://{addr}"));
+    }
+
+    #[test]
+    fn stored_login_is_used_only_without_explicit_credentials() {
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = dir.path();
+        let store = CredentialStore::new(StateDir::new(root)).with_key(KeySource::File, None);
+        store
+            .save(
+                "http://127.0.0.1:18789",
+                &Credential::Password("stored-password".to_owned()),
+            )
+            .expect("credential should save");
+        let options = ClientOptions {
+            credentials: Some(store),
+            ..ClientOptions::default()
+        };
+
+        let stored = HttpGatewayClient::new_with_options(
+            "http://127.0.0.1:18789/",
+            None,
+            None,
+            options.clone(),
+        )
+        .expect("client should build");
+        assert_eq!(
+            stored.connect_params(None)["auth"],
+            json!({ "password": "stored-password" })
+        );
+
+        let explicit = HttpGatewayClient::new_with_options(
+            "http://127.0.0.1:18789",
+            Some("flag-token".to_owned()),
+            None,
+            options,
+        )
+        .expect("client should build");
+        assert_eq!(
+            explicit.connect_params(None)["auth"],
+            json!({ "token": "flag-token" })
+        );
+    }
+
+    #[test]
+    fn rejected_connect_without_credentials_prompts_once_or_fails_fast() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let mut passwords = Vec::new();
+            for _ in 0..3 {
+                let (stream, _) = listener.accept().expect("connection should arrive");
+                let mut ws = accept(stream).expect("websocket handshake should succeed");
+                let connect_frame = read_frame(&mut ws);
+                let password = connect_frame["params"]["auth"]["password"].clone();
+                let reply = if password == "typed-password" {
+                    json!({ "type": "res", "id": "connect-1", "ok": true, "payload": { "type": "hello-ok" } })
+                } else {
+                    json!({
+                        "type": "res",
+                        "id": "connect-1",
+                        "ok": false,
+                        "error": { "code": "UNAUTHORIZED", "message": "auth required" }
+                    })
+                };
+                ws.send(Message::Text(reply.to_string().into()))
+                    .expect("connect response should be sent");
+                passwords.push(password);
+                let _ = ws.read();
+            }
+            passwords
+        });
+
+        let unattended =
+            HttpGatewayClient::new(format!("http://{addr}")).expect("client should build");
+        assert!(matches!(unattended.hello(), Err(CliError::InvalidAuth(_))));
+
+        let options = ClientOptions {
+            password_prompt: Some(PasswordPrompt::new(|| Ok("typed-password\n".to_owned()))),
+            ..ClientOptions::default()
+        };
+        let interactive =
+            HttpGatewayClient::new_with_options(format!("http://{addr}"), None, None, options)
+                .expect("client should build");
+        interactive
+            .hello()
+            .expect("hello should succeed after the prompt");
+        drop(interactive);
+
+        let passwords = server.join().expect("server should finish");
+        assert_eq!(
+            passwords,
+            vec![Value::Null, Value::Null, json!("typed-password")]
+        );
+    }
+
     #[test]
     fn rpc_connect_frame_includes_token_auth_when_configured() {
         let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
@@ -461,6 +1374,178 @@ mod tests {
         let _ = server.join();
     }
 
+    #[test]
+    fn rpc_reuses_session_across_calls() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+
+            let connect_frame = read_frame(&mut ws);
+            assert_eq!(connect_frame["method"], "connect");
+            ws.send(Message::Text(
+                json!({
+                    "type": "res",
+                    "id": "connect-1",
+                    "ok": true,
+                    "payload": { "type": "hello-ok" }
+                })
+                .to_string()
+                .into(),
+            ))
+            .expect("connect response should be sent");
+
+            for _ in 0..2 {
+                let rpc_frame = read_frame(&mut ws);
+                ws.send(Message::Text(
+                    json!({
+                        "type": "res",
+                        "id": rpc_frame["id"],
+                        "ok": true,
+                        "payload": { "id": rpc_frame["id"] }
+                    })
+                    .to_string()
+                    .into(),
+                ))
+                .expect("rpc response should be sent");
+            }
+        });
+
+        let client = HttpGatewayClient::new(format!("http://{addr}")).expect("client should build");
+        let first = client
+            .rpc("health", json!({}))
+            .expect("first rpc should succeed");
+        let second = client
+            .rpc("status", json!({}))
+            .expect("second rpc should succeed");
+        assert_eq!(first["id"], "rpc-1");
+        assert_eq!(second["id"], "rpc-2");
+
+        drop(client);
+        let _ = server.join();
+    }
+
+    #[test]
+    fn http_gets_and_websocket_go_through_the_proxy() {
+        let gateway = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let gateway_addr = gateway
+            .local_addr()
+            .expect("listener should expose local addr");
+        let gateway = thread::spawn(move || {
+            let (stream, _) = gateway.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            let _ = read_frame(&mut ws);
+            ws.send(Message::Text(
+                json!({ "type": "res", "id": "connect-1", "ok": true, "payload": { "type": "hello-ok" } })
+                    .to_string()
+                    .into(),
+            ))
+            .expect("connect response should be sent");
+            let _ = ws.read();
+        });
+
+        // Answers plain-HTTP GETs itself and tunnels CONNECT to the gateway,
+        // whatever host the client asked for.
+        let proxy = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let proxy_addr = proxy
+            .local_addr()
+            .expect("listener should expose local addr");
+        let proxy = thread::spawn(move || {
+            let mut request_lines = Vec::new();
+            for _ in 0..2 {
+                let (mut client, _) = proxy.accept().expect("connection should arrive");
+                let mut head = Vec::new();
+                let mut byte = [0_u8; 1];
+                while !head.ends_with(b"\r\n\r\n") {
+                    client.read_exact(&mut byte).expect("request should arrive");
+                    head.push(byte[0]);
+                }
+                let line = String::from_utf8_lossy(&head)
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_owned();
+
+                if line.starts_with("CONNECT ") {
+                    let mut upstream =
+                        TcpStream::connect(gateway_addr).expect("gateway should accept");
+                    client
+                        .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                        .expect("CONNECT reply should be sent");
+                    let mut client_writer = client.try_clone().expect("socket should clone");
+                    let mut upstream_reader = upstream.try_clone().expect("socket should clone");
+                    let downstream = thread::spawn(move || {
+                        let _ = io::copy(&mut upstream_reader, &mut client_writer);
+                        let _ = client_writer.shutdown(Shutdown::Write);
+                    });
+                    let _ = io::copy(&mut client, &mut upstream);
+                    let _ = upstream.shutdown(Shutdown::Write);
+                    let _ = downstream.join();
+                } else {
+                    let body = r#"{"ok":true,"via":"proxy"}"#;
+                    write!(
+                        client,
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                        body.len()
+                    )
+                    .expect("response should be sent");
+                }
+                request_lines.push(line);
+            }
+            request_lines
+        });
+
+        let options = ClientOptions {
+            proxy: ProxySettings::default().with_proxy(
+                Proxy::parse(&format!("http://{proxy_addr}")).expect("proxy should parse"),
+            ),
+            ..ClientOptions::default()
+        };
+        // The gateway's name does not resolve, so only the proxy can reach it.
+        let client =
+            HttpGatewayClient::new_with_options("http://gateway.test:18789", None, None, options)
+                .expect("client should build");
+        assert_eq!(
+            client
+                .healthz()
+                .expect("healthz should go through the proxy")["via"],
+            "proxy"
+        );
+        client.hello().expect("hello should be tunnelled");
+        drop(client);
+
+        let request_lines = proxy.join().expect("proxy should finish");
+        assert_eq!(
+            request_lines,
+            vec![
+                "GET http://gateway.test:18789/healthz HTTP/1.1".to_owned(),
+                "CONNECT gateway.test:18789 HTTP/1.1".to_owned(),
+            ]
+        );
+        gateway.join().expect("gateway should finish");
+    }
+
+    /// Collects the upgrade request's headers as `name: value` lines.
+    struct RecordHeaders<'a>(&'a mut Vec<String>);
+
+    impl Callback for RecordHeaders<'_> {
+        fn on_request(
+            self,
+            request: &Request,
+            response: Response,
+        ) -> Result<Response, ErrorResponse> {
+            for (name, value) in request.headers() {
+                self.0
+                    .push(format!("{name}: {}", value.to_str().unwrap_or_default()));
+            }
+            Ok(response)
+        }
+    }
+
     fn read_frame<S>(socket: &mut tungstenite::WebSocket<S>) -> Value
     where
         S: std::io::Read + std::io::Write,
@@ -1,14 +1,32 @@
-use clap::{Parser, Subcommand};
-use serde_json::Value;
+use std::{
+    io::{BufRead, ErrorKind, Write},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
 use thiserror::Error;
 
-use crate::GatewayClient;
+use crate::{
+    client::normalize_base_url,
+    output::{render_record, ColorChoice, OutputOptions, Summary},
+    protocol::describe_server_range,
+    secret, ClientOptions, Config, Credential, CredentialStore, DeviceIdentity, DeviceToken,
+    GatewayClient, GatewayError, HttpGatewayClient, KeySource, OutputFormat, Profile,
+    ProtocolRange, Proxy, ProxySettings, Query, RetryPolicy, StateDir, TimeoutPhase, Timeouts,
+    TlsConfig, TlsOptions, TokenStore,
+};
+
+/// The gateway used when neither `--server` nor the profile names one.
+pub const DEFAULT_SERVER: &str = "http://127.0.0.1:18789";
 
 #[derive(Debug, Clone, Parser)]
 #[command(name = "reclaw-cli", version)]
 pub struct CliArgs {
-    #[arg(long, default_value = "http://127.0.0.1:18789")]
-    pub server: String,
+    /// Gateway base URL [default: http://127.0.0.1:18789].
+    #[arg(long)]
+    pub server: Option<String>,
 
     #[arg(long)]
     pub auth_token: Option<String>,
@@ -16,21 +34,551 @@ pub struct CliArgs {
     #[arg(long)]
     pub auth_password: Option<String>,
 
-    #[arg(long)]
+    /// Read the auth token from the first line of this file.
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["auth_token", "auth_command"])]
+    pub auth_token_file: Option<PathBuf>,
+
+    /// Read the auth password from the first line of stdin.
+    #[arg(long, conflicts_with = "auth_password")]
+    pub auth_password_stdin: bool,
+
+    /// Run this shell command and use the first line of its stdout as the auth token.
+    #[arg(long, value_name = "COMMAND", conflicts_with = "auth_token")]
+    pub auth_command: Option<String>,
+
+    /// Prompt for the auth password on the terminal before connecting.
+    #[arg(
+        long,
+        conflicts_with_all = ["auth_token", "auth_token_file", "auth_command", "auth_password", "auth_password_stdin"]
+    )]
+    pub prompt_password: bool,
+
+    /// Same as --output json.
+    #[arg(long, conflicts_with = "output")]
     pub json: bool,
 
+    /// How to print the result [default: text, or from RECLAW_OUTPUT or the
+    /// profile].
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub output: Option<OutputFormat>,
+
+    /// JMESPath expression applied to the result before it is printed.
+    #[arg(long, value_name = "EXPRESSION")]
+    pub query: Option<String>,
+
+    /// Table columns as comma-separated dotted paths, e.g. id,status.state
+    /// [default: every key].
+    #[arg(long, value_name = "PATHS", value_delimiter = ',')]
+    pub columns: Vec<String>,
+
+    /// Sort table rows by the value at this dotted path.
+    #[arg(long, value_name = "PATH")]
+    pub sort_by: Option<String>,
+
+    /// Print tables without the header row.
+    #[arg(long)]
+    pub no_headers: bool,
+
+    /// Go-style template that renders the result (after --query) as text,
+    /// e.g. '{{.sessionKey}} {{.updatedAt}}'.
+    #[arg(long, value_name = "TEMPLATE", conflicts_with_all = ["output", "json"])]
+    pub template: Option<String>,
+
+    /// Color the health and info summaries.
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto)]
+    pub color: ColorChoice,
+
+    #[command(flatten)]
+    pub config: ConfigArgs,
+
+    #[command(flatten)]
+    pub connection: ConnectionArgs,
+
     #[command(subcommand)]
     pub command: CliCommand,
 }
 
+impl CliArgs {
+    pub fn server(&self) -> &str {
+        self.server.as_deref().unwrap_or(DEFAULT_SERVER)
+    }
+
+    fn has_credentials(&self) -> bool {
+        self.auth_token.is_some()
+            || self.auth_password.is_some()
+            || self.auth_token_file.is_some()
+            || self.auth_password_stdin
+            || self.auth_command.is_some()
+            || self.prompt_password
+    }
+
+    /// The auth token and password to connect with, reading
+    /// `--auth-token-file`, `--auth-password-stdin` (from `stdin`) and
+    /// `--auth-command` as needed.
+    pub fn credentials(
+        &self,
+        stdin: &mut dyn BufRead,
+    ) -> Result<(Option<String>, Option<String>), CliError> {
+        single_secret_source(&[
+            ("--auth-token", self.auth_token.is_some()),
+            ("--auth-password", self.auth_password.is_some()),
+            ("--auth-token-file", self.auth_token_file.is_some()),
+            ("--auth-password-stdin", self.auth_password_stdin),
+            ("--auth-command", self.auth_command.is_some()),
+        ])?;
+        let token = match (&self.auth_token_file, &self.auth_command) {
+            (Some(path), _) => Some(secret::from_file(path)?),
+            (_, Some(command)) => Some(secret::from_command(command)?),
+            _ => self.auth_token.clone(),
+        };
+        let password = if self.auth_password_stdin {
+            Some(secret::from_reader(stdin)?)
+        } else {
+            self.auth_password.clone()
+        };
+        Ok((token, password))
+    }
+
+    pub fn output_format(&self) -> OutputFormat {
+        if self.json {
+            OutputFormat::Json
+        } else {
+            self.output.unwrap_or(OutputFormat::Text)
+        }
+    }
+
+    /// The output flags. The table width, whether to color and whether
+    /// stdout is a terminal the summary is meant for are left for the
+    /// caller, and so is parsing the template.
+    pub fn output_options(&self) -> OutputOptions {
+        OutputOptions {
+            format: self.output_format(),
+            columns: self
+                .columns
+                .iter()
+                .map(|path| path.trim().to_owned())
+                .filter(|path| !path.is_empty())
+                .collect(),
+            sort_by: self.sort_by.clone(),
+            no_headers: self.no_headers,
+            width: None,
+            // A query may reshape the result into something else entirely.
+            summary: match (&self.command, &self.query) {
+                (CliCommand::Client(ClientCommand::Health), None) => Some(Summary::Health),
+                (CliCommand::Client(ClientCommand::Info), None) => Some(Summary::Info),
+                _ => None,
+            },
+            color: false,
+            template: None,
+        }
+    }
+
+    /// Fills every setting not given on the command line from `RECLAW_*`
+    /// variables looked up through `env`. Call it before
+    /// [`CliArgs::load_profile`] so the environment wins over the profile.
+    pub fn apply_env(&mut self, env: impl Fn(&str) -> Option<String>) -> Result<(), CliError> {
+        let var = |name: &str| env(name).filter(|value| !value.trim().is_empty());
+        let invalid =
+            |name: &str, error: String| CliError::InvalidOption(format!("{name}: {error}"));
+        let duration = |name: &str| {
+            var(name)
+                .map(|raw| parse_duration(&raw).map_err(|error| invalid(name, error)))
+                .transpose()
+        };
+
+        self.server = self.server.take().or_else(|| var("RECLAW_SERVER"));
+        if !self.has_credentials() {
+            self.auth_token = var("RECLAW_AUTH_TOKEN");
+            self.auth_password = var("RECLAW_AUTH_PASSWORD");
+            self.auth_token_file = var("RECLAW_AUTH_TOKEN_FILE").map(PathBuf::from);
+            self.auth_command = var("RECLAW_AUTH_COMMAND");
+            single_secret_source(&[
+                ("RECLAW_AUTH_TOKEN", self.auth_token.is_some()),
+                ("RECLAW_AUTH_PASSWORD", self.auth_password.is_some()),
+                ("RECLAW_AUTH_TOKEN_FILE", self.auth_token_file.is_some()),
+                ("RECLAW_AUTH_COMMAND", self.auth_command.is_some()),
+            ])?;
+        }
+        if !self.json && self.output.is_none() {
+            self.output = var("RECLAW_OUTPUT")
+                .map(|raw| {
+                    OutputFormat::from_str(raw.trim(), true)
+                        .map_err(|error| invalid("RECLAW_OUTPUT", error))
+                })
+                .transpose()?;
+        }
+        self.config.path = self
+            .config
+            .path
+            .take()
+            .or_else(|| var("RECLAW_CONFIG").map(PathBuf::from));
+        self.config.profile = self.config.profile.take().or_else(|| var("RECLAW_PROFILE"));
+
+        let connection = &mut self.connection;
+        connection.state_dir = connection
+            .state_dir
+            .take()
+            .or_else(|| var("RECLAW_STATE_DIR").map(PathBuf::from));
+        if connection.credential_key.is_none() {
+            connection.credential_key = var("RECLAW_CREDENTIAL_KEY")
+                .map(|raw| {
+                    KeySource::from_str(raw.trim(), true)
+                        .map_err(|error| invalid("RECLAW_CREDENTIAL_KEY", error))
+                })
+                .transpose()?;
+        }
+        connection.credential_passphrase = var("RECLAW_CREDENTIAL_PASSPHRASE");
+        if connection.connect_timeout.is_none() {
+            connection.connect_timeout = duration("RECLAW_CONNECT_TIMEOUT")?;
+        }
+        if connection.request_timeout.is_none() {
+            connection.request_timeout = duration("RECLAW_REQUEST_TIMEOUT")?;
+        }
+        if connection.deadline.is_none() {
+            connection.deadline = duration("RECLAW_DEADLINE")?;
+        }
+        if connection.retries.is_none() {
+            connection.retries = var("RECLAW_RETRIES")
+                .map(|raw| {
+                    raw.trim()
+                        .parse()
+                        .map_err(|error| invalid("RECLAW_RETRIES", format!("{error}")))
+                })
+                .transpose()?;
+        }
+        if !connection.device_identity_set() {
+            match var("RECLAW_DEVICE_IDENTITY")
+                .map(|raw| {
+                    parse_bool(&raw).map_err(|error| invalid("RECLAW_DEVICE_IDENTITY", error))
+                })
+                .transpose()?
+            {
+                Some(true) => connection.device_identity = true,
+                Some(false) => connection.no_device_identity = true,
+                None => {}
+            }
+        }
+        connection.proxy_env = ProxySettings::from_env(&env);
+        Ok(())
+    }
+
+    /// Fills every setting not given on the command line from the profile
+    /// chosen by `--profile` or the config's current profile.
+    pub fn load_profile(&mut self) -> Result<(), CliError> {
+        let config = Config::load(&self.config.path()?)?;
+        if let Some((_, profile)) = config.profile(self.config.profile.as_deref())? {
+            self.apply_profile(profile)?;
+        }
+        Ok(())
+    }
+
+    pub fn apply_profile(&mut self, profile: &Profile) -> Result<(), CliError> {
+        if self.server.is_none() {
+            self.server = profile.server.clone();
+        }
+        if !self.has_credentials() {
+            self.auth_token = profile.auth_token.clone();
+            self.auth_password = profile.auth_password.clone();
+            self.auth_token_file = profile.auth_token_file.clone();
+            self.auth_command = profile.auth_command.clone();
+            single_secret_source(&[
+                ("auth_token", self.auth_token.is_some()),
+                ("auth_password", self.auth_password.is_some()),
+                ("auth_token_file", self.auth_token_file.is_some()),
+                ("auth_command", self.auth_command.is_some()),
+            ])?;
+        }
+        if !self.json && self.output.is_none() {
+            self.output = profile.output;
+        }
+
+        let duration = |raw: &Option<String>| {
+            raw.as_deref()
+                .map(parse_duration)
+                .transpose()
+                .map_err(CliError::Config)
+        };
+        let connection = &mut self.connection;
+        connection.connect_timeout = connection
+            .connect_timeout
+            .or(duration(&profile.connect_timeout)?);
+        connection.request_timeout = connection
+            .request_timeout
+            .or(duration(&profile.request_timeout)?);
+        connection.deadline = connection.deadline.or(duration(&profile.deadline)?);
+        if !connection.device_identity_set() {
+            connection.device_identity = profile.device_identity.unwrap_or(false);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct ConfigArgs {
+    /// Config file with named profiles [default: $XDG_CONFIG_HOME/reclaw-cli/config.toml].
+    #[arg(long = "config", value_name = "PATH")]
+    pub path: Option<PathBuf>,
+
+    /// Profile to take defaults from instead of the config's current profile.
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+}
+
+impl ConfigArgs {
+    pub fn path(&self) -> Result<PathBuf, CliError> {
+        match &self.path {
+            Some(path) => Ok(path.clone()),
+            None => Config::default_path(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Args)]
+pub struct ConnectionArgs {
+    /// Lowest gateway protocol version to offer (defaults to the native version).
+    #[arg(long, value_name = "VERSION")]
+    pub min_protocol: Option<u64>,
+
+    /// Highest gateway protocol version to offer (defaults to the native version).
+    #[arg(long, value_name = "VERSION")]
+    pub max_protocol: Option<u64>,
+
+    /// Limit for opening the connection and completing the handshake (e.g. 5s, 500ms).
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub connect_timeout: Option<Duration>,
+
+    /// Limit for each HTTP request or RPC reply.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub request_timeout: Option<Duration>,
+
+    /// Limit for the whole invocation, across every phase.
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub deadline: Option<Duration>,
+
+    /// Retries after a transient failure (default 2; 0 disables retries).
+    #[arg(long, value_name = "COUNT")]
+    pub retries: Option<u32>,
+
+    /// Delay before the first retry, doubled for each further one (default 250ms).
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub retry_delay: Option<Duration>,
+
+    /// Upper bound for a single retry delay (default 5s).
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    pub retry_max_delay: Option<Duration>,
+
+    /// Fraction of each retry delay that is randomly shaved off (default 0.2).
+    #[arg(long, value_name = "FRACTION")]
+    pub retry_jitter: Option<f64>,
+
+    /// RPC method glob that is safe to resend after a dropped connection (repeatable).
+    #[arg(long = "retry-safe-method", value_name = "GLOB")]
+    pub retry_safe_methods: Vec<String>,
+
+    /// Directory for the device identity and other local state.
+    #[arg(long, value_name = "PATH")]
+    pub state_dir: Option<PathBuf>,
+
+    /// Where the first `login` keeps the key that encrypts stored
+    /// credentials [default: passphrase when RECLAW_CREDENTIAL_PASSPHRASE is
+    /// set, otherwise keyring].
+    #[arg(long, value_enum, value_name = "SOURCE")]
+    pub credential_key: Option<KeySource>,
+
+    /// The passphrase read from RECLAW_CREDENTIAL_PASSPHRASE by
+    /// [`CliArgs::apply_env`].
+    #[arg(skip)]
+    pub credential_passphrase: Option<String>,
+
+    /// Sign the connect handshake with the device identity in the state
+    /// directory, for gateways that require device pairing.
+    #[arg(long, conflicts_with = "no_device_identity")]
+    pub device_identity: bool,
+
+    /// Connect without the device identity even if RECLAW_DEVICE_IDENTITY
+    /// or the profile turns it on.
+    #[arg(long)]
+    pub no_device_identity: bool,
+
+    /// Trust the CA certificates in this PEM file instead of the built-in
+    /// roots (repeatable).
+    #[arg(long = "ca-cert", value_name = "PATH")]
+    pub ca_certs: Vec<PathBuf>,
+
+    /// Present this PEM certificate chain for mutual TLS.
+    #[arg(long, value_name = "PATH", requires = "client_key")]
+    pub client_cert: Option<PathBuf>,
+
+    /// The PEM private key for --client-cert.
+    #[arg(long, value_name = "PATH", requires = "client_cert")]
+    pub client_key: Option<PathBuf>,
+
+    /// Require the server certificate's SHA-256 fingerprint to be this hex
+    /// value (repeatable; any one may match).
+    #[arg(long = "pin-sha256", value_name = "FINGERPRINT")]
+    pub pins: Vec<String>,
+
+    /// Reach the gateway through this proxy: http://, socks5:// or
+    /// socks5h://, with optional user:password@ [default: from HTTPS_PROXY,
+    /// http_proxy or ALL_PROXY].
+    #[arg(long, value_name = "URL")]
+    pub proxy: Option<String>,
+
+    /// Send this header with the HTTP requests and the WebSocket upgrade
+    /// (repeatable).
+    #[arg(long = "header", value_name = "NAME: VALUE", value_parser = parse_header)]
+    pub headers: Vec<(String, String)>,
+
+    /// Open the WebSocket session at this ws:// or wss:// URL instead of
+    /// `ws` under --server.
+    #[arg(long, value_name = "URL")]
+    pub ws_url: Option<String>,
+
+    /// Proxy settings read from the environment by [`CliArgs::apply_env`].
+    #[arg(skip)]
+    pub proxy_env: ProxySettings,
+}
+
+impl ConnectionArgs {
+    pub fn client_options(&self) -> Result<ClientOptions, CliError> {
+        Ok(ClientOptions {
+            protocol: ProtocolRange::from_bounds(self.min_protocol, self.max_protocol)?,
+            timeouts: Timeouts {
+                connect: self.connect_timeout,
+                request: self.request_timeout,
+                deadline: self.deadline,
+            },
+            retry: self.retry_policy()?,
+            device: None,
+            tokens: None,
+            password_prompt: None,
+            credentials: None,
+            tls: Some(TlsConfig::new(&TlsOptions {
+                ca_certs: self.ca_certs.clone(),
+                client_cert: self.client_cert.clone(),
+                client_key: self.client_key.clone(),
+                pins: self.pins.clone(),
+            })?),
+            headers: self.headers.clone(),
+            ws_url: self.ws_url.clone(),
+            proxy: match &self.proxy {
+                Some(url) => self.proxy_env.clone().with_proxy(Proxy::parse(url)?),
+                None => self.proxy_env.clone(),
+            },
+        })
+    }
+
+    pub fn state_dir(&self) -> Result<StateDir, CliError> {
+        match &self.state_dir {
+            Some(path) => Ok(StateDir::new(path)),
+            None => StateDir::default_location(),
+        }
+    }
+
+    /// The store `login` saves to and other commands fall back on, keyed as
+    /// `--credential-key` and `RECLAW_CREDENTIAL_PASSPHRASE` say.
+    pub fn credential_store(&self) -> Result<CredentialStore, CliError> {
+        let source = self
+            .credential_key
+            .unwrap_or(match self.credential_passphrase {
+                Some(_) => KeySource::Passphrase,
+                None => KeySource::Keyring,
+            });
+        Ok(CredentialStore::new(self.state_dir()?)
+            .with_key(source, self.credential_passphrase.clone()))
+    }
+
+    /// Loads the device identity from the state directory, creating it on
+    /// first use, when `--device-identity` (or its variable or profile key)
+    /// is on. Only commands that open a WebSocket session need it.
+    pub fn device(&self) -> Result<Option<DeviceIdentity>, CliError> {
+        if !self.device_identity || self.no_device_identity {
+            return Ok(None);
+        }
+        DeviceIdentity::load_or_create(&self.state_dir()?).map(Some)
+    }
+
+    /// Whether either device identity flag was given (or already filled in
+    /// from the environment).
+    fn device_identity_set(&self) -> bool {
+        self.device_identity || self.no_device_identity
+    }
+
+    fn retry_policy(&self) -> Result<RetryPolicy, CliError> {
+        let defaults = RetryPolicy::default();
+        let jitter = self.retry_jitter.unwrap_or(defaults.jitter);
+        if !(0.0..=1.0).contains(&jitter) {
+            return Err(CliError::InvalidOption(
+                "--retry-jitter must be between 0 and 1".to_owned(),
+            ));
+        }
+
+        Ok(RetryPolicy {
+            max_attempts: self
+                .retries
+                .map_or(defaults.max_attempts, |retries| retries.saturating_add(1)),
+            base_delay: self.retry_delay.unwrap_or(defaults.base_delay),
+            max_delay: self.retry_max_delay.unwrap_or(defaults.max_delay),
+            jitter,
+            safe_methods: self.retry_safe_methods.clone(),
+        })
+    }
+}
+
 #[derive(Debug, Clone, Subcommand)]
 pub enum CliCommand {
+    #[command(flatten)]
+    Client(ClientCommand),
+
+    /// Manage named profiles in the config file.
+    Profile {
+        #[command(subcommand)]
+        command: ProfileCommand,
+    },
+
+    /// Manage credentials stored in the state directory.
+    Auth {
+        #[command(subcommand)]
+        command: AuthCommand,
+    },
+
+    /// Verify credentials with a connect handshake, then store them
+    /// encrypted for --server so later commands use them.
+    ///
+    /// The key is kept in the OS keyring, or derived from
+    /// RECLAW_CREDENTIAL_PASSPHRASE (see --credential-key). With
+    /// `--credential-key file` it sits next to the credentials, which only
+    /// obfuscates them.
+    Login,
+
+    /// Delete the credentials `login` stored for --server.
+    Logout {
+        /// Delete the credentials for every server instead.
+        #[arg(long)]
+        all: bool,
+    },
+
+    /// Stream gateway events as NDJSON until interrupted.
+    Events {
+        /// Only print events whose name matches this glob (repeatable).
+        #[arg(long = "filter", value_name = "GLOB")]
+        filters: Vec<String>,
+    },
+}
+
+// The commands `run_with_client` answers with a single gateway call. A plain
+// comment: clap would show a doc comment here as the top-level help text.
+#[derive(Debug, Clone, Subcommand)]
+pub enum ClientCommand {
     /// Query /healthz and assert ok=true.
     Health,
 
     /// Query /info.
     Info,
 
+    /// Perform the connect handshake and print the gateway's hello-ok payload.
+    Hello,
+
     /// Invoke a JSON-RPC method over WebSocket RPC.
     Rpc {
         method: String,
@@ -39,6 +587,81 @@ pub enum CliCommand {
     },
 }
 
+impl ClientCommand {
+    /// Whether the command needs a WebSocket session rather than a plain
+    /// HTTP `GET`.
+    pub fn opens_session(&self) -> bool {
+        !matches!(self, Self::Health | Self::Info)
+    }
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ProfileCommand {
+    /// List profiles, marking the current one.
+    List,
+
+    /// Print a profile (the current one by default), with secrets masked.
+    Show { name: Option<String> },
+
+    /// Make a profile the current one.
+    Use { name: String },
+
+    /// Create or replace a profile.
+    Add {
+        name: String,
+        #[arg(long)]
+        server: Option<String>,
+        #[arg(long)]
+        auth_token: Option<String>,
+        #[arg(long)]
+        auth_password: Option<String>,
+        #[arg(long, value_name = "PATH")]
+        auth_token_file: Option<PathBuf>,
+        #[arg(long, value_name = "COMMAND")]
+        auth_command: Option<String>,
+        #[arg(long, value_enum)]
+        output: Option<OutputFormat>,
+        #[arg(long, value_name = "DURATION", value_parser = duration_text)]
+        connect_timeout: Option<String>,
+        #[arg(long, value_name = "DURATION", value_parser = duration_text)]
+        request_timeout: Option<String>,
+        #[arg(long, value_name = "DURATION", value_parser = duration_text)]
+        deadline: Option<String>,
+        /// Sign the connect handshake with the device identity.
+        #[arg(long)]
+        device_identity: bool,
+        /// Also make it the current profile.
+        #[arg(long = "use")]
+        make_current: bool,
+    },
+
+    /// Delete a profile.
+    Remove { name: String },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum AuthCommand {
+    /// Device tokens issued by gateways after pairing.
+    Tokens {
+        #[command(subcommand)]
+        command: TokenCommand,
+    },
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum TokenCommand {
+    /// List stored device tokens, with the secrets masked.
+    List,
+
+    /// Forget the stored device token for --server. The gateway is not
+    /// told; the token stays valid there until it is revoked with `rpc`.
+    Forget {
+        /// Forget the tokens for every server instead.
+        #[arg(long)]
+        all: bool,
+    },
+}
+
 #[derive(Debug, Error)]
 pub enum CliError {
     #[error("invalid server URL: {0}")]
@@ -55,29 +678,420 @@ pub enum CliError {
 
     #[error("invalid auth options: {0}")]
     InvalidAuth(String),
+
+    #[error("invalid option: {0}")]
+    InvalidOption(String),
+
+    #[error("{phase} timed out after {after:?}")]
+    Timeout {
+        phase: TimeoutPhase,
+        after: Duration,
+    },
+
+    #[error(
+        "protocol version mismatch: client offered {client}, {}",
+        describe_server_range(.server)
+    )]
+    UnsupportedProtocol {
+        client: ProtocolRange,
+        /// The gateway's range, when it reported one.
+        server: Option<String>,
+    },
+
+    #[error("gateway error: {0}")]
+    Gateway(GatewayError),
+
+    #[error("gateway rejected credentials: {0}")]
+    Unauthorized(String),
+
+    #[error("gateway reported unhealthy: {0}")]
+    Unhealthy(Value),
+
+    #[error("local state error: {0}")]
+    State(String),
+
+    #[error("config error: {0}")]
+    Config(String),
+
+    #[error("invalid query: {0}")]
+    Query(String),
+
+    #[error("template error: {0}")]
+    Template(String),
 }
 
-pub fn run_with_client(args: &CliArgs, client: &dyn GatewayClient) -> Result<Value, CliError> {
-    match &args.command {
-        CliCommand::Health => {
+impl CliError {
+    /// A stable, machine-readable name for the error class.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::InvalidServer(_) => "invalid_server",
+            Self::Transport(_) => "transport",
+            Self::Protocol(_) => "protocol",
+            Self::InvalidParams(_) => "invalid_params",
+            Self::InvalidAuth(_) => "invalid_auth",
+            Self::InvalidOption(_) => "invalid_option",
+            Self::Timeout { .. } => "timeout",
+            Self::UnsupportedProtocol { .. } => "unsupported_protocol",
+            Self::Gateway(_) => "gateway",
+            Self::Unauthorized(_) => "unauthorized",
+            Self::Unhealthy(_) => "unhealthy",
+            Self::State(_) => "state",
+            Self::Config(_) => "config",
+            Self::Query(_) => "query",
+            Self::Template(_) => "template",
+        }
+    }
+
+    /// The process exit code for this error. `1` is left for unexpected
+    /// failures and `2` for usage errors reported by the argument parser;
+    /// 3-9 mean the CLI was misconfigured, 10-19 that the gateway could not
+    /// be reached or spoke an unexpected protocol, 20-29 that the gateway
+    /// answered but refused or reported a problem, and 30-39 that the
+    /// output could not be rendered.
+    ///
+    /// Every variant has its own code except that a gateway error with an
+    /// auth code shares 21 with [`CliError::Unauthorized`]: both mean the
+    /// credentials were rejected, and `kind` still tells them apart.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::InvalidServer(_) => 3,
+            Self::InvalidParams(_) => 4,
+            Self::InvalidAuth(_) => 5,
+            Self::InvalidOption(_) => 6,
+            Self::State(_) => 7,
+            Self::Config(_) => 8,
+            Self::Query(_) => 9,
+            Self::Transport(_) => 10,
+            Self::Timeout { .. } => 11,
+            Self::Protocol(_) => 12,
+            Self::UnsupportedProtocol { .. } => 13,
+            Self::Gateway(error) if error.is_auth_failure() => 21,
+            Self::Gateway(_) => 20,
+            Self::Unauthorized(_) => 21,
+            Self::Unhealthy(_) => 22,
+            Self::Template(_) => 30,
+        }
+    }
+
+    /// The `--json` error document: `kind` and `message` for every error,
+    /// plus the gateway's `code`, `details`, `retryable` and `retryAfterMs`
+    /// when the gateway reported the failure.
+    pub fn to_json(&self) -> Value {
+        let mut error = json!({
+            "kind": self.kind(),
+            "exitCode": self.exit_code(),
+            "message": self.to_string(),
+        });
+        let extra = match self {
+            Self::Gateway(gateway) => serde_json::to_value(gateway).unwrap_or(Value::Null),
+            Self::Timeout { phase, after } => json!({
+                "phase": phase.to_string(),
+                "afterMs": u64::try_from(after.as_millis()).unwrap_or(u64::MAX),
+            }),
+            Self::UnsupportedProtocol { client, server } => json!({
+                "clientProtocol": client.to_string(),
+                "serverProtocol": server,
+            }),
+            Self::Unhealthy(payload) => json!({ "health": payload }),
+            _ => Value::Null,
+        };
+        if let (Some(error), Value::Object(extra)) = (error.as_object_mut(), extra) {
+            for (key, value) in extra {
+                if key != "message" {
+                    error.insert(key, value);
+                }
+            }
+        }
+
+        json!({ "error": error })
+    }
+}
+
+pub fn run_with_client(
+    command: &ClientCommand,
+    client: &dyn GatewayClient,
+) -> Result<Value, CliError> {
+    match command {
+        ClientCommand::Health => {
             let payload = client.healthz()?;
             let is_ok = payload.get("ok").and_then(Value::as_bool).unwrap_or(false);
             if is_ok {
                 Ok(payload)
             } else {
-                Err(CliError::Protocol(
-                    "healthz response missing ok=true".to_owned(),
-                ))
+                Err(CliError::Unhealthy(payload))
             }
         }
-        CliCommand::Info => client.info(),
-        CliCommand::Rpc { method, params } => {
+        ClientCommand::Info => client.info(),
+        ClientCommand::Hello => {
+            let hello = client.hello()?;
+            serde_json::to_value(hello)
+                .map_err(|error| CliError::Protocol(format!("failed to encode hello-ok: {error}")))
+        }
+        ClientCommand::Rpc { method, params } => {
             let params = parse_params(params)?;
             client.rpc(method, params)
         }
     }
 }
 
+/// Runs a `profile` subcommand against the config file at `path`.
+pub fn run_profile_command(command: &ProfileCommand, path: &Path) -> Result<Value, CliError> {
+    let mut config = Config::load(path)?;
+    let missing = |name: &str| CliError::Config(format!("no profile named `{name}`"));
+
+    match command {
+        ProfileCommand::List => Ok(config
+            .profiles
+            .iter()
+            .map(|(name, profile)| {
+                json!({
+                    "name": name,
+                    "current": config.current_profile.as_deref() == Some(name.as_str()),
+                    "server": profile.server.as_deref().unwrap_or(DEFAULT_SERVER),
+                })
+            })
+            .collect()),
+        ProfileCommand::Show { name } => {
+            let (name, profile) = config
+                .profile(name.as_deref())?
+                .ok_or_else(|| CliError::Config("no current profile; pass a name".to_owned()))?;
+            let mut profile = profile.clone();
+            for secret in [&mut profile.auth_token, &mut profile.auth_password] {
+                if secret.is_some() {
+                    *secret = Some("********".to_owned());
+                }
+            }
+            let mut value = serde_json::to_value(profile)
+                .map_err(|error| CliError::Config(format!("failed to encode profile: {error}")))?;
+            value["name"] = json!(name);
+            Ok(value)
+        }
+        ProfileCommand::Use { name } => {
+            if !config.profiles.contains_key(name) {
+                return Err(missing(name));
+            }
+            config.current_profile = Some(name.clone());
+            config.save(path)?;
+            Ok(json!({ "current": name }))
+        }
+        ProfileCommand::Add {
+            name,
+            server,
+            auth_token,
+            auth_password,
+            auth_token_file,
+            auth_command,
+            output,
+            connect_timeout,
+            request_timeout,
+            deadline,
+            device_identity,
+            make_current,
+        } => {
+            single_secret_source(&[
+                ("--auth-token", auth_token.is_some()),
+                ("--auth-password", auth_password.is_some()),
+                ("--auth-token-file", auth_token_file.is_some()),
+                ("--auth-command", auth_command.is_some()),
+            ])?;
+            if let Some(server) = server {
+                normalize_base_url(server.clone())?;
+            }
+            config.profiles.insert(
+                name.clone(),
+                Profile {
+                    server: server.clone(),
+                    auth_token: auth_token.clone(),
+                    auth_password: auth_password.clone(),
+                    auth_token_file: auth_token_file.clone(),
+                    auth_command: auth_command.clone(),
+                    output: *output,
+                    connect_timeout: connect_timeout.clone(),
+                    request_timeout: request_timeout.clone(),
+                    deadline: deadline.clone(),
+                    device_identity: device_identity.then_some(true),
+                },
+            );
+            if *make_current || config.current_profile.is_none() {
+                config.current_profile = Some(name.clone());
+            }
+            config.save(path)?;
+            Ok(json!({ "saved": name }))
+        }
+        ProfileCommand::Remove { name } => {
+            config.profiles.remove(name).ok_or_else(|| missing(name))?;
+            if config.current_profile.as_deref() == Some(name.as_str()) {
+                config.current_profile = None;
+            }
+            config.save(path)?;
+            Ok(json!({ "removed": name }))
+        }
+    }
+}
+
+/// Runs an `auth` subcommand against the local credential stores; `server`
+/// selects the entries a `forget` removes.
+pub fn run_auth_command(
+    command: &AuthCommand,
+    server: &str,
+    tokens: &TokenStore,
+) -> Result<Value, CliError> {
+    match command {
+        AuthCommand::Tokens {
+            command: TokenCommand::List,
+        } => Ok(Value::Array(
+            tokens.list()?.iter().map(masked_token).collect(),
+        )),
+        AuthCommand::Tokens {
+            command: TokenCommand::Forget { all },
+        } => {
+            let server = normalize_base_url(server.to_owned())?;
+            let removed = tokens.forget((!all).then_some(server.as_str()))?;
+            Ok(json!({ "removed": removed }))
+        }
+    }
+}
+
+/// Verifies `credential` with a connect handshake against `server` and
+/// stores it on success. `options` should not carry a credential store, so
+/// only the new credential is tried.
+pub fn run_login(
+    server: &str,
+    credential: &Credential,
+    options: ClientOptions,
+    store: &CredentialStore,
+) -> Result<Value, CliError> {
+    let server = normalize_base_url(server.to_owned())?;
+    let client = HttpGatewayClient::new_with_options(
+        &server,
+        credential.token(),
+        credential.password(),
+        options,
+    )?;
+    client.hello()?;
+    store.save(&server, credential)?;
+    Ok(json!({ "server": server, "auth": credential.kind() }))
+}
+
+/// Deletes the stored credentials for `server`, or for every server with
+/// `all`.
+pub fn run_logout(server: &str, all: bool, store: &CredentialStore) -> Result<Value, CliError> {
+    let removed = store.remove((!all).then_some(server))?;
+    Ok(json!({ "removed": removed }))
+}
+
+fn masked_token(token: &DeviceToken) -> Value {
+    let visible: String = token.token.chars().take(4).collect();
+    let mut value = serde_json::to_value(token).unwrap_or(Value::Null);
+    value["token"] = json!(format!("{visible}..."));
+    value
+}
+
+/// Writes matching gateway events to `out`, one [`render_record`] each (so
+/// NDJSON for most formats), until the event stream closes or `out` stops
+/// accepting data. With a `query`, each event is replaced by its result and
+/// events for which it is `null` are skipped; the output's template, if any,
+/// then renders each event on its own line.
+pub fn stream_events(
+    client: &dyn GatewayClient,
+    filters: &[String],
+    query: Option<&Query>,
+    output: &OutputOptions,
+    out: &mut dyn Write,
+) -> Result<(), CliError> {
+    let subscription = client.subscribe(filters)?;
+    loop {
+        let event = subscription.recv()?;
+        let mut event = serde_json::to_value(&event)
+            .map_err(|error| CliError::Protocol(format!("failed to encode event: {error}")))?;
+        if let Some(query) = query {
+            event = query.search(&event)?;
+            if event.is_null() {
+                continue;
+            }
+        }
+        let record = render_record(output, &event)?;
+
+        match out.write_all(record.as_bytes()).and_then(|()| out.flush()) {
+            Ok(()) => {}
+            Err(error) if error.kind() == ErrorKind::BrokenPipe => return Ok(()),
+            Err(error) => {
+                return Err(CliError::Transport(format!(
+                    "failed to write event: {error}"
+                )))
+            }
+        }
+    }
+}
+
+/// Parses durations such as `30s`, `500ms`, `2m` or `1h`; a bare number is
+/// taken as seconds.
+fn parse_duration(raw: &str) -> Result<Duration, String> {
+    let raw = raw.trim();
+    let split = raw
+        .find(|ch: char| !(ch.is_ascii_digit() || ch == '.'))
+        .unwrap_or(raw.len());
+    let (number, unit) = raw.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration `{raw}`"))?;
+    let seconds = match unit.trim() {
+        "" | "s" => number,
+        "ms" => number / 1000.0,
+        "m" => number * 60.0,
+        "h" => number * 3600.0,
+        other => return Err(format!("unknown duration unit `{other}` in `{raw}`")),
+    };
+
+    Duration::try_from_secs_f64(seconds)
+        .map_err(|error| format!("invalid duration `{raw}`: {error}"))
+}
+
+/// Parses a boolean variable: `1`, `true`, `yes` or `on`, or their
+/// opposites.
+fn parse_bool(raw: &str) -> Result<bool, String> {
+    match raw.trim().to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        other => Err(format!("expected true or false, got `{other}`")),
+    }
+}
+
+/// Rejects more than one secret from the same layer (flags, environment or
+/// profile) instead of letting one of them win silently.
+fn single_secret_source(sources: &[(&str, bool)]) -> Result<(), CliError> {
+    let set: Vec<&str> = sources
+        .iter()
+        .filter(|(_, set)| *set)
+        .map(|(name, _)| *name)
+        .collect();
+    match set.as_slice() {
+        [] | [_] => Ok(()),
+        [rest @ .., last] => Err(CliError::InvalidAuth(format!(
+            "provide only one of {} or {last}",
+            rest.join(", ")
+        ))),
+    }
+}
+
+/// Validates a duration but keeps the text, for values written to the
+/// config file.
+fn duration_text(raw: &str) -> Result<String, String> {
+    parse_duration(raw).map(|_| raw.trim().to_owned())
+}
+
+/// Splits `Name: value`; the client checks that both are valid HTTP.
+fn parse_header(raw: &str) -> Result<(String, String), String> {
+    let (name, value) = raw
+        .split_once(':')
+        .ok_or_else(|| format!("`{raw}` is not in `Name: value` form"))?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("`{raw}` has an empty header name"));
+    }
+    Ok((name.to_owned(), value.trim().to_owned()))
+}
+
 fn parse_params(raw: &str) -> Result<Value, CliError> {
     let parsed: Value =
         serde_json::from_str(raw).map_err(|error| CliError::InvalidParams(error.to_string()))?;
@@ -95,7 +1109,14 @@ fn parse_params(raw: &str) -> Result<Value, CliError> {
 mod tests {
     use serde_json::json;
 
-    use crate::{run_with_client, CliArgs, CliCommand, CliError, GatewayClient};
+    use std::{sync::mpsc, time::Duration};
+
+    use crate::{
+        command::stream_events, run_auth_command, run_profile_command, run_with_client,
+        AuthCommand, CliArgs, CliCommand, CliError, ClientCommand, ConnectionArgs, DeviceToken,
+        EventSubscription, GatewayClient, GatewayError, GatewayEvent, HelloOk, OutputFormat,
+        OutputOptions, Profile, StateDir, TimeoutPhase, TokenCommand, TokenStore,
+    };
 
     struct StaticClient;
 
@@ -115,39 +1136,460 @@ mod tests {
         ) -> Result<serde_json::Value, CliError> {
             Ok(json!({ "method": method, "params": params }))
         }
+
+        fn hello(&self) -> Result<HelloOk, CliError> {
+            Ok(HelloOk {
+                protocol: Some(3),
+                ..HelloOk::default()
+            })
+        }
+
+        fn subscribe(&self, patterns: &[String]) -> Result<EventSubscription, CliError> {
+            let (sender, receiver) = mpsc::channel();
+            for name in ["tick", "agent.delta"] {
+                if crate::protocol::event_matches(patterns, name) {
+                    let _ = sender.send(Ok(GatewayEvent {
+                        event: name.to_owned(),
+                        payload: json!({ "name": name }),
+                        seq: None,
+                        state_version: None,
+                    }));
+                }
+            }
+            Ok(EventSubscription::new(receiver))
+        }
     }
 
     #[test]
     fn rpc_command_accepts_object_params() {
-        let args = CliArgs {
-            server: "http://127.0.0.1:18789".to_owned(),
-            auth_token: None,
-            auth_password: None,
-            json: false,
-            command: CliCommand::Rpc {
-                method: "system.healthz".to_owned(),
-                params: "{\"scope\":\"node\"}".to_owned(),
-            },
+        let command = ClientCommand::Rpc {
+            method: "system.healthz".to_owned(),
+            params: "{\"scope\":\"node\"}".to_owned(),
         };
 
-        let output = run_with_client(&args, &StaticClient).expect("rpc should succeed");
+        let output = run_with_client(&command, &StaticClient).expect("rpc should succeed");
         assert_eq!(output["params"]["scope"], "node");
     }
 
     #[test]
     fn rpc_command_rejects_invalid_json() {
-        let args = CliArgs {
-            server: "http://127.0.0.1:18789".to_owned(),
-            auth_token: None,
-            auth_password: None,
-            json: false,
-            command: CliCommand::Rpc {
-                method: "system.healthz".to_owned(),
-                params: "{invalid".to_owned(),
-            },
+        let command = ClientCommand::Rpc {
+            method: "system.healthz".to_owned(),
+            params: "{invalid".to_owned(),
         };
 
-        let result = run_with_client(&args, &StaticClient);
+        let result = run_with_client(&command, &StaticClient);
         assert!(matches!(result, Err(CliError::InvalidParams(_))));
     }
+
+    #[test]
+    fn hello_command_returns_handshake_payload() {
+        let output =
+            run_with_client(&ClientCommand::Hello, &StaticClient).expect("hello should succeed");
+        assert_eq!(output["protocol"], 3);
+        assert_eq!(output["features"]["methods"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn stream_events_writes_filtered_ndjson() {
+        let mut out = Vec::new();
+        let result = stream_events(
+            &StaticClient,
+            &["agent.*".to_owned()],
+            None,
+            &OutputOptions {
+                format: OutputFormat::Ndjson,
+                ..OutputOptions::default()
+            },
+            &mut out,
+        );
+        assert!(matches!(result, Err(CliError::Transport(_))));
+
+        let text = String::from_utf8(out).expect("output should be UTF-8");
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let event: serde_json::Value = serde_json::from_str(lines[0]).expect("line should be JSON");
+        assert_eq!(event["event"], "agent.delta");
+    }
+
+    #[test]
+    fn connection_args_build_protocol_range() {
+        let connection = ConnectionArgs {
+            min_protocol: Some(2),
+            max_protocol: Some(4),
+            ..ConnectionArgs::default()
+        };
+        let options = connection.client_options().expect("options should build");
+        assert_eq!((options.protocol.min(), options.protocol.max()), (2, 4));
+
+        let connection = ConnectionArgs {
+            min_protocol: Some(5),
+            max_protocol: Some(4),
+            ..ConnectionArgs::default()
+        };
+        assert!(matches!(
+            connection.client_options(),
+            Err(CliError::InvalidOption(_))
+        ));
+    }
+
+    #[test]
+    fn parse_duration_accepts_units_and_bare_seconds() {
+        use std::time::Duration;
+
+        use crate::command::parse_duration;
+
+        assert_eq!(parse_duration("30"), Ok(Duration::from_secs(30)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("250ms"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("2m"), Ok(Duration::from_secs(120)));
+        assert!(parse_duration("5 parsecs").is_err());
+        assert!(parse_duration("soon").is_err());
+    }
+
+    #[test]
+    fn gateway_error_json_exposes_code_and_retry_hints() {
+        let error = CliError::Gateway(GatewayError {
+            code: Some("UNAVAILABLE".to_owned()),
+            message: "draining".to_owned(),
+            details: json!({ "node": "n-1" }),
+            retryable: true,
+            retry_after_ms: Some(500),
+        });
+
+        let document = error.to_json();
+        assert_eq!(document["error"]["kind"], "gateway");
+        assert_eq!(document["error"]["code"], "UNAVAILABLE");
+        assert_eq!(document["error"]["details"]["node"], "n-1");
+        assert_eq!(document["error"]["retryable"], true);
+        assert_eq!(document["error"]["retryAfterMs"], 500);
+        assert_eq!(
+            document["error"]["message"],
+            "gateway error: draining (UNAVAILABLE)"
+        );
+
+        let document =
+            CliError::InvalidParams("params JSON must be an object".to_owned()).to_json();
+        assert_eq!(document["error"]["kind"], "invalid_params");
+        assert!(document["error"].get("code").is_none());
+    }
+
+    #[test]
+    fn exit_codes_separate_misconfiguration_from_gateway_failures() {
+        let gateway = |code: &str| {
+            CliError::Gateway(GatewayError {
+                code: Some(code.to_owned()),
+                message: "rejected".to_owned(),
+                ..GatewayError::default()
+            })
+        };
+
+        assert_eq!(CliError::InvalidServer("ftp://x".to_owned()).exit_code(), 3);
+        assert_eq!(
+            CliError::InvalidOption("--retries".to_owned()).exit_code(),
+            6
+        );
+        assert_eq!(CliError::Transport("refused".to_owned()).exit_code(), 10);
+        assert_eq!(
+            CliError::Timeout {
+                phase: TimeoutPhase::Request,
+                after: Duration::from_secs(1),
+            }
+            .exit_code(),
+            11
+        );
+        assert_eq!(gateway("INVALID_REQUEST").exit_code(), 20);
+        assert_eq!(gateway("UNAUTHORIZED").exit_code(), 21);
+        assert_eq!(CliError::Unauthorized("401".to_owned()).exit_code(), 21);
+        assert_eq!(CliError::Unhealthy(json!({ "ok": false })).exit_code(), 22);
+        assert_eq!(CliError::Query("bad".to_owned()).exit_code(), 9);
+        assert_eq!(CliError::Template("bad".to_owned()).exit_code(), 30);
+    }
+
+    #[test]
+    fn auth_tokens_list_masks_secrets_and_forget_uses_server() {
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = dir.path();
+        let store = TokenStore::new(StateDir::new(root));
+        for server in ["http://127.0.0.1:18789", "http://gateway.internal"] {
+            store
+                .save(DeviceToken {
+                    server: server.to_owned(),
+                    device_id: "device-1".to_owned(),
+                    token: "secret-token".to_owned(),
+                    role: None,
+                    scopes: Vec::new(),
+                    issued_at_ms: None,
+                })
+                .expect("token should save");
+        }
+
+        let list = AuthCommand::Tokens {
+            command: TokenCommand::List,
+        };
+        let listed =
+            run_auth_command(&list, "http://127.0.0.1:18789", &store).expect("list should succeed");
+        assert_eq!(listed[0]["token"], "secr...");
+        assert_eq!(listed[0]["deviceId"], "device-1");
+
+        let forget = AuthCommand::Tokens {
+            command: TokenCommand::Forget { all: false },
+        };
+        let removed = run_auth_command(&forget, "http://127.0.0.1:18789/", &store)
+            .expect("forget should succeed");
+        assert_eq!(removed["removed"], 1);
+        assert_eq!(store.list().expect("store should read").len(), 1);
+    }
+
+    #[test]
+    fn profile_fills_only_unset_arguments() {
+        use clap::Parser;
+
+        let mut args = CliArgs::parse_from([
+            "reclaw-cli",
+            "--auth-password",
+            "flag-password",
+            "--request-timeout",
+            "5s",
+            "health",
+        ]);
+        args.apply_profile(&Profile {
+            server: Some("https://staging.example.com".to_owned()),
+            auth_token: Some("profile-token".to_owned()),
+            output: Some(OutputFormat::Json),
+            request_timeout: Some("30s".to_owned()),
+            deadline: Some("1m".to_owned()),
+            ..Profile::default()
+        })
+        .expect("profile should apply");
+
+        assert_eq!(args.server(), "https://staging.example.com");
+        assert_eq!(args.auth_token, None);
+        assert_eq!(args.auth_password.as_deref(), Some("flag-password"));
+        assert_eq!(args.output_format(), OutputFormat::Json);
+        assert_eq!(
+            args.connection.request_timeout,
+            Some(std::time::Duration::from_secs(5))
+        );
+        assert_eq!(
+            args.connection.deadline,
+            Some(std::time::Duration::from_secs(60))
+        );
+
+        let bad = Profile {
+            connect_timeout: Some("soon".to_owned()),
+            ..Profile::default()
+        };
+        assert!(matches!(
+            CliArgs::parse_from(["reclaw-cli", "health"]).apply_profile(&bad),
+            Err(CliError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn profile_commands_edit_the_config_file() {
+        use clap::Parser;
+
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = dir.path();
+        let path = root.join("config.toml");
+        let run = |argv: &[&str]| {
+            let args = CliArgs::parse_from(["reclaw-cli", "profile"].iter().chain(argv));
+            let CliCommand::Profile { command } = args.command else {
+                panic!("expected profile command");
+            };
+            run_profile_command(&command, &path)
+        };
+
+        run(&["add", "dev", "--server", "http://127.0.0.1:18789"]).expect("dev should be added");
+        run(&[
+            "add",
+            "prod",
+            "--server",
+            "https://gateway.example.com",
+            "--auth-token",
+            "prod-token",
+            "--request-timeout",
+            "30s",
+        ])
+        .expect("prod should be added");
+
+        let listed = run(&["list"]).expect("list should succeed");
+        assert_eq!(listed[0]["name"], "dev");
+        assert_eq!(listed[0]["current"], true);
+        assert_eq!(listed[1]["current"], false);
+
+        run(&["use", "prod"]).expect("use should succeed");
+        let shown = run(&["show"]).expect("show should succeed");
+        assert_eq!(shown["name"], "prod");
+        assert_eq!(shown["auth-token"], "********");
+        assert_eq!(shown["request-timeout"], "30s");
+
+        run(&["remove", "prod"]).expect("remove should succeed");
+        assert!(matches!(run(&["use", "prod"]), Err(CliError::Config(_))));
+        assert!(matches!(
+            run(&["add", "x", "--server", "ftp://nope"]),
+            Err(CliError::InvalidServer(_))
+        ));
+    }
+
+    #[test]
+    fn settings_prefer_flags_then_env_then_profile() {
+        use std::{collections::HashMap, time::Duration};
+
+        use clap::Parser;
+
+        let env = HashMap::from([
+            ("RECLAW_SERVER", "https://env.example.com"),
+            ("RECLAW_AUTH_TOKEN", "env-token"),
+            ("RECLAW_OUTPUT", "text"),
+            ("RECLAW_PROFILE", "prod"),
+            ("RECLAW_REQUEST_TIMEOUT", "10s"),
+            ("RECLAW_DEADLINE", ""),
+        ]);
+        let lookup = |name: &str| env.get(name).map(|value| (*value).to_owned());
+        let profile = Profile {
+            server: Some("https://profile.example.com".to_owned()),
+            auth_password: Some("profile-password".to_owned()),
+            output: Some(OutputFormat::Json),
+            request_timeout: Some("30s".to_owned()),
+            deadline: Some("1m".to_owned()),
+            ..Profile::default()
+        };
+
+        let mut args = CliArgs::parse_from([
+            "reclaw-cli",
+            "--server",
+            "https://flag.example.com",
+            "health",
+        ]);
+        args.apply_env(lookup).expect("env should apply");
+        args.apply_profile(&profile).expect("profile should apply");
+
+        assert_eq!(args.server(), "https://flag.example.com");
+        assert_eq!(args.auth_token.as_deref(), Some("env-token"));
+        assert_eq!(args.auth_password, None);
+        assert_eq!(args.output_format(), OutputFormat::Text);
+        assert_eq!(args.config.profile.as_deref(), Some("prod"));
+        assert_eq!(
+            args.connection.request_timeout,
+            Some(Duration::from_secs(10))
+        );
+        assert_eq!(args.connection.deadline, Some(Duration::from_secs(60)));
+
+        let mut defaults = CliArgs::parse_from(["reclaw-cli", "health"]);
+        defaults
+            .apply_env(|_| None)
+            .expect("empty env should apply");
+        assert_eq!(defaults.server(), crate::DEFAULT_SERVER);
+        assert_eq!(defaults.output_format(), OutputFormat::Text);
+
+        let mut invalid = CliArgs::parse_from(["reclaw-cli", "health"]);
+        let result = invalid.apply_env(|name| (name == "RECLAW_OUTPUT").then(|| "xml".to_owned()));
+        assert!(matches!(result, Err(CliError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn device_identity_is_opt_in_and_only_then_reads_the_state_dir() {
+        use clap::Parser;
+
+        // A state directory below a regular file cannot be created.
+        let unusable = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml/state");
+        let args = CliArgs::parse_from(["reclaw-cli", "--state-dir", unusable, "hello"]);
+        assert!(args
+            .connection
+            .device()
+            .expect("no identity needed")
+            .is_none());
+        let args = CliArgs::parse_from([
+            "reclaw-cli",
+            "--state-dir",
+            unusable,
+            "--device-identity",
+            "hello",
+        ]);
+        assert!(matches!(args.connection.device(), Err(CliError::State(_))));
+
+        let enabled = |env: Option<&str>, profile: Option<bool>, flags: &[&str]| {
+            let mut args =
+                CliArgs::parse_from(["reclaw-cli"].iter().chain(flags).chain(&["hello"]));
+            args.apply_env(|name| {
+                (name == "RECLAW_DEVICE_IDENTITY")
+                    .then_some(env)
+                    .flatten()
+                    .map(str::to_owned)
+            })
+            .expect("env should apply");
+            args.apply_profile(&Profile {
+                device_identity: profile,
+                ..Profile::default()
+            })
+            .expect("profile should apply");
+            args.connection.device_identity && !args.connection.no_device_identity
+        };
+        assert!(!enabled(None, None, &[]));
+        assert!(enabled(None, Some(true), &[]));
+        assert!(enabled(Some("1"), None, &[]));
+        assert!(!enabled(Some("false"), Some(true), &[]));
+        assert!(!enabled(Some("true"), None, &["--no-device-identity"]));
+        assert!(enabled(None, None, &["--device-identity"]));
+
+        let mut args = CliArgs::parse_from(["reclaw-cli", "hello"]);
+        let result =
+            args.apply_env(|name| (name == "RECLAW_DEVICE_IDENTITY").then(|| "maybe".to_owned()));
+        assert!(matches!(result, Err(CliError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn secret_sources_feed_token_and_password() {
+        use clap::Parser;
+
+        let mut stdin = "stdin-password\n".as_bytes();
+        let args = CliArgs::parse_from(["reclaw-cli", "--auth-password-stdin", "health"]);
+        assert_eq!(
+            args.credentials(&mut stdin).expect("stdin should be read"),
+            (None, Some("stdin-password".to_owned()))
+        );
+
+        let env = |name: &str| (name == "RECLAW_AUTH_TOKEN").then(|| "env-token".to_owned());
+        let mut args = CliArgs::parse_from(["reclaw-cli", "--auth-password-stdin", "health"]);
+        args.apply_env(env).expect("env should apply");
+        assert_eq!(args.auth_token, None);
+
+        // Two secrets from one layer are an error rather than a silent pick.
+        let env = |name: &str| {
+            matches!(name, "RECLAW_AUTH_TOKEN" | "RECLAW_AUTH_TOKEN_FILE")
+                .then(|| "env-secret".to_owned())
+        };
+        let mut args = CliArgs::parse_from(["reclaw-cli", "health"]);
+        assert!(matches!(
+            args.apply_env(env),
+            Err(CliError::InvalidAuth(message))
+                if message == "provide only one of RECLAW_AUTH_TOKEN or RECLAW_AUTH_TOKEN_FILE"
+        ));
+        let mut args = CliArgs::parse_from(["reclaw-cli", "health"]);
+        let profile = Profile {
+            auth_token_file: Some("/tmp/token".into()),
+            auth_command: Some("pass show gateway".to_owned()),
+            ..Profile::default()
+        };
+        assert!(matches!(
+            args.apply_profile(&profile),
+            Err(CliError::InvalidAuth(_))
+        ));
+        // A layer the flags override is not checked.
+        let mut args = CliArgs::parse_from(["reclaw-cli", "--auth-token", "t", "health"]);
+        args.apply_profile(&profile).expect("profile should apply");
+        assert_eq!(args.auth_command, None);
+
+        assert!(CliArgs::try_parse_from([
+            "reclaw-cli",
+            "--auth-token",
+            "t",
+            "--auth-token-file",
+            "/tmp/token",
+            "health",
+        ])
+        .is_err());
+    }
 }
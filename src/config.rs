@@ -0,0 +1,190 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{state::write_private_file, CliError};
+
+const APP_DIR: &str = "reclaw-cli";
+const CONFIG_FILE: &str = "config.toml";
+
+/// How command output is printed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// For people: the --template rendering if given, a summary for
+    /// health and info on a terminal, otherwise JSON on one line.
+    /// Errors go to stderr.
+    #[default]
+    Text,
+    /// Pretty-printed JSON.
+    Json,
+    /// JSON on one line.
+    JsonCompact,
+    Yaml,
+    /// Aligned columns, one row per object.
+    Table,
+    /// One compact JSON value per line; arrays print an element per line.
+    Ndjson,
+    /// Strings without quotes, arrays an element per line.
+    Raw,
+}
+
+impl OutputFormat {
+    /// Whether errors are printed to stdout in this format (as the error
+    /// JSON) rather than as a message on stderr.
+    pub fn is_structured(self) -> bool {
+        matches!(
+            self,
+            Self::Json | Self::JsonCompact | Self::Yaml | Self::Ndjson
+        )
+    }
+}
+
+/// A named set of connection defaults.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_password: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_token_file: Option<PathBuf>,
+    /// A shell command whose first line of output is the auth token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_command: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<OutputFormat>,
+    /// Durations use the CLI's syntax, e.g. `5s` or `250ms`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_timeout: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<String>,
+    /// Sign the connect handshake with the device identity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_identity: Option<bool>,
+}
+
+/// The contents of `config.toml`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub current_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/reclaw-cli/config.toml` on Linux, the platform's
+    /// configuration directory elsewhere.
+    pub fn default_path() -> Result<PathBuf, CliError> {
+        dirs::config_dir()
+            .map(|dir| dir.join(APP_DIR).join(CONFIG_FILE))
+            .ok_or_else(|| {
+                CliError::Config("cannot determine a config directory; pass --config".to_owned())
+            })
+    }
+
+    /// Reads the config at `path`; a missing file is an empty config.
+    pub fn load(path: &Path) -> Result<Self, CliError> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(error) => {
+                return Err(CliError::Config(format!(
+                    "failed to read {}: {error}",
+                    path.display()
+                )))
+            }
+        };
+
+        toml::from_str(&text)
+            .map_err(|error| CliError::Config(format!("invalid {}: {error}", path.display())))
+    }
+
+    /// Writes the config to `path`, readable only by the current user since
+    /// profiles may hold credentials.
+    pub fn save(&self, path: &Path) -> Result<(), CliError> {
+        let text = toml::to_string_pretty(self)
+            .map_err(|error| CliError::Config(format!("failed to encode config: {error}")))?;
+        write_private_file(path, text.as_bytes()).map_err(|error| {
+            CliError::Config(format!("failed to write {}: {error}", path.display()))
+        })
+    }
+
+    /// The profile named `name`, or the current profile when `name` is
+    /// `None`. Having no current profile is not an error.
+    pub fn profile(&self, name: Option<&str>) -> Result<Option<(&str, &Profile)>, CliError> {
+        let Some(name) = name.or(self.current_profile.as_deref()) else {
+            return Ok(None);
+        };
+
+        self.profiles
+            .get_key_value(name)
+            .map(|(name, profile)| Some((name.as_str(), profile)))
+            .ok_or_else(|| CliError::Config(format!("no profile named `{name}`")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{CliError, Config, OutputFormat};
+
+    #[test]
+    fn config_parses_profiles_and_round_trips() {
+        let config: Config = toml::from_str(
+            r#"
+            current-profile = "dev"
+
+            [profiles.dev]
+            server = "http://127.0.0.1:18789"
+            output = "json"
+            request-timeout = "30s"
+
+            [profiles.prod]
+            server = "https://gateway.example.com"
+            auth-token = "prod-token"
+            "#,
+        )
+        .expect("config should parse");
+
+        let (name, dev) = config
+            .profile(None)
+            .expect("current profile should exist")
+            .expect("current profile should be set");
+        assert_eq!(name, "dev");
+        assert_eq!(dev.output, Some(OutputFormat::Json));
+        assert_eq!(dev.request_timeout.as_deref(), Some("30s"));
+        assert!(matches!(
+            config.profile(Some("staging")),
+            Err(CliError::Config(_))
+        ));
+
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = dir.path();
+        let path = root.join("config.toml");
+        assert_eq!(
+            Config::load(&path).expect("missing config should load"),
+            Config::default()
+        );
+        config.save(&path).expect("config should save");
+        assert_eq!(Config::load(&path).expect("config should reload"), config);
+    }
+
+    #[test]
+    fn config_rejects_unknown_keys() {
+        let result = toml::from_str::<Config>("[profiles.dev]\nsever = \"http://x\"\n");
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,466 @@
+use std::fmt;
+
+use argon2::Argon2;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{client::normalize_base_url, keyring, CliError, StateDir};
+
+const CREDENTIALS_FILE: &str = "credentials.json";
+const KEY_FILE: &str = "credentials.key";
+const CREDENTIALS_VERSION: u32 = 2;
+
+/// A secret `login` verified against a gateway.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Credential {
+    Token(String),
+    Password(String),
+}
+
+impl Credential {
+    /// Picks the one credential among `token` and `password`.
+    pub fn new(token: Option<String>, password: Option<String>) -> Result<Self, CliError> {
+        let trimmed = |secret: Option<String>| {
+            secret
+                .map(|secret| secret.trim().to_owned())
+                .filter(|secret| !secret.is_empty())
+        };
+        match (trimmed(token), trimmed(password)) {
+            (Some(token), None) => Ok(Self::Token(token)),
+            (None, Some(password)) => Ok(Self::Password(password)),
+            (Some(_), Some(_)) => Err(CliError::InvalidAuth(
+                "provide only one of --auth-token or --auth-password".to_owned(),
+            )),
+            (None, None) => Err(CliError::InvalidAuth("no credentials given".to_owned())),
+        }
+    }
+
+    pub fn token(&self) -> Option<String> {
+        match self {
+            Self::Token(token) => Some(token.clone()),
+            Self::Password(_) => None,
+        }
+    }
+
+    pub fn password(&self) -> Option<String> {
+        match self {
+            Self::Token(_) => None,
+            Self::Password(password) => Some(password.clone()),
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Token(_) => "token",
+            Self::Password(_) => "password",
+        }
+    }
+}
+
+/// Where the key that encrypts stored credentials comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum KeySource {
+    /// A random key kept in the OS keyring.
+    #[default]
+    Keyring,
+    /// A key derived with Argon2id from `RECLAW_CREDENTIAL_PASSPHRASE`.
+    Passphrase,
+    /// A random key in `credentials.key` beside the credentials, which only
+    /// obfuscates them.
+    File,
+}
+
+impl KeySource {
+    /// Stores written before there was a choice kept the key in a file.
+    fn legacy() -> Self {
+        Self::File
+    }
+}
+
+impl fmt::Display for KeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Keyring => "keyring",
+            Self::Passphrase => "passphrase",
+            Self::File => "file",
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredCredentials {
+    version: u32,
+    #[serde(default = "KeySource::legacy")]
+    key: KeySource,
+    /// The Argon2id salt of a passphrase-derived key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    salt: Option<String>,
+    #[serde(default)]
+    entries: Vec<SealedCredential>,
+}
+
+/// A [`Credential`] encrypted with the store key; the server URL is bound
+/// in as associated data so entries cannot be swapped between servers.
+#[derive(Serialize, Deserialize)]
+struct SealedCredential {
+    server: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+/// Credentials saved by `login`, one per server, encrypted with
+/// XChaCha20-Poly1305. The key comes from the [`KeySource`] chosen when the
+/// first credential is saved; the store records it, so later commands only
+/// need the passphrase, if that is the source.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CredentialStore {
+    state: StateDir,
+    source: KeySource,
+    passphrase: Option<String>,
+}
+
+impl CredentialStore {
+    /// A store keyed by the OS keyring.
+    pub fn new(state: StateDir) -> Self {
+        Self {
+            state,
+            source: KeySource::default(),
+            passphrase: None,
+        }
+    }
+
+    /// Keys a new store from `source`; `passphrase` unlocks a store whose
+    /// key is derived from one.
+    pub fn with_key(self, source: KeySource, passphrase: Option<String>) -> Self {
+        Self {
+            source,
+            passphrase,
+            ..self
+        }
+    }
+
+    /// The credential stored for `server`, if any.
+    pub fn get(&self, server: &str) -> Result<Option<Credential>, CliError> {
+        let server = normalize_base_url(server.to_owned())?;
+        let Some(mut stored) = self.read()? else {
+            return Ok(None);
+        };
+        let Some(sealed) = stored
+            .entries
+            .iter()
+            .position(|entry| entry.server == server)
+            .map(|position| stored.entries.swap_remove(position))
+        else {
+            return Ok(None);
+        };
+
+        let undecryptable = || {
+            CliError::State(format!(
+                "cannot decrypt the stored credentials for {server}; run `reclaw-cli logout` and log in again"
+            ))
+        };
+        let nonce = URL_SAFE_NO_PAD
+            .decode(&sealed.nonce)
+            .ok()
+            .filter(|nonce| nonce.len() == 24)
+            .ok_or_else(undecryptable)?;
+        let ciphertext = URL_SAFE_NO_PAD
+            .decode(&sealed.ciphertext)
+            .map_err(|_| undecryptable())?;
+        let plaintext = self
+            .cipher(&mut stored, false)?
+            .ok_or_else(undecryptable)?
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &ciphertext,
+                    aad: server.as_bytes(),
+                },
+            )
+            .map_err(|_| undecryptable())?;
+        serde_json::from_slice(&plaintext)
+            .map(Some)
+            .map_err(|_| undecryptable())
+    }
+
+    /// Stores `credential` for `server`, replacing any earlier one.
+    pub fn save(&self, server: &str, credential: &Credential) -> Result<(), CliError> {
+        let server = normalize_base_url(server.to_owned())?;
+        let mut stored = match self.read()? {
+            Some(stored) if !stored.entries.is_empty() => {
+                if stored.key != self.source {
+                    return Err(CliError::State(format!(
+                        "the stored credentials use the {} key, not {}; run `reclaw-cli logout --all` first to switch",
+                        stored.key, self.source
+                    )));
+                }
+                stored
+            }
+            // An empty store takes the key source asked for now.
+            _ => StoredCredentials {
+                version: CREDENTIALS_VERSION,
+                key: self.source,
+                salt: None,
+                entries: Vec::new(),
+            },
+        };
+
+        let plaintext = serde_json::to_vec(credential)
+            .map_err(|error| CliError::State(format!("failed to encode credential: {error}")))?;
+        let mut nonce = [0_u8; 24];
+        getrandom::fill(&mut nonce)
+            .map_err(|error| CliError::State(format!("failed to generate nonce: {error}")))?;
+        let ciphertext = self
+            .cipher(&mut stored, true)?
+            .ok_or_else(|| CliError::State("credential key is missing".to_owned()))?
+            .encrypt(
+                XNonce::from_slice(&nonce),
+                Payload {
+                    msg: &plaintext,
+                    aad: server.as_bytes(),
+                },
+            )
+            .map_err(|_| CliError::State("failed to encrypt credential".to_owned()))?;
+
+        stored.entries.retain(|entry| entry.server != server);
+        stored.entries.push(SealedCredential {
+            server,
+            nonce: URL_SAFE_NO_PAD.encode(nonce),
+            ciphertext: URL_SAFE_NO_PAD.encode(ciphertext),
+        });
+        self.write(stored)
+    }
+
+    /// Deletes the credential for `server`, or for every server when
+    /// `None`. Returns how many were removed.
+    pub fn remove(&self, server: Option<&str>) -> Result<usize, CliError> {
+        let server = server
+            .map(|server| normalize_base_url(server.to_owned()))
+            .transpose()?;
+        let Some(mut stored) = self.read()? else {
+            return Ok(0);
+        };
+        let before = stored.entries.len();
+        stored.entries.retain(|entry| {
+            server
+                .as_ref()
+                .is_some_and(|server| entry.server != *server)
+        });
+        let removed = before - stored.entries.len();
+        if removed > 0 {
+            self.write(stored)?;
+        }
+        Ok(removed)
+    }
+
+    /// The cipher for the key `stored` names, creating the key (or the
+    /// passphrase's salt) first when `create` is set.
+    fn cipher(
+        &self,
+        stored: &mut StoredCredentials,
+        create: bool,
+    ) -> Result<Option<XChaCha20Poly1305>, CliError> {
+        let key = match stored.key {
+            KeySource::Keyring => {
+                let account = self.state.root().display().to_string();
+                match keyring::lookup(&account)? {
+                    Some(key) => URL_SAFE_NO_PAD.decode(key).map_err(|_| {
+                        CliError::State(format!(
+                            "the OS keyring holds an invalid credential key for {account}"
+                        ))
+                    })?,
+                    None if create => {
+                        let key = random_key()?;
+                        keyring::store(&account, &URL_SAFE_NO_PAD.encode(key))?;
+                        key.to_vec()
+                    }
+                    None => return Ok(None),
+                }
+            }
+            KeySource::Passphrase => {
+                let passphrase = self.passphrase.as_deref().ok_or_else(|| {
+                    CliError::State(
+                        "the stored credentials are locked with a passphrase; set RECLAW_CREDENTIAL_PASSPHRASE"
+                            .to_owned(),
+                    )
+                })?;
+                let salt = match &stored.salt {
+                    Some(salt) => URL_SAFE_NO_PAD.decode(salt).map_err(|_| {
+                        CliError::State(
+                            "invalid passphrase salt in the credential store".to_owned(),
+                        )
+                    })?,
+                    None if create => {
+                        let salt = random_key()?;
+                        stored.salt = Some(URL_SAFE_NO_PAD.encode(salt));
+                        salt.to_vec()
+                    }
+                    None => return Ok(None),
+                };
+                let mut key = [0_u8; 32];
+                Argon2::default()
+                    .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+                    .map_err(|error| {
+                        CliError::State(format!("failed to derive the credential key: {error}"))
+                    })?;
+                key.to_vec()
+            }
+            KeySource::File => match self.state.read(KEY_FILE)? {
+                Some(key) => key,
+                None if create => {
+                    let key = random_key()?;
+                    self.state.write_private(KEY_FILE, &key)?;
+                    key.to_vec()
+                }
+                None => return Ok(None),
+            },
+        };
+
+        XChaCha20Poly1305::new_from_slice(&key)
+            .map(Some)
+            .map_err(|_| CliError::State(format!("invalid {} credential key", stored.key)))
+    }
+
+    fn read(&self) -> Result<Option<StoredCredentials>, CliError> {
+        let Some(contents) = self.state.read(CREDENTIALS_FILE)? else {
+            return Ok(None);
+        };
+
+        serde_json::from_slice::<StoredCredentials>(&contents)
+            .map(Some)
+            .map_err(|error| {
+                CliError::State(format!(
+                    "invalid credential store {}: {error}",
+                    self.state.root().join(CREDENTIALS_FILE).display()
+                ))
+            })
+    }
+
+    fn write(&self, mut stored: StoredCredentials) -> Result<(), CliError> {
+        stored.version = CREDENTIALS_VERSION;
+        let contents = serde_json::to_vec_pretty(&stored).map_err(|error| {
+            CliError::State(format!("failed to encode credential store: {error}"))
+        })?;
+        self.state.write_private(CREDENTIALS_FILE, &contents)
+    }
+}
+
+impl fmt::Debug for CredentialStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CredentialStore")
+            .field("state", &self.state)
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+/// 32 random bytes, for a key or a salt.
+fn random_key() -> Result<[u8; 32], CliError> {
+    let mut key = [0_u8; 32];
+    getrandom::fill(&mut key)
+        .map_err(|error| CliError::State(format!("failed to generate credential key: {error}")))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{CliError, Credential, CredentialStore, KeySource, StateDir};
+
+    #[test]
+    fn credentials_are_encrypted_per_server_and_removed() {
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = dir.path();
+        let store = CredentialStore::new(StateDir::new(root))
+            .with_key(KeySource::Passphrase, Some("correct horse".to_owned()));
+        assert_eq!(
+            store.get("http://a").expect("empty store should read"),
+            None
+        );
+
+        let token = Credential::Token("secret-token".to_owned());
+        store
+            .save("http://a/", &token)
+            .expect("credential should save");
+        store
+            .save("http://b", &Credential::Password("hunter2".to_owned()))
+            .expect("credential should save");
+        assert_eq!(
+            store.get("http://a").expect("store should read"),
+            Some(token)
+        );
+
+        let on_disk = fs::read_to_string(root.join("credentials.json"))
+            .expect("credential file should exist");
+        assert!(!on_disk.contains("secret-token"));
+        assert!(!on_disk.contains("hunter2"));
+        assert!(!root.join("credentials.key").exists());
+
+        // The key is not on disk: another passphrase, or none, cannot read.
+        let wrong = CredentialStore::new(StateDir::new(root))
+            .with_key(KeySource::Passphrase, Some("wrong".to_owned()));
+        assert!(matches!(wrong.get("http://a"), Err(CliError::State(_))));
+        let locked = CredentialStore::new(StateDir::new(root));
+        assert!(matches!(locked.get("http://a"), Err(CliError::State(_))));
+        assert!(matches!(
+            locked.save("http://c", &Credential::Token("t".to_owned())),
+            Err(CliError::State(message)) if message.contains("passphrase")
+        ));
+
+        // An entry moved to another server no longer authenticates.
+        fs::write(
+            root.join("credentials.json"),
+            on_disk.replace("http://b", "http://c"),
+        )
+        .expect("credential file should be writable");
+        assert!(matches!(store.get("http://c"), Err(CliError::State(_))));
+
+        assert_eq!(
+            store
+                .remove(Some("http://a"))
+                .expect("remove should succeed"),
+            1
+        );
+        assert_eq!(store.get("http://a").expect("store should read"), None);
+        assert_eq!(store.remove(None).expect("remove should succeed"), 1);
+    }
+
+    #[test]
+    fn a_key_file_is_used_only_when_asked_for_or_already_there() {
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = dir.path();
+        let store = CredentialStore::new(StateDir::new(root)).with_key(KeySource::File, None);
+        let token = Credential::Token("secret-token".to_owned());
+        store
+            .save("http://a", &token)
+            .expect("credential should save");
+        assert!(root.join("credentials.key").exists());
+
+        // The store names its key, so a default store still reads it.
+        let default = CredentialStore::new(StateDir::new(root));
+        assert_eq!(
+            default.get("http://a").expect("store should read"),
+            Some(token)
+        );
+
+        // Stores from before the key choice had no `key` field.
+        let on_disk = fs::read_to_string(root.join("credentials.json"))
+            .expect("credential file should exist");
+        let legacy = on_disk
+            .replace("\"version\": 2", "\"version\": 1")
+            .replace("\"key\": \"file\",", "");
+        fs::write(root.join("credentials.json"), legacy).expect("file should be writable");
+        assert!(default
+            .get("http://a")
+            .expect("legacy store should read")
+            .is_some());
+    }
+}
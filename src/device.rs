@@ -0,0 +1,265 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ed25519_dalek::{Signer, SigningKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::{CliError, StateDir};
+
+const IDENTITY_FILE: &str = "device.json";
+const IDENTITY_VERSION: u32 = 1;
+
+/// The Ed25519 keypair this installation uses to sign `connect` requests.
+///
+/// The device id is the hex SHA-256 of the raw public key, so gateways can
+/// pair a device once and recognise it on every later connection.
+#[derive(Clone)]
+pub struct DeviceIdentity {
+    signing_key: SigningKey,
+    id: String,
+}
+
+/// The on-disk form of a [`DeviceIdentity`]; keys are base64url without
+/// padding.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StoredIdentity {
+    version: u32,
+    device_id: String,
+    public_key: String,
+    private_key: String,
+}
+
+impl DeviceIdentity {
+    pub fn from_secret_key(secret: [u8; 32]) -> Self {
+        let signing_key = SigningKey::from_bytes(&secret);
+        let id = device_id(signing_key.verifying_key().as_bytes());
+        Self { signing_key, id }
+    }
+
+    pub fn generate() -> Result<Self, CliError> {
+        let mut secret = [0_u8; 32];
+        getrandom::fill(&mut secret)
+            .map_err(|error| CliError::State(format!("failed to generate device key: {error}")))?;
+        Ok(Self::from_secret_key(secret))
+    }
+
+    /// Loads the identity stored in `state`, creating and persisting a new
+    /// one on first use.
+    pub fn load_or_create(state: &StateDir) -> Result<Self, CliError> {
+        let invalid = |reason: &str| {
+            CliError::State(format!(
+                "invalid device identity in {}: {reason}",
+                state.root().join(IDENTITY_FILE).display()
+            ))
+        };
+
+        if let Some(contents) = state.read(IDENTITY_FILE)? {
+            let stored: StoredIdentity =
+                serde_json::from_slice(&contents).map_err(|error| invalid(&error.to_string()))?;
+            let secret = URL_SAFE_NO_PAD
+                .decode(&stored.private_key)
+                .ok()
+                .and_then(|secret| <[u8; 32]>::try_from(secret).ok())
+                .ok_or_else(|| invalid("privateKey is not a 32-byte base64url key"))?;
+            let identity = Self::from_secret_key(secret);
+            if identity.id != stored.device_id || identity.public_key() != stored.public_key {
+                return Err(invalid("deviceId or publicKey does not match privateKey"));
+            }
+            return Ok(identity);
+        }
+
+        let identity = Self::generate()?;
+        let stored = StoredIdentity {
+            version: IDENTITY_VERSION,
+            device_id: identity.id.clone(),
+            public_key: identity.public_key(),
+            private_key: URL_SAFE_NO_PAD.encode(identity.signing_key.to_bytes()),
+        };
+        let contents = serde_json::to_vec_pretty(&stored).map_err(|error| {
+            CliError::State(format!("failed to encode device identity: {error}"))
+        })?;
+        state.write_private(IDENTITY_FILE, &contents)?;
+        Ok(identity)
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The raw public key, base64url-encoded without padding.
+    pub fn public_key(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.signing_key.verifying_key().as_bytes())
+    }
+
+    /// Builds the `device` block of `connect` params, signing the client,
+    /// role, scopes and token already present in `params` together with the
+    /// gateway's challenge `nonce`.
+    pub(crate) fn connect_block(
+        &self,
+        params: &Value,
+        nonce: Option<&str>,
+        signed_at_ms: u64,
+    ) -> Value {
+        let text = |pointer: &str| params.pointer(pointer).and_then(Value::as_str);
+        let scopes = params
+            .get("scopes")
+            .and_then(Value::as_array)
+            .map(|scopes| {
+                scopes
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join(",")
+            })
+            .unwrap_or_default();
+
+        let payload = auth_payload(&AuthPayload {
+            device_id: &self.id,
+            client_id: text("/client/id").unwrap_or_default(),
+            client_mode: text("/client/mode").unwrap_or_default(),
+            role: text("/role").unwrap_or_default(),
+            scopes: &scopes,
+            signed_at_ms,
+            token: text("/auth/token").unwrap_or_default(),
+            nonce,
+        });
+        let signature = self.signing_key.sign(payload.as_bytes());
+
+        let mut block = json!({
+            "id": self.id,
+            "publicKey": self.public_key(),
+            "signature": URL_SAFE_NO_PAD.encode(signature.to_bytes()),
+            "signedAt": signed_at_ms,
+        });
+        if let (Some(block), Some(nonce)) = (block.as_object_mut(), nonce) {
+            block.insert("nonce".to_owned(), json!(nonce));
+        }
+        block
+    }
+}
+
+impl fmt::Debug for DeviceIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceIdentity")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The fields covered by a device signature.
+struct AuthPayload<'a> {
+    device_id: &'a str,
+    client_id: &'a str,
+    client_mode: &'a str,
+    role: &'a str,
+    scopes: &'a str,
+    signed_at_ms: u64,
+    token: &'a str,
+    nonce: Option<&'a str>,
+}
+
+/// The `|`-joined string a device signs: `v2` when the gateway issued a
+/// challenge nonce (appended last), `v1` otherwise.
+fn auth_payload(payload: &AuthPayload<'_>) -> String {
+    let signed_at = payload.signed_at_ms.to_string();
+    let mut fields = vec![
+        if payload.nonce.is_some() { "v2" } else { "v1" },
+        payload.device_id,
+        payload.client_id,
+        payload.client_mode,
+        payload.role,
+        payload.scopes,
+        &signed_at,
+        payload.token,
+    ];
+    fields.extend(payload.nonce);
+    fields.join("|")
+}
+
+fn device_id(public_key: &[u8]) -> String {
+    Sha256::digest(public_key)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+    use serde_json::json;
+
+    use crate::{CliError, DeviceIdentity, StateDir};
+
+    #[test]
+    fn identity_is_created_once_and_reloaded() {
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = dir.path();
+        let state = StateDir::new(root);
+
+        let created = DeviceIdentity::load_or_create(&state).expect("identity should be created");
+        let reloaded = DeviceIdentity::load_or_create(&state).expect("identity should reload");
+        assert_eq!(created.id(), reloaded.id());
+        assert_eq!(created.public_key(), reloaded.public_key());
+        assert_eq!(created.id().len(), 64);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(root.join("device.json"))
+                .expect("identity file should exist")
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::write(root.join("device.json"), b"{\"version\":1}").expect("file should be writable");
+        assert!(matches!(
+            DeviceIdentity::load_or_create(&state),
+            Err(CliError::State(_))
+        ));
+    }
+
+    #[test]
+    fn connect_block_signs_challenge_nonce() {
+        let identity = DeviceIdentity::from_secret_key([7; 32]);
+        let params = json!({
+            "role": "operator",
+            "client": { "id": "reclaw-cli", "mode": "operator" },
+            "auth": { "token": "secret" }
+        });
+
+        let block = identity.connect_block(&params, Some("n-1"), 1_700_000_000_000);
+        assert_eq!(block["id"], identity.id());
+        assert_eq!(block["nonce"], "n-1");
+        assert_eq!(block["signedAt"], 1_700_000_000_000_u64);
+
+        let public_key: [u8; 32] = URL_SAFE_NO_PAD
+            .decode(block["publicKey"].as_str().expect("publicKey"))
+            .expect("publicKey should decode")
+            .try_into()
+            .expect("publicKey should be 32 bytes");
+        let signature: [u8; 64] = URL_SAFE_NO_PAD
+            .decode(block["signature"].as_str().expect("signature"))
+            .expect("signature should decode")
+            .try_into()
+            .expect("signature should be 64 bytes");
+        let expected = format!(
+            "v2|{}|reclaw-cli|operator|operator||1700000000000|secret|n-1",
+            identity.id()
+        );
+        VerifyingKey::from_bytes(&public_key)
+            .expect("public key should be valid")
+            .verify(expected.as_bytes(), &Signature::from_bytes(&signature))
+            .expect("signature should cover the v2 payload");
+
+        let unchallenged = identity.connect_block(&params, None, 1);
+        assert!(unchallenged.get("nonce").is_none());
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    io::{ErrorKind, Write},
+    process::{Command, Output, Stdio},
+};
+
+use crate::CliError;
+
+const SERVICE: &str = "reclaw-cli";
+
+/// Looks up the secret stored for `account` in the OS keyring: the Secret
+/// Service through `secret-tool` on Linux and the BSDs, the login keychain
+/// through `security` on macOS. `None` when nothing is stored yet.
+pub(crate) fn lookup(account: &str) -> Result<Option<String>, CliError> {
+    let output = if cfg!(target_os = "macos") {
+        run(
+            "security",
+            &["find-generic-password", "-s", SERVICE, "-a", account, "-w"],
+            None,
+        )?
+    } else {
+        run(
+            "secret-tool",
+            &["lookup", "service", SERVICE, "account", account],
+            None,
+        )?
+    };
+    let secret = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    match (output.status.success(), secret.is_empty()) {
+        (true, false) => Ok(Some(secret)),
+        // Both tools exit non-zero with no output when there is no entry.
+        (_, true) => Ok(None),
+        (false, false) => Err(failed(&output)),
+    }
+}
+
+/// Stores `secret` for `account`, replacing any earlier one. The secret is
+/// written to the tool's stdin, never passed as an argument.
+pub(crate) fn store(account: &str, secret: &str) -> Result<(), CliError> {
+    let output = if cfg!(target_os = "macos") {
+        // `security -i` reads commands from stdin, keeping the secret out of
+        // the process list.
+        let command = format!(
+            "add-generic-password -U -s {SERVICE} -a \"{}\" -w \"{secret}\"\n",
+            account.replace('\\', "\\\\").replace('"', "\\\"")
+        );
+        run("security", &["-i"], Some(&command))?
+    } else {
+        run(
+            "secret-tool",
+            &[
+                "store",
+                "--label",
+                "reclaw-cli credential key",
+                "service",
+                SERVICE,
+                "account",
+                account,
+            ],
+            Some(secret),
+        )?
+    };
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(failed(&output))
+    }
+}
+
+fn run(program: &str, args: &[&str], input: Option<&str>) -> Result<Output, CliError> {
+    if cfg!(windows) {
+        return Err(CliError::State(
+            "no OS keyring support on Windows; set RECLAW_CREDENTIAL_PASSPHRASE or pass --credential-key file".to_owned(),
+        ));
+    }
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| match error.kind() {
+            ErrorKind::NotFound => CliError::State(format!(
+                "no OS keyring available ({program} not found); set RECLAW_CREDENTIAL_PASSPHRASE or pass --credential-key file"
+            )),
+            _ => CliError::State(format!("failed to run {program}: {error}")),
+        })?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin
+            .write_all(input.as_bytes())
+            .map_err(|error| CliError::State(format!("failed to write to {program}: {error}")))?;
+    }
+    child
+        .wait_with_output()
+        .map_err(|error| CliError::State(format!("failed to run {program}: {error}")))
+}
+
+fn failed(output: &Output) -> CliError {
+    CliError::State(format!(
+        "OS keyring failed ({}): {}",
+        output.status,
+        String::from_utf8_lossy(&output.stderr).trim()
+    ))
+}
@@ -1,14 +1,54 @@
 mod client;
 mod command;
-
-pub use client::{GatewayClient, HttpGatewayClient};
-pub use command::{run_with_client, CliArgs, CliCommand, CliError};
+mod config;
+mod credentials;
+mod device;
+mod keyring;
+mod output;
+mod protocol;
+mod proxy;
+mod query;
+mod retry;
+mod secret;
+mod session;
+mod state;
+mod summary;
+mod template;
+mod timeout;
+mod tls;
+mod tokens;
+
+pub use client::{ClientOptions, GatewayClient, HttpGatewayClient, PasswordPrompt};
+pub use command::{
+    run_auth_command, run_login, run_logout, run_profile_command, run_with_client, stream_events,
+    AuthCommand, CliArgs, CliCommand, CliError, ClientCommand, ConfigArgs, ConnectionArgs,
+    ProfileCommand, TokenCommand, DEFAULT_SERVER,
+};
+pub use config::{Config, OutputFormat, Profile};
+pub use credentials::{Credential, CredentialStore, KeySource};
+pub use device::DeviceIdentity;
+pub use output::{render_error, render_output, render_record, ColorChoice, OutputOptions, Summary};
+pub use protocol::{
+    GatewayError, GatewayEvent, HelloAuth, HelloFeatures, HelloOk, HelloPolicy, HelloServer,
+    ProtocolRange, PROTOCOL_VERSION,
+};
+pub use proxy::{Proxy, ProxySettings};
+pub use query::Query;
+pub use retry::RetryPolicy;
+pub use session::{EventSubscription, GatewaySession, PendingResponse};
+pub use state::StateDir;
+pub use template::Template;
+pub use timeout::{TimeoutPhase, Timeouts};
+pub use tls::{TlsConfig, TlsOptions};
+pub use tokens::{DeviceToken, TokenStore};
 
 #[cfg(test)]
 mod tests {
     use serde_json::{json, Value};
 
-    use crate::{run_with_client, CliArgs, CliCommand, CliError, GatewayClient};
+    use crate::{
+        run_with_client, CliError, ClientCommand, EventSubscription, GatewayClient, HelloOk,
+    };
 
     #[derive(Default)]
     struct MockClient {
@@ -35,59 +75,46 @@ mod tests {
                 .clone()
                 .ok_or_else(|| CliError::Transport("rpc response fixture missing".to_owned()))
         }
+
+        fn hello(&self) -> Result<HelloOk, CliError> {
+            Err(CliError::Transport("hello fixture missing".to_owned()))
+        }
+
+        fn subscribe(&self, _patterns: &[String]) -> Result<EventSubscription, CliError> {
+            Err(CliError::Transport("event fixture missing".to_owned()))
+        }
     }
 
     #[test]
     fn health_command_requires_ok_true() {
-        let args = CliArgs {
-            server: "http://127.0.0.1:18789".to_owned(),
-            auth_token: None,
-            auth_password: None,
-            json: false,
-            command: CliCommand::Health,
-        };
-
         let client = MockClient {
             healthz_response: Some(json!({ "ok": false })),
             info_response: None,
             rpc_response: None,
         };
 
-        let result = run_with_client(&args, &client);
-        assert!(matches!(result, Err(CliError::Protocol(_))));
+        let result = run_with_client(&ClientCommand::Health, &client);
+        assert!(matches!(result, Err(CliError::Unhealthy(_))));
     }
 
     #[test]
     fn info_command_returns_payload() {
-        let args = CliArgs {
-            server: "http://127.0.0.1:18789".to_owned(),
-            auth_token: None,
-            auth_password: None,
-            json: true,
-            command: CliCommand::Info,
-        };
-
         let client = MockClient {
             healthz_response: None,
             info_response: Some(json!({ "runtime": "reclaw-core" })),
             rpc_response: None,
         };
 
-        let output = run_with_client(&args, &client).expect("info command should succeed");
+        let output =
+            run_with_client(&ClientCommand::Info, &client).expect("info command should succeed");
         assert_eq!(output["runtime"], "reclaw-core");
     }
 
     #[test]
     fn rpc_command_rejects_non_object_params() {
-        let args = CliArgs {
-            server: "http://127.0.0.1:18789".to_owned(),
-            auth_token: None,
-            auth_password: None,
-            json: true,
-            command: CliCommand::Rpc {
-                method: "system.healthz".to_owned(),
-                params: "[]".to_owned(),
-            },
+        let command = ClientCommand::Rpc {
+            method: "system.healthz".to_owned(),
+            params: "[]".to_owned(),
         };
 
         let client = MockClient {
@@ -96,7 +123,7 @@ mod tests {
             rpc_response: Some(json!({ "result": {} })),
         };
 
-        let result = run_with_client(&args, &client);
+        let result = run_with_client(&command, &client);
         assert!(matches!(result, Err(CliError::InvalidParams(_))));
     }
 }
@@ -1,35 +1,199 @@
-use std::process::ExitCode;
+use std::{
+    io::{self, IsTerminal},
+    process::ExitCode,
+};
 
 use clap::Parser;
-use reclaw_cli::{run_with_client, CliArgs, HttpGatewayClient};
+use reclaw_cli::{
+    render_error, render_output, run_auth_command, run_login, run_logout, run_profile_command,
+    run_with_client, stream_events, CliArgs, CliCommand, CliError, ClientOptions, Credential,
+    HttpGatewayClient, OutputOptions, PasswordPrompt, Query, Template, TokenStore,
+};
 
 fn main() -> ExitCode {
-    match run() {
+    let mut args = CliArgs::parse();
+    match run(&mut args) {
         Ok(()) => ExitCode::SUCCESS,
         Err(error) => {
-            eprintln!("reclaw-cli failed: {error}");
-            ExitCode::from(1)
+            let mut output = output_options(&args);
+            output.template = args
+                .template
+                .as_deref()
+                .and_then(|template| Template::parse(template).ok());
+            let (stdout, stderr) = render_error(&output, &error);
+            if let Some(text) = stdout {
+                print!("{text}");
+            }
+            if let Some(message) = stderr {
+                eprintln!("{message}");
+            }
+            ExitCode::from(error.exit_code())
         }
     }
 }
 
-fn run() -> Result<(), String> {
-    let args = CliArgs::parse();
-    let client = HttpGatewayClient::new_with_auth(
-        args.server.clone(),
-        args.auth_token.clone(),
-        args.auth_password.clone(),
-    )
-    .map_err(|error| error.to_string())?;
-    let output = run_with_client(&args, &client).map_err(|error| error.to_string())?;
-
-    if args.json {
-        let text = serde_json::to_string_pretty(&output)
-            .map_err(|error| format!("failed to encode output as JSON: {error}"))?;
-        println!("{text}");
-    } else {
-        println!("{output}");
+fn run(args: &mut CliArgs) -> Result<(), CliError> {
+    args.apply_env(|name| std::env::var(name).ok())?;
+    if !matches!(args.command, CliCommand::Profile { .. }) {
+        args.load_profile()?;
     }
+    let args = &*args;
+    let query = args.query.as_deref().map(Query::parse).transpose()?;
+    let mut output_options = output_options(args);
+    output_options.template = args.template.as_deref().map(Template::parse).transpose()?;
 
+    let mut output = match &args.command {
+        CliCommand::Profile { command } => run_profile_command(command, &args.config.path()?)?,
+        CliCommand::Auth { command } => {
+            let tokens = TokenStore::new(args.connection.state_dir()?);
+            run_auth_command(command, args.server(), &tokens)?
+        }
+        CliCommand::Login => {
+            let (token, password) = credentials(args)?;
+            let credential = if token.is_none() && password.is_none() {
+                let prompt = io::stdin().is_terminal().then(terminal_prompt).ok_or_else(|| {
+                    CliError::InvalidAuth(
+                        "login needs credentials; pass --auth-token, --auth-password or another credential source"
+                            .to_owned(),
+                    )
+                })?;
+                Credential::new(None, Some(prompt.ask()?))?
+            } else {
+                Credential::new(token, password)?
+            };
+            let store = args.connection.credential_store()?;
+            run_login(
+                args.server(),
+                &credential,
+                connect_options(args, true)?,
+                &store,
+            )?
+        }
+        CliCommand::Logout { all } => {
+            let store = args.connection.credential_store()?;
+            run_logout(args.server(), *all, &store)?
+        }
+        CliCommand::Events { filters } => {
+            return stream_events(
+                &client(args, true)?,
+                filters,
+                query.as_ref(),
+                &output_options,
+                &mut io::stdout().lock(),
+            );
+        }
+        CliCommand::Client(command) => {
+            run_with_client(command, &client(args, command.opens_session())?)?
+        }
+    };
+
+    if let Some(query) = &query {
+        output = query.search(&output)?;
+    }
+    print!("{}", render_output(&output_options, &output)?);
     Ok(())
 }
+
+fn client(args: &CliArgs, opens_session: bool) -> Result<HttpGatewayClient, CliError> {
+    let (auth_token, auth_password) = credentials(args)?;
+    let mut options = connect_options(args, opens_session)?;
+    options.password_prompt = io::stdin().is_terminal().then(terminal_prompt);
+    // Without a state directory there can be no stored login to fall back to.
+    options.credentials = args.connection.credential_store().ok();
+
+    HttpGatewayClient::new_with_options(args.server(), auth_token, auth_password, options)
+}
+
+/// Connection options, plus the device identity and its token store when
+/// the command opens a WebSocket session and device signing is on. Unusable
+/// proxy variables are reported here, once per invocation.
+fn connect_options(args: &CliArgs, opens_session: bool) -> Result<ClientOptions, CliError> {
+    let mut options = args.connection.client_options()?;
+    for warning in options.proxy.warnings() {
+        eprintln!("reclaw-cli: {warning}");
+    }
+    if opens_session {
+        options.device = args.connection.device()?;
+    }
+    if options.device.is_some() {
+        options.tokens = Some(TokenStore::new(args.connection.state_dir()?));
+    }
+    Ok(options)
+}
+
+/// The token and password from the credential flags, prompting first when
+/// `--prompt-password` is set.
+fn credentials(args: &CliArgs) -> Result<(Option<String>, Option<String>), CliError> {
+    let (auth_token, auth_password) = args.credentials(&mut io::stdin().lock())?;
+    if !args.prompt_password {
+        return Ok((auth_token, auth_password));
+    }
+
+    if !io::stdin().is_terminal() {
+        return Err(CliError::InvalidAuth(
+            "--prompt-password needs a terminal on stdin".to_owned(),
+        ));
+    }
+    let password = terminal_prompt().ask()?;
+    if password.trim().is_empty() {
+        return Err(CliError::InvalidAuth("empty password".to_owned()));
+    }
+    Ok((auth_token, Some(password)))
+}
+
+/// The output flags, completed with the terminal width and color choice.
+/// Summaries are for people; piped output keeps the JSON scripts expect.
+fn output_options(args: &CliArgs) -> OutputOptions {
+    let mut options = args.output_options();
+    let terminal = io::stdout().is_terminal();
+    if !terminal {
+        options.summary = None;
+    }
+    options.width = terminal_width();
+    let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    options.color = args.color.enabled(terminal, no_color);
+    options
+}
+
+/// The width tables are fitted to: `COLUMNS` when set, otherwise the width
+/// of the terminal on stdout. Output to a pipe or file is not narrowed.
+fn terminal_width() -> Option<usize> {
+    if let Some(columns) = std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.trim().parse().ok())
+        .filter(|columns| *columns > 0)
+    {
+        return Some(columns);
+    }
+    if !io::stdout().is_terminal() {
+        return None;
+    }
+    window_columns()
+}
+
+#[cfg(unix)]
+fn window_columns() -> Option<usize> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: TIOCGWINSZ only writes a `winsize` through the pointer, which
+    // points at a live, correctly typed local.
+    let result = unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) };
+    (result == 0 && size.ws_col > 0).then_some(usize::from(size.ws_col))
+}
+
+#[cfg(not(unix))]
+fn window_columns() -> Option<usize> {
+    None
+}
+
+/// Reads the password from the terminal without echoing it.
+fn terminal_prompt() -> PasswordPrompt {
+    PasswordPrompt::new(|| {
+        rpassword::prompt_password("Gateway password: ")
+            .map_err(|error| CliError::InvalidAuth(format!("failed to read password: {error}")))
+    })
+}
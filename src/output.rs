@@ -0,0 +1,545 @@
+use std::cmp::Ordering;
+
+use clap::ValueEnum;
+use serde_json::Value;
+
+use crate::{summary, CliError, OutputFormat, Template};
+
+/// When to color human-readable output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    /// When stdout is a terminal and `NO_COLOR` is unset or empty.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self, terminal: bool, no_color: bool) -> bool {
+        match self {
+            Self::Auto => terminal && !no_color,
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+/// A command whose `text` output is a human summary rather than JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Summary {
+    Health,
+    Info,
+}
+
+/// How a command's result is printed.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutputOptions {
+    pub format: OutputFormat,
+    /// Dotted paths (`a.b.c`) of the table columns, in order. Inferred from
+    /// the rows' keys when empty.
+    pub columns: Vec<String>,
+    /// Dotted path to sort table rows by, ascending.
+    pub sort_by: Option<String>,
+    pub no_headers: bool,
+    /// Width to fit tables into, e.g. the terminal's; unlimited when `None`.
+    pub width: Option<usize>,
+    /// Printed instead of JSON by the `text` format.
+    pub summary: Option<Summary>,
+    /// Whether summaries use ANSI colors.
+    pub color: bool,
+    /// Renders results as text in place of `format`.
+    pub template: Option<Template>,
+}
+
+/// Renders a command's result, newline-terminated unless there is nothing
+/// to print (e.g. `ndjson` of an empty array).
+pub fn render_output(options: &OutputOptions, value: &Value) -> Result<String, CliError> {
+    if let Some(template) = &options.template {
+        return Ok(line(template.render(value)?));
+    }
+    let text = match options.format {
+        OutputFormat::Text => match options.summary {
+            Some(Summary::Health) => summary::health(value, options.color),
+            Some(Summary::Info) => summary::info(value, options.color),
+            None => value.to_string(),
+        },
+        OutputFormat::Json => pretty(value)?,
+        OutputFormat::JsonCompact => compact(value)?,
+        OutputFormat::Yaml => yaml(value),
+        OutputFormat::Table => table(options, value),
+        OutputFormat::Ndjson => match value {
+            Value::Array(items) => items
+                .iter()
+                .map(compact)
+                .collect::<Result<Vec<_>, _>>()?
+                .join("\n"),
+            value => compact(value)?,
+        },
+        OutputFormat::Raw => raw(value),
+    };
+    Ok(line(text))
+}
+
+/// What a failed command prints, as `(stdout, stderr)`. Structured formats
+/// print the error document on stdout, the others a message on stderr. The
+/// template only ever renders an unhealthy gateway's payload, which is
+/// printed like a result, never the error document.
+pub fn render_error(options: &OutputOptions, error: &CliError) -> (Option<String>, Option<String>) {
+    let failed = || Some(format!("reclaw-cli failed: {error}"));
+    match error {
+        CliError::Unhealthy(health)
+            if options.template.is_some() || options.format == OutputFormat::Text =>
+        {
+            (
+                render_output(options, health).ok(),
+                Some("reclaw-cli failed: gateway reported unhealthy".to_owned()),
+            )
+        }
+        _ if options.format.is_structured() => {
+            let document = OutputOptions {
+                format: options.format,
+                ..OutputOptions::default()
+            };
+            match render_output(&document, &error.to_json()) {
+                Ok(text) => (Some(text), None),
+                Err(_) => (None, failed()),
+            }
+        }
+        _ => (None, failed()),
+    }
+}
+
+fn line(mut text: String) -> String {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+/// Renders one record of a stream such as `events`: a YAML document, a
+/// pretty JSON object, or a single compact JSON line for every other format.
+/// A template's output gets a line of its own.
+pub fn render_record(options: &OutputOptions, value: &Value) -> Result<String, CliError> {
+    if let Some(template) = &options.template {
+        return Ok(format!(
+            "{}\n",
+            template.render(value)?.trim_end_matches('\n')
+        ));
+    }
+    match options.format {
+        OutputFormat::Json => Ok(format!("{}\n", pretty(value)?)),
+        OutputFormat::Yaml => Ok(format!("---\n{}", yaml(value))),
+        _ => Ok(format!("{}\n", compact(value)?)),
+    }
+}
+
+fn pretty(value: &Value) -> Result<String, CliError> {
+    serde_json::to_string_pretty(value)
+        .map_err(|error| CliError::Protocol(format!("failed to encode output as JSON: {error}")))
+}
+
+fn compact(value: &Value) -> Result<String, CliError> {
+    serde_json::to_string(value)
+        .map_err(|error| CliError::Protocol(format!("failed to encode output as JSON: {error}")))
+}
+
+/// Strings unquoted, arrays one element per line, anything else as JSON.
+fn raw(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(raw).collect::<Vec<_>>().join("\n"),
+        value => value.to_string(),
+    }
+}
+
+/// The value at a dotted path; numeric segments also index arrays.
+fn lookup<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(value, |value, segment| match value {
+            Value::Array(items) => items.get(segment.parse::<usize>().ok()?),
+            value => value.get(segment),
+        })
+}
+
+/// A table cell on one line: strings unquoted, `null` empty, anything else
+/// as JSON.
+fn cell(value: Option<&Value>) -> String {
+    let text = match value {
+        None | Some(Value::Null) => return String::new(),
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => value.to_string(),
+    };
+    text.replace(['\n', '\r', '\t'], " ")
+}
+
+/// Numbers, strings and booleans sort among their own kind; across kinds,
+/// and for missing values (last), by kind.
+fn compare_cells(left: Option<&Value>, right: Option<&Value>) -> Ordering {
+    let rank = |value: Option<&Value>| match value {
+        Some(Value::Number(_)) => 0,
+        Some(Value::String(_)) => 1,
+        Some(Value::Bool(_)) => 2,
+        Some(Value::Array(_)) => 3,
+        Some(Value::Object(_)) => 4,
+        Some(Value::Null) | None => 5,
+    };
+    match (left, right) {
+        (Some(Value::Number(left)), Some(Value::Number(right))) => left
+            .as_f64()
+            .partial_cmp(&right.as_f64())
+            .unwrap_or(Ordering::Equal),
+        (Some(Value::String(left)), Some(Value::String(right))) => left.cmp(right),
+        (Some(Value::Bool(left)), Some(Value::Bool(right))) => left.cmp(right),
+        (left, right) => rank(left).cmp(&rank(right)),
+    }
+}
+
+/// `text` cut to `width` characters, ending in `…` when shortened.
+fn truncate(text: &str, width: usize) -> String {
+    if text.chars().count() <= width {
+        return text.to_owned();
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    cut
+}
+
+/// Columns are not narrowed below this many characters to fit the width.
+const MIN_COLUMN_WIDTH: usize = 6;
+
+/// An object is one row and an array one row per element. Columns come from
+/// `options.columns`, or else from the rows' keys in order of first
+/// appearance; arrays of non-objects get a single `VALUE` column. Scalars
+/// print as `raw` does.
+fn table(options: &OutputOptions, value: &Value) -> String {
+    let mut rows: Vec<&Value> = match value {
+        Value::Array(items) => items.iter().collect(),
+        Value::Object(_) => vec![value],
+        value => return raw(value),
+    };
+    if rows.is_empty() {
+        return String::new();
+    }
+    if let Some(path) = &options.sort_by {
+        rows.sort_by(|left, right| compare_cells(lookup(left, path), lookup(right, path)));
+    }
+
+    let mut columns: Vec<&str> = options.columns.iter().map(String::as_str).collect();
+    if columns.is_empty() {
+        for row in &rows {
+            if let Value::Object(map) = row {
+                for key in map.keys() {
+                    if !columns.contains(&key.as_str()) {
+                        columns.push(key);
+                    }
+                }
+            }
+        }
+    }
+
+    let mut lines = Vec::with_capacity(rows.len() + 1);
+    if columns.is_empty() {
+        lines.push(vec!["VALUE".to_owned()]);
+        lines.extend(rows.iter().map(|row| vec![cell(Some(row))]));
+    } else {
+        lines.push(columns.iter().map(|name| name.to_uppercase()).collect());
+        lines.extend(rows.iter().map(|row| {
+            columns
+                .iter()
+                .map(|path| cell(lookup(row, path)))
+                .collect::<Vec<_>>()
+        }));
+    }
+    if options.no_headers {
+        lines.remove(0);
+    }
+
+    let mut widths = vec![0; lines[0].len()];
+    for line in &lines {
+        for (width, text) in widths.iter_mut().zip(line) {
+            *width = (*width).max(text.chars().count());
+        }
+    }
+    if let Some(limit) = options.width {
+        // Narrow the widest column one character at a time until the row,
+        // with its two-space gaps, fits.
+        let gaps = 2 * (widths.len() - 1);
+        while widths.iter().sum::<usize>() + gaps > limit {
+            let Some(widest) = widths
+                .iter_mut()
+                .filter(|width| **width > MIN_COLUMN_WIDTH)
+                .max_by_key(|width| **width)
+            else {
+                break;
+            };
+            *widest -= 1;
+        }
+    }
+
+    lines
+        .iter()
+        .map(|line| {
+            let mut text = String::new();
+            for (index, (value, width)) in line.iter().zip(&widths).enumerate() {
+                let value = truncate(value, *width);
+                if index + 1 == line.len() {
+                    text.push_str(&value);
+                } else {
+                    text.push_str(&format!("{value:<width$}  "));
+                }
+            }
+            text.trim_end().to_owned()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn yaml(value: &Value) -> String {
+    let mut out = String::new();
+    write_yaml(&mut out, value, 0);
+    out
+}
+
+/// Writes `value` starting at the current position of the line, with any
+/// further lines indented by `indent` spaces.
+fn write_yaml(out: &mut String, value: &Value, indent: usize) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (index, (key, value)) in map.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(&" ".repeat(indent));
+                }
+                out.push_str(&yaml_string(key));
+                out.push(':');
+                write_yaml_child(out, value, indent);
+            }
+        }
+        Value::Array(items) if !items.is_empty() => {
+            for (index, item) in items.iter().enumerate() {
+                if index > 0 {
+                    out.push_str(&" ".repeat(indent));
+                }
+                out.push_str("- ");
+                write_yaml(out, item, indent + 2);
+            }
+        }
+        scalar => {
+            out.push_str(&yaml_scalar(scalar));
+            out.push('\n');
+        }
+    }
+}
+
+/// Writes the value of a mapping entry after its `key:`.
+fn write_yaml_child(out: &mut String, value: &Value, indent: usize) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent + 2));
+            write_yaml(out, value, indent + 2);
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push('\n');
+            out.push_str(&" ".repeat(indent + 2));
+            write_yaml(out, value, indent + 2);
+        }
+        scalar => {
+            out.push(' ');
+            write_yaml(out, scalar, indent);
+        }
+    }
+}
+
+fn yaml_scalar(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_owned(),
+        Value::String(text) => yaml_string(text),
+        Value::Object(_) => "{}".to_owned(),
+        Value::Array(_) => "[]".to_owned(),
+        value => value.to_string(),
+    }
+}
+
+/// A string plain when YAML would read it back unchanged, otherwise in
+/// double quotes (JSON's escaping is valid YAML).
+fn yaml_string(text: &str) -> String {
+    let reserved = matches!(
+        text.to_ascii_lowercase().as_str(),
+        "" | "~" | "null" | "true" | "false" | "yes" | "no" | "on" | "off" | "y" | "n"
+    );
+    let numeric = text.parse::<f64>().is_ok()
+        || text.starts_with(|ch: char| ch.is_ascii_digit() || ch == '.' || ch == '+');
+    let indicator = text.starts_with(|ch: char| "-?:,[]{}#&*!|>'\"%@`".contains(ch));
+    let ambiguous = text.contains(": ")
+        || text.contains(" #")
+        || text.ends_with(':')
+        || text.starts_with(' ')
+        || text.ends_with(' ')
+        || text.chars().any(char::is_control);
+
+    if reserved || numeric || indicator || ambiguous {
+        Value::String(text.to_owned()).to_string()
+    } else {
+        text.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        output::{render_error, render_output, OutputOptions, Summary},
+        CliError, OutputFormat, Template,
+    };
+
+    fn render(format: OutputFormat, value: &serde_json::Value) -> String {
+        let options = OutputOptions {
+            format,
+            ..OutputOptions::default()
+        };
+        render_output(&options, value).expect("output should render")
+    }
+
+    #[test]
+    fn formats_render_arrays_strings_and_nested_values() {
+        let value = json!([
+            { "id": "a", "state": "idle", "tags": ["x"] },
+            { "id": "b", "count": 2 }
+        ]);
+
+        assert_eq!(
+            render(OutputFormat::Ndjson, &value),
+            "{\"id\":\"a\",\"state\":\"idle\",\"tags\":[\"x\"]}\n{\"count\":2,\"id\":\"b\"}\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Table, &value),
+            "ID  STATE  TAGS   COUNT\na   idle   [\"x\"]\nb                 2\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Yaml, &value),
+            "- id: a\n  state: idle\n  tags:\n    - x\n- count: 2\n  id: b\n"
+        );
+        assert_eq!(
+            render(OutputFormat::Raw, &json!("plain text")),
+            "plain text\n"
+        );
+        assert_eq!(
+            render(
+                OutputFormat::Yaml,
+                &json!({ "a": "true", "b": "1.0", "c": {} })
+            ),
+            "a: \"true\"\nb: \"1.0\"\nc: {}\n"
+        );
+        assert_eq!(render(OutputFormat::Ndjson, &json!([])), "");
+    }
+
+    #[test]
+    fn tables_select_dotted_columns_sort_and_fit_the_width() {
+        let nodes = json!([
+            { "name": "worker-b", "status": { "ready": true }, "load": 0.5 },
+            { "name": "worker-a", "status": { "ready": false }, "load": 2 },
+            { "name": "worker-c", "note": "line one\nline two" }
+        ]);
+        let options = OutputOptions {
+            format: OutputFormat::Table,
+            columns: vec!["name".to_owned(), "status.ready".to_owned()],
+            sort_by: Some("load".to_owned()),
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            render_output(&options, &nodes).expect("table should render"),
+            "NAME      STATUS.READY\nworker-b  true\nworker-a  false\nworker-c\n"
+        );
+
+        let options = OutputOptions {
+            format: OutputFormat::Table,
+            columns: vec!["name".to_owned(), "note".to_owned()],
+            sort_by: Some("name".to_owned()),
+            no_headers: true,
+            width: Some(18),
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            render_output(&options, &nodes).expect("table should render"),
+            "worker-a\nworker-b\nworker-c  line on…\n"
+        );
+    }
+
+    #[test]
+    fn text_output_summarizes_health_and_info() {
+        let health = json!({
+            "ok": true,
+            "version": "1.4.2",
+            "uptimeMs": 7_980_000,
+            "checks": { "db": "ok", "queue": "degraded" }
+        });
+        let options = OutputOptions {
+            summary: Some(Summary::Health),
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            render_output(&options, &health).expect("summary should render"),
+            "Gateway healthy\n  version       1.4.2\n  uptime        2h 13m\n  checks.db     ok\n  checks.queue  degraded\n"
+        );
+
+        let options = OutputOptions {
+            summary: Some(Summary::Health),
+            color: true,
+            ..OutputOptions::default()
+        };
+        let colored =
+            render_output(&options, &json!({ "ok": false })).expect("summary should render");
+        assert_eq!(colored, "Gateway \u{1b}[1m\u{1b}[31munhealthy\u{1b}[0m\n");
+
+        let options = OutputOptions {
+            summary: Some(Summary::Info),
+            ..OutputOptions::default()
+        };
+        assert_eq!(
+            render_output(
+                &options,
+                &json!({ "runtime": "reclaw-core", "server": { "version": "2.0.0", "host": "gw-1" }, "uptime": 42 })
+            )
+            .expect("summary should render"),
+            "reclaw-core 2.0.0\n  uptime       42s\n  server.host  gw-1\n"
+        );
+    }
+
+    #[test]
+    fn errors_skip_the_template_unless_the_gateway_is_unhealthy() {
+        // The format may come from RECLAW_OUTPUT or a profile next to --template.
+        let options = OutputOptions {
+            format: OutputFormat::JsonCompact,
+            template: Some(Template::parse("{{.s}}").expect("template should parse")),
+            ..OutputOptions::default()
+        };
+        let (stdout, stderr) = render_error(
+            &options,
+            &CliError::Transport("connection refused".to_owned()),
+        );
+        assert_eq!(
+            stdout.as_deref(),
+            Some("{\"error\":{\"exitCode\":10,\"kind\":\"transport\",\"message\":\"transport failure: connection refused\"}}\n")
+        );
+        assert_eq!(stderr, None);
+
+        let unhealthy = CliError::Unhealthy(json!({ "ok": false, "s": "degraded" }));
+        let (stdout, stderr) = render_error(&options, &unhealthy);
+        assert_eq!(stdout.as_deref(), Some("degraded\n"));
+        assert_eq!(
+            stderr.as_deref(),
+            Some("reclaw-cli failed: gateway reported unhealthy")
+        );
+
+        let text = OutputOptions::default();
+        assert_eq!(
+            render_error(&text, &CliError::Transport("connection refused".to_owned())),
+            (
+                None,
+                Some("reclaw-cli failed: transport failure: connection refused".to_owned())
+            )
+        );
+    }
+}
@@ -0,0 +1,505 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::CliError;
+
+/// The protocol version this client implements natively.
+pub const PROTOCOL_VERSION: u64 = 3;
+
+/// The inclusive range of gateway protocol versions offered in `connect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolRange {
+    min: u64,
+    max: u64,
+}
+
+impl ProtocolRange {
+    pub fn new(min: u64, max: u64) -> Result<Self, CliError> {
+        if min == 0 || min > max {
+            return Err(CliError::InvalidOption(format!(
+                "protocol range {min}-{max} is empty; --min-protocol must be between 1 and --max-protocol"
+            )));
+        }
+
+        Ok(Self { min, max })
+    }
+
+    /// Builds a range from optional bounds, filling a missing bound from
+    /// [`PROTOCOL_VERSION`] so that `--max-protocol 2` alone means `2-2`.
+    pub fn from_bounds(min: Option<u64>, max: Option<u64>) -> Result<Self, CliError> {
+        match (min, max) {
+            (Some(min), Some(max)) => Self::new(min, max),
+            (Some(min), None) => Self::new(min, min.max(PROTOCOL_VERSION)),
+            (None, Some(max)) => Self::new(max.min(PROTOCOL_VERSION), max),
+            (None, None) => Ok(Self::default()),
+        }
+    }
+
+    pub fn min(&self) -> u64 {
+        self.min
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max
+    }
+
+    pub fn contains(&self, version: u64) -> bool {
+        (self.min..=self.max).contains(&version)
+    }
+}
+
+impl Default for ProtocolRange {
+    fn default() -> Self {
+        Self {
+            min: PROTOCOL_VERSION,
+            max: PROTOCOL_VERSION,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.min == self.max {
+            write!(f, "{}", self.min)
+        } else {
+            write!(f, "{}-{}", self.min, self.max)
+        }
+    }
+}
+
+/// Recognises a `connect` rejection caused by a protocol version mismatch.
+/// The inner value is the range the gateway reported, or `None` when it
+/// reported the mismatch without one.
+pub(crate) fn protocol_mismatch(error: &GatewayError) -> Option<Option<String>> {
+    let number = |key: &str| error.details.get(key).and_then(Value::as_u64);
+
+    if let Some(expected) = number("expectedProtocol").or_else(|| number("protocol")) {
+        return Some(Some(expected.to_string()));
+    }
+    match (number("minProtocol"), number("maxProtocol")) {
+        (Some(min), Some(max)) if min == max => return Some(Some(min.to_string())),
+        (Some(min), Some(max)) => return Some(Some(format!("{min}-{max}"))),
+        _ => {}
+    }
+
+    if error.code.as_deref() == Some("PROTOCOL_MISMATCH")
+        || error
+            .message
+            .to_ascii_lowercase()
+            .contains("protocol mismatch")
+    {
+        return Some(None);
+    }
+
+    None
+}
+
+/// The end of the protocol mismatch message, after the offered range.
+pub(crate) fn describe_server_range(server: &Option<String>) -> String {
+    match server {
+        Some(range) => format!("gateway supports {range}"),
+        None => "gateway did not say which versions it supports".to_owned(),
+    }
+}
+
+/// The `hello-ok` payload returned by a successful `connect` handshake.
+/// Fields this client does not model are kept in `extra` and serialized
+/// back, so `hello` prints everything the gateway sent.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HelloOk {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub protocol: Option<u64>,
+    #[serde(default)]
+    pub server: HelloServer,
+    #[serde(default)]
+    pub features: HelloFeatures,
+    #[serde(default)]
+    pub policy: HelloPolicy,
+    #[serde(default)]
+    pub snapshot: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<HelloAuth>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Credentials the gateway issued to a paired device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HelloAuth {
+    /// Never printed; stored by the client for later connects.
+    #[serde(default, skip_serializing)]
+    pub device_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_at_ms: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HelloServer {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub conn_id: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// Methods and events the gateway advertises.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HelloFeatures {
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default)]
+    pub events: Vec<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HelloPolicy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_payload: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_buffered_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tick_interval_ms: Option<u64>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl HelloOk {
+    pub(crate) fn from_payload(mut payload: Value) -> Result<Self, CliError> {
+        match payload.get("type").and_then(Value::as_str) {
+            None | Some("hello-ok") => {}
+            Some(other) => {
+                return Err(CliError::Protocol(format!(
+                    "unexpected connect response type `{other}`"
+                )))
+            }
+        }
+        // The frame type is not part of the payload callers see.
+        if let Some(payload) = payload.as_object_mut() {
+            payload.remove("type");
+        }
+
+        serde_json::from_value(payload)
+            .map_err(|error| CliError::Protocol(format!("invalid hello-ok payload: {error}")))
+    }
+
+    pub fn supports_method(&self, method: &str) -> bool {
+        self.features.methods.iter().any(|name| name == method)
+    }
+
+    pub fn supports_event(&self, event: &str) -> bool {
+        self.features.events.iter().any(|name| name == event)
+    }
+}
+
+/// The `error` object of a failed `res` frame, kept intact so callers can
+/// branch on `code` and honour `retryable`/`retryAfterMs`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GatewayError {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    pub details: Value,
+    #[serde(default)]
+    pub retryable: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_ms: Option<u64>,
+}
+
+impl GatewayError {
+    pub(crate) fn from_frame(frame: &Value) -> Self {
+        let mut error: Self = frame
+            .get("error")
+            .cloned()
+            .and_then(|error| serde_json::from_value(error).ok())
+            .unwrap_or_default();
+        if error.message.is_empty() {
+            error.message = "rpc request failed".to_owned();
+        }
+        error
+    }
+
+    /// Whether the gateway refused the caller's credentials.
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(self.code.as_deref(), Some("UNAUTHORIZED" | "FORBIDDEN"))
+    }
+}
+
+impl fmt::Display for GatewayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{} ({code})", self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+/// A server-pushed `event` frame.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GatewayEvent {
+    pub event: String,
+    #[serde(default)]
+    pub payload: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_version: Option<Value>,
+}
+
+impl GatewayEvent {
+    pub(crate) fn from_frame(frame: &Value) -> Option<Self> {
+        if frame.get("type").and_then(Value::as_str) != Some("event") {
+            return None;
+        }
+
+        serde_json::from_value(frame.clone()).ok()
+    }
+}
+
+/// Returns true when `name` matches any of `patterns`; an empty list matches
+/// every event. Patterns support `*` (any run of characters) and `?` (one
+/// character).
+pub(crate) fn event_matches(patterns: &[String], name: &str) -> bool {
+    patterns.is_empty() || patterns.iter().any(|pattern| glob_matches(pattern, name))
+}
+
+pub(crate) fn glob_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                n += 1;
+            }
+            Some(expected) if *expected == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    n = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|ch| *ch == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{
+        protocol::{
+            event_matches, glob_matches, protocol_mismatch, GatewayError, GatewayEvent, HelloOk,
+            ProtocolRange,
+        },
+        CliError,
+    };
+
+    #[test]
+    fn protocol_range_fills_missing_bounds_from_current_version() {
+        let range = ProtocolRange::from_bounds(None, Some(2)).expect("range should build");
+        assert_eq!((range.min(), range.max()), (2, 2));
+
+        let range = ProtocolRange::from_bounds(Some(2), None).expect("range should build");
+        assert_eq!((range.min(), range.max()), (2, 3));
+        assert_eq!(range.to_string(), "2-3");
+
+        let range = ProtocolRange::from_bounds(None, None).expect("range should build");
+        assert_eq!(range.to_string(), "3");
+    }
+
+    #[test]
+    fn protocol_range_rejects_inverted_bounds() {
+        let result = ProtocolRange::from_bounds(Some(4), Some(3));
+        assert!(matches!(result, Err(CliError::InvalidOption(_))));
+    }
+
+    #[test]
+    fn protocol_mismatch_reads_gateway_details() {
+        let error = |error| GatewayError::from_frame(&json!({ "error": error }));
+
+        let mismatch = error(json!({
+            "code": "INVALID_REQUEST",
+            "message": "protocol mismatch",
+            "details": { "expectedProtocol": 3 }
+        }));
+        assert_eq!(protocol_mismatch(&mismatch), Some(Some("3".to_owned())));
+
+        let mismatch = error(json!({
+            "code": "INVALID_REQUEST",
+            "details": { "minProtocol": 2, "maxProtocol": 4 }
+        }));
+        assert_eq!(protocol_mismatch(&mismatch), Some(Some("2-4".to_owned())));
+
+        let mismatch = error(json!({ "code": "PROTOCOL_MISMATCH", "message": "no" }));
+        assert_eq!(protocol_mismatch(&mismatch), Some(None));
+        let unknown = CliError::UnsupportedProtocol {
+            client: ProtocolRange::default(),
+            server: None,
+        };
+        assert_eq!(
+            unknown.to_string(),
+            "protocol version mismatch: client offered 3, gateway did not say which versions it supports"
+        );
+
+        let unrelated = error(json!({ "code": "INVALID_REQUEST", "message": "unauthorized" }));
+        assert!(protocol_mismatch(&unrelated).is_none());
+    }
+
+    #[test]
+    fn gateway_error_keeps_code_details_and_retry_hints() {
+        let error = GatewayError::from_frame(&json!({
+            "type": "res",
+            "id": "rpc-1",
+            "ok": false,
+            "error": {
+                "code": "UNAVAILABLE",
+                "message": "gateway draining",
+                "details": { "node": "n-1" },
+                "retryable": true,
+                "retryAfterMs": 2000
+            }
+        }));
+
+        assert_eq!(error.code.as_deref(), Some("UNAVAILABLE"));
+        assert_eq!(error.details["node"], "n-1");
+        assert!(error.retryable);
+        assert_eq!(error.retry_after_ms, Some(2000));
+        assert_eq!(error.to_string(), "gateway draining (UNAVAILABLE)");
+
+        let bare = GatewayError::from_frame(&json!({ "type": "res", "ok": false }));
+        assert_eq!(bare.message, "rpc request failed");
+        assert!(!bare.retryable);
+    }
+
+    #[test]
+    fn hello_ok_parses_server_features_and_policy() {
+        let hello = HelloOk::from_payload(json!({
+            "type": "hello-ok",
+            "protocol": 3,
+            "server": { "version": "2026.2.1", "connId": "c-1" },
+            "features": { "methods": ["health", "status"], "events": ["tick"] },
+            "snapshot": { "presence": [] },
+            "policy": { "maxPayload": 1048576, "tickIntervalMs": 30000 }
+        }))
+        .expect("hello-ok should parse");
+
+        assert_eq!(hello.protocol, Some(3));
+        assert!(hello.auth.is_none());
+        assert_eq!(hello.server.version.as_deref(), Some("2026.2.1"));
+        assert_eq!(hello.server.conn_id.as_deref(), Some("c-1"));
+        assert!(hello.supports_method("status"));
+        assert!(!hello.supports_method("cron.list"));
+        assert!(hello.supports_event("tick"));
+        assert_eq!(hello.policy.max_payload, Some(1_048_576));
+        assert_eq!(hello.snapshot["presence"], json!([]));
+    }
+
+    #[test]
+    fn hello_ok_round_trips_fields_it_does_not_model() {
+        let payload = json!({
+            "protocol": 3,
+            "server": { "version": "2026.2.1", "region": "eu-1" },
+            "features": { "methods": ["status"], "events": [], "streaming": true },
+            "policy": { "maxPayload": 1024, "rateLimit": { "rpm": 600 } },
+            "snapshot": {},
+            "auth": { "role": "operator", "scopes": ["operator.read"], "expiresAtMs": 5 },
+            "canvasHostUrl": "https://canvas.example.com"
+        });
+        let mut frame = payload.clone();
+        frame["type"] = json!("hello-ok");
+
+        let hello = HelloOk::from_payload(frame).expect("hello-ok should parse");
+        assert_eq!(hello.extra["canvasHostUrl"], "https://canvas.example.com");
+        assert_eq!(
+            hello.auth.as_ref().map(|auth| &auth.extra["expiresAtMs"]),
+            Some(&json!(5))
+        );
+        assert_eq!(
+            serde_json::to_value(&hello).expect("hello-ok should serialize"),
+            payload
+        );
+    }
+
+    #[test]
+    fn hello_ok_tolerates_minimal_payload_and_rejects_other_types() {
+        let hello = HelloOk::from_payload(json!({ "type": "hello-ok" }))
+            .expect("minimal hello-ok should parse");
+        assert_eq!(hello, HelloOk::default());
+
+        assert!(HelloOk::from_payload(json!({ "type": "hello-error" })).is_err());
+    }
+
+    #[test]
+    fn glob_matches_wildcards() {
+        assert!(glob_matches("agent", "agent"));
+        assert!(glob_matches("agent.*", "agent.delta"));
+        assert!(glob_matches("*.delta", "chat.delta"));
+        assert!(glob_matches("ch?t", "chat"));
+        assert!(glob_matches("*", ""));
+        assert!(!glob_matches("agent.*", "chat.delta"));
+        assert!(!glob_matches("ch?t", "chaat"));
+    }
+
+    #[test]
+    fn empty_filter_matches_every_event() {
+        assert!(event_matches(&[], "presence"));
+        assert!(event_matches(
+            &["tick".to_owned(), "presence*".to_owned()],
+            "presence"
+        ));
+        assert!(!event_matches(&["tick".to_owned()], "presence"));
+    }
+
+    #[test]
+    fn event_frame_parses_payload_and_sequence() {
+        let event = GatewayEvent::from_frame(&json!({
+            "type": "event",
+            "event": "presence",
+            "payload": { "clients": 2 },
+            "seq": 7
+        }))
+        .expect("event frame should parse");
+
+        assert_eq!(event.event, "presence");
+        assert_eq!(event.payload["clients"], 2);
+        assert_eq!(event.seq, Some(7));
+        assert!(GatewayEvent::from_frame(&json!({ "type": "res", "id": "rpc-1" })).is_none());
+    }
+}
@@ -0,0 +1,550 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, TcpStream, ToSocketAddrs},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+use crate::CliError;
+
+const MAX_CONNECT_RESPONSE: usize = 8 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ProxyKind {
+    /// HTTP `CONNECT` tunnelling.
+    Http,
+    /// SOCKS5; `remote_dns` (`socks5h://`) lets the proxy resolve the
+    /// gateway's host name.
+    Socks5 { remote_dns: bool },
+}
+
+/// A proxy server given as `http://`, `socks5://` or `socks5h://` URL, with
+/// optional `user:password@` credentials.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Proxy {
+    url: String,
+    kind: ProxyKind,
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+}
+
+impl Proxy {
+    pub fn parse(url: &str) -> Result<Self, CliError> {
+        let invalid = |reason: &str| CliError::InvalidOption(format!("proxy `{url}`: {reason}"));
+        let trimmed = url.trim();
+        let (scheme, rest) = trimmed.split_once("://").unwrap_or(("http", trimmed));
+        let (kind, default_port) = match scheme.to_ascii_lowercase().as_str() {
+            "http" => (ProxyKind::Http, 80),
+            "socks5" => (ProxyKind::Socks5 { remote_dns: false }, 1080),
+            "socks5h" => (ProxyKind::Socks5 { remote_dns: true }, 1080),
+            _ => return Err(invalid("scheme must be http, socks5 or socks5h")),
+        };
+
+        let authority = rest.split('/').next().unwrap_or_default();
+        let (userinfo, address) = match authority.rsplit_once('@') {
+            Some((userinfo, address)) => (Some(userinfo), address),
+            None => (None, authority),
+        };
+        let credentials = userinfo.map(|userinfo| {
+            let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+            (percent_decode(user), percent_decode(password))
+        });
+
+        let (host, port) = match address.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, after) = bracketed
+                    .split_once(']')
+                    .ok_or_else(|| invalid("unterminated IPv6 address"))?;
+                (host, after.strip_prefix(':'))
+            }
+            None => match address.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (address, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid("missing host"));
+        }
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid("invalid port"))?,
+            None => default_port,
+        };
+
+        Ok(Self {
+            url: format!("{scheme}://{rest}"),
+            kind,
+            host: host.to_owned(),
+            port,
+            credentials,
+        })
+    }
+
+    /// The proxy URL as given, for HTTP clients that take one.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub(crate) fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub(crate) fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Asks the proxy on the other end of `stream` to open a tunnel to
+    /// `host:port`. Afterwards the stream carries the tunnelled bytes.
+    pub(crate) fn tunnel(&self, stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+        match self.kind {
+            ProxyKind::Http => self.http_connect(stream, host, port),
+            ProxyKind::Socks5 { remote_dns } => self.socks5_connect(stream, host, port, remote_dns),
+        }
+    }
+
+    fn http_connect(&self, stream: &mut TcpStream, host: &str, port: u16) -> io::Result<()> {
+        let authority = if host.contains(':') {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        };
+        let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+        if let Some((user, password)) = &self.credentials {
+            let token = STANDARD.encode(format!("{user}:{password}"));
+            request.push_str(&format!("Proxy-Authorization: Basic {token}\r\n"));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes())?;
+
+        // Read byte by byte so nothing past the header belongs to us.
+        let mut response = Vec::new();
+        let mut byte = [0_u8; 1];
+        while !response.ends_with(b"\r\n\r\n") {
+            if response.len() >= MAX_CONNECT_RESPONSE {
+                return Err(io::Error::other("proxy sent an oversized CONNECT response"));
+            }
+            stream.read_exact(&mut byte)?;
+            response.push(byte[0]);
+        }
+
+        let status_line = String::from_utf8_lossy(&response);
+        let status_line = status_line.lines().next().unwrap_or_default();
+        match status_line.split_whitespace().nth(1) {
+            Some(status) if status.starts_with('2') => Ok(()),
+            Some("407") => Err(io::Error::other(
+                "proxy requires authentication (407); put user:password@ in the proxy URL",
+            )),
+            _ => Err(io::Error::other(format!(
+                "proxy refused CONNECT to {authority}: {status_line}"
+            ))),
+        }
+    }
+
+    fn socks5_connect(
+        &self,
+        stream: &mut TcpStream,
+        host: &str,
+        port: u16,
+        remote_dns: bool,
+    ) -> io::Result<()> {
+        const NO_AUTH: u8 = 0x00;
+        const USER_PASSWORD: u8 = 0x02;
+
+        let greeting: &[u8] = if self.credentials.is_some() {
+            &[0x05, 2, NO_AUTH, USER_PASSWORD]
+        } else {
+            &[0x05, 1, NO_AUTH]
+        };
+        stream.write_all(greeting)?;
+        let mut choice = [0_u8; 2];
+        stream.read_exact(&mut choice)?;
+        match (choice, &self.credentials) {
+            ([0x05, NO_AUTH], _) => {}
+            ([0x05, USER_PASSWORD], Some((user, password))) => {
+                let mut auth = vec![0x01, field_len(user)?];
+                auth.extend_from_slice(user.as_bytes());
+                auth.push(field_len(password)?);
+                auth.extend_from_slice(password.as_bytes());
+                stream.write_all(&auth)?;
+                let mut status = [0_u8; 2];
+                stream.read_exact(&mut status)?;
+                if status[1] != 0 {
+                    return Err(io::Error::other("SOCKS5 proxy rejected the credentials"));
+                }
+            }
+            _ => {
+                return Err(io::Error::other(
+                    "SOCKS5 proxy accepted none of the offered authentication methods",
+                ))
+            }
+        }
+
+        let mut request = vec![0x05, 0x01, 0x00];
+        let address = match host.parse::<IpAddr>() {
+            Ok(address) => Some(address),
+            Err(_) if remote_dns => None,
+            Err(_) => Some(
+                (host, port)
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| io::Error::other(format!("no addresses found for {host}")))?
+                    .ip(),
+            ),
+        };
+        match address {
+            Some(IpAddr::V4(address)) => {
+                request.push(0x01);
+                request.extend_from_slice(&address.octets());
+            }
+            Some(IpAddr::V6(address)) => {
+                request.push(0x04);
+                request.extend_from_slice(&address.octets());
+            }
+            None => {
+                request.extend_from_slice(&[0x03, field_len(host)?]);
+                request.extend_from_slice(host.as_bytes());
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request)?;
+
+        let mut reply = [0_u8; 4];
+        stream.read_exact(&mut reply)?;
+        if reply[1] != 0 {
+            return Err(io::Error::other(format!(
+                "SOCKS5 proxy could not connect to {host}:{port}: {}",
+                socks5_reply(reply[1])
+            )));
+        }
+        let bound_len = match reply[3] {
+            0x01 => 4,
+            0x04 => 16,
+            0x03 => {
+                let mut len = [0_u8; 1];
+                stream.read_exact(&mut len)?;
+                usize::from(len[0])
+            }
+            other => {
+                return Err(io::Error::other(format!(
+                    "SOCKS5 proxy sent unknown address type {other}"
+                )))
+            }
+        };
+        let mut bound = vec![0_u8; bound_len + 2];
+        stream.read_exact(&mut bound)
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("kind", &self.kind)
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Where a route's proxy comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ProxySource {
+    /// `--proxy`, checked when it was given.
+    Given(Proxy),
+    /// A variable's URL, parsed only once a connection takes the route.
+    Env { name: &'static str, url: String },
+}
+
+/// Which proxy, if any, each kind of gateway URL goes through.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProxySettings {
+    /// Used for `http://` gateways.
+    http: Option<ProxySource>,
+    /// Used for `https://` gateways.
+    https: Option<ProxySource>,
+    /// Hosts reached directly: `*`, exact names or addresses, and domain
+    /// suffixes such as `.internal` or `internal`.
+    pub no_proxy: Vec<String>,
+}
+
+impl ProxySettings {
+    /// Reads `HTTPS_PROXY`, `http_proxy`, `ALL_PROXY` and `NO_PROXY`, each
+    /// also in the other case except `HTTP_PROXY`, which curl ignores too.
+    /// The URLs are only parsed by [`ProxySettings::route`].
+    pub fn from_env(env: impl Fn(&str) -> Option<String>) -> Self {
+        let var = |names: &[&'static str]| {
+            names.iter().find_map(|name| {
+                env(name)
+                    .filter(|value| !value.trim().is_empty())
+                    .map(|url| (*name, url))
+            })
+        };
+        let proxy =
+            |names: &[&'static str]| var(names).map(|(name, url)| ProxySource::Env { name, url });
+
+        Self {
+            http: proxy(&["http_proxy", "ALL_PROXY", "all_proxy"]),
+            https: proxy(&["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]),
+            no_proxy: var(&["NO_PROXY", "no_proxy"])
+                .map(|(_, hosts)| {
+                    hosts
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|host| !host.is_empty())
+                        .map(str::to_owned)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Sends every gateway URL through `proxy`, keeping `no_proxy`.
+    pub fn with_proxy(self, proxy: Proxy) -> Self {
+        Self {
+            http: Some(ProxySource::Given(proxy.clone())),
+            https: Some(ProxySource::Given(proxy)),
+            no_proxy: self.no_proxy,
+        }
+    }
+
+    /// Why each proxy variable that [`ProxySettings::route`] would skip
+    /// cannot be used, for the caller to report once.
+    pub fn warnings(&self) -> Vec<String> {
+        let mut warnings = Vec::new();
+        for source in [&self.http, &self.https].into_iter().flatten() {
+            if let ProxySource::Env { name, url } = source {
+                if let Err(error) = Proxy::parse(url) {
+                    let warning = format!("ignoring {name}: {error}");
+                    if !warnings.contains(&warning) {
+                        warnings.push(warning);
+                    }
+                }
+            }
+        }
+        warnings
+    }
+
+    /// The proxy for a gateway at `host`, over TLS when `secure`. A variable
+    /// holding a URL that cannot be used is skipped, as the setting may be
+    /// meant for other programs; see [`ProxySettings::warnings`].
+    pub(crate) fn route(&self, host: &str, secure: bool) -> Option<Proxy> {
+        let source = if secure { &self.https } else { &self.http };
+        let source = source.as_ref()?;
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if self
+            .no_proxy
+            .iter()
+            .any(|entry| no_proxy_matches(entry, host))
+        {
+            return None;
+        }
+        match source {
+            ProxySource::Given(proxy) => Some(proxy.clone()),
+            ProxySource::Env { url, .. } => Proxy::parse(url).ok(),
+        }
+    }
+}
+
+/// Whether a `NO_PROXY` entry covers `host`. Ports in entries are ignored.
+fn no_proxy_matches(entry: &str, host: &str) -> bool {
+    let entry = match entry.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or_default(),
+        None if entry.matches(':').count() == 1 => entry.split(':').next().unwrap_or_default(),
+        None => entry,
+    };
+    if entry == "*" {
+        return true;
+    }
+
+    let domain = entry.trim_start_matches('*').trim_start_matches('.');
+    let (host, domain) = (host.as_bytes(), domain.as_bytes());
+    host.eq_ignore_ascii_case(domain)
+        || (host.len() > domain.len()
+            && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+            && host[host.len() - domain.len() - 1] == b'.')
+}
+
+fn field_len(value: &str) -> io::Result<u8> {
+    u8::try_from(value.len())
+        .map_err(|_| io::Error::other("SOCKS5 fields are limited to 255 bytes"))
+}
+
+fn socks5_reply(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| text.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use crate::{CliError, Proxy, ProxySettings};
+
+    #[test]
+    fn proxy_urls_and_environment_are_parsed() {
+        let proxy = Proxy::parse("socks5h://user:p%40ss@[::1]:9050").expect("proxy should parse");
+        assert_eq!(proxy.host(), "::1");
+        assert_eq!(proxy.port(), 9050);
+        assert_eq!(
+            proxy.credentials,
+            Some(("user".to_owned(), "p@ss".to_owned()))
+        );
+        assert_eq!(
+            Proxy::parse("proxy.corp:3128")
+                .expect("bare proxy should parse")
+                .url(),
+            "http://proxy.corp:3128"
+        );
+        assert!(matches!(
+            Proxy::parse("ftp://proxy.corp"),
+            Err(CliError::InvalidOption(_))
+        ));
+
+        let settings = ProxySettings::from_env(|name| match name {
+            "HTTPS_PROXY" => Some("http://secure.corp:3128".to_owned()),
+            "ALL_PROXY" => Some("socks5://all.corp".to_owned()),
+            "NO_PROXY" => Some("localhost, .internal,10.0.0.1:8443".to_owned()),
+            _ => None,
+        });
+        let host = |proxy: Option<Proxy>| proxy.map(|proxy| proxy.host().to_owned());
+        assert_eq!(
+            host(settings.route("gateway.example.com", true)).as_deref(),
+            Some("secure.corp")
+        );
+        assert_eq!(
+            host(settings.route("gateway.example.com", false)).as_deref(),
+            Some("all.corp")
+        );
+        assert!(settings.route("LOCALHOST", true).is_none());
+        assert!(settings.route("gw.internal", true).is_none());
+        assert!(settings.route("internal", true).is_none());
+        assert!(settings.route("10.0.0.1", true).is_none());
+        assert!(settings.route("notinternal", true).is_some());
+
+        // An unusable variable only matters to the route that reads it, and
+        // there it is skipped rather than failing the command.
+        let settings = ProxySettings::from_env(|name| match name {
+            "HTTPS_PROXY" => Some("https://proxy.corp:3128".to_owned()),
+            "http_proxy" => Some("http://plain.corp:3128".to_owned()),
+            _ => None,
+        });
+        assert!(settings.route("gateway.example.com", true).is_none());
+        assert_eq!(
+            host(settings.route("gateway.example.com", false)).as_deref(),
+            Some("plain.corp")
+        );
+        assert_eq!(settings.warnings().len(), 1);
+        assert!(settings.warnings()[0].starts_with("ignoring HTTPS_PROXY: "));
+
+        // ALL_PROXY backs both routes but is reported once.
+        let settings = ProxySettings::from_env(|name| match name {
+            "ALL_PROXY" => Some("socks4://all.corp".to_owned()),
+            _ => None,
+        });
+        assert_eq!(settings.warnings().len(), 1);
+        assert!(ProxySettings::default().warnings().is_empty());
+    }
+
+    #[test]
+    fn http_connect_and_socks5_open_tunnels() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            {
+                // HTTP CONNECT with basic auth.
+                let (mut stream, _) = listener.accept().expect("connection should arrive");
+                let mut request = Vec::new();
+                let mut byte = [0_u8; 1];
+                while !request.ends_with(b"\r\n\r\n") {
+                    stream.read_exact(&mut byte).expect("request should arrive");
+                    request.push(byte[0]);
+                }
+                let request = String::from_utf8(request).expect("request should be text");
+                assert!(request.starts_with("CONNECT gateway.test:443 HTTP/1.1\r\n"));
+                assert!(request.contains("Proxy-Authorization: Basic dXNlcjpwYXNz\r\n"));
+                stream
+                    .write_all(b"HTTP/1.1 200 Connection established\r\n\r\ntunnelled")
+                    .expect("response should be sent");
+            }
+            {
+                // SOCKS5 with the host name resolved by the proxy.
+                let (mut stream, _) = listener.accept().expect("connection should arrive");
+                let mut greeting = [0_u8; 3];
+                stream.read_exact(&mut greeting).expect("greeting");
+                assert_eq!(greeting, [0x05, 1, 0x00]);
+                stream.write_all(&[0x05, 0x00]).expect("method choice");
+                let mut request = [0_u8; 5 + 12 + 2];
+                stream.read_exact(&mut request).expect("connect request");
+                assert_eq!(&request[..5], &[0x05, 0x01, 0x00, 0x03, 12]);
+                assert_eq!(&request[5..17], b"gateway.test");
+                assert_eq!(&request[17..], &18789_u16.to_be_bytes());
+                stream
+                    .write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 80])
+                    .expect("reply");
+                stream.write_all(b"socks").expect("payload");
+            }
+        });
+
+        let http = Proxy::parse(&format!("http://user:pass@{addr}")).expect("proxy should parse");
+        let mut stream = TcpStream::connect(addr).expect("proxy should accept");
+        http.tunnel(&mut stream, "gateway.test", 443)
+            .expect("CONNECT should succeed");
+        let mut payload = String::new();
+        stream
+            .read_to_string(&mut payload)
+            .expect("tunnel should carry data");
+        assert_eq!(payload, "tunnelled");
+
+        let socks = Proxy::parse(&format!("socks5h://{addr}")).expect("proxy should parse");
+        let mut stream = TcpStream::connect(addr).expect("proxy should accept");
+        socks
+            .tunnel(&mut stream, "gateway.test", 18789)
+            .expect("SOCKS5 connect should succeed");
+        let mut payload = String::new();
+        stream
+            .read_to_string(&mut payload)
+            .expect("tunnel should carry data");
+        assert_eq!(payload, "socks");
+
+        server.join().expect("proxy stand-in should finish");
+    }
+}
@@ -0,0 +1,1178 @@
+use std::{cmp::Ordering, fmt};
+
+use serde_json::{Map, Value};
+
+use crate::CliError;
+
+/// A `--query` expression: JMESPath (<https://jmespath.org/specification.html>)
+/// with the standard operators and most of its built-in functions.
+#[derive(Debug, Clone)]
+pub struct Query {
+    ast: Node,
+}
+
+impl Query {
+    pub fn parse(expression: &str) -> Result<Self, CliError> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            position: 0,
+        };
+        if *parser.current() == Token::Eof {
+            return Err(CliError::Query("empty expression".to_owned()));
+        }
+        let ast = parser.expression(0)?;
+        match parser.current() {
+            Token::Eof => Ok(Self { ast }),
+            token => Err(unexpected(token)),
+        }
+    }
+
+    /// Evaluates the expression against `value`; missing keys and indexes
+    /// yield `null` rather than an error.
+    pub fn search(&self, value: &Value) -> Result<Value, CliError> {
+        eval(&self.ast, value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparator {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Identifier(String),
+    QuotedIdentifier(String),
+    Literal(Value),
+    Number(i64),
+    Dot,
+    Star,
+    Flatten,
+    Filter,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    LParen,
+    RParen,
+    Comma,
+    Colon,
+    Current,
+    Ampersand,
+    Pipe,
+    Or,
+    And,
+    Not,
+    Compare(Comparator),
+    Eof,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            Self::Identifier(name) => return write!(f, "`{name}`"),
+            Self::QuotedIdentifier(name) => return write!(f, "`{}`", Value::from(name.as_str())),
+            Self::Literal(value) => return write!(f, "literal `{value}`"),
+            Self::Number(number) => return write!(f, "`{number}`"),
+            Self::Dot => "`.`",
+            Self::Star => "`*`",
+            Self::Flatten => "`[]`",
+            Self::Filter => "`[?`",
+            Self::LBracket => "`[`",
+            Self::RBracket => "`]`",
+            Self::LBrace => "`{`",
+            Self::RBrace => "`}`",
+            Self::LParen => "`(`",
+            Self::RParen => "`)`",
+            Self::Comma => "`,`",
+            Self::Colon => "`:`",
+            Self::Current => "`@`",
+            Self::Ampersand => "`&`",
+            Self::Pipe => "`|`",
+            Self::Or => "`||`",
+            Self::And => "`&&`",
+            Self::Not => "`!`",
+            Self::Compare(Comparator::Eq) => "`==`",
+            Self::Compare(Comparator::Ne) => "`!=`",
+            Self::Compare(Comparator::Lt) => "`<`",
+            Self::Compare(Comparator::Le) => "`<=`",
+            Self::Compare(Comparator::Gt) => "`>`",
+            Self::Compare(Comparator::Ge) => "`>=`",
+            Self::Eof => "end of expression",
+        };
+        f.write_str(text)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Identity,
+    Field(String),
+    Literal(Value),
+    Index(i64),
+    Slice([Option<i64>; 3]),
+    /// `left.right`, `left | right` and `left[index]`: `right` applied to
+    /// the result of `left`.
+    Chain(Box<Node>, Box<Node>),
+    /// `right` applied to each element of the array `left`, dropping nulls.
+    Projection(Box<Node>, Box<Node>),
+    /// `right` applied to each value of the object `left`, dropping nulls.
+    ValueProjection(Box<Node>, Box<Node>),
+    /// A projection over the elements of `left` for which the condition holds.
+    Filter {
+        left: Box<Node>,
+        right: Box<Node>,
+        condition: Box<Node>,
+    },
+    Flatten(Box<Node>),
+    Compare(Comparator, Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Not(Box<Node>),
+    List(Vec<Node>),
+    Hash(Vec<(String, Node)>),
+    Function(String, Vec<Node>),
+    ExpressionRef(Box<Node>),
+}
+
+fn unexpected(token: &Token) -> CliError {
+    CliError::Query(format!("unexpected {token}"))
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, CliError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    while let Some(&ch) = chars.get(index) {
+        let next = chars.get(index + 1).copied();
+        index += 1;
+        let token = match (ch, next) {
+            (' ' | '\t' | '\r' | '\n', _) => continue,
+            ('.', _) => Token::Dot,
+            ('*', _) => Token::Star,
+            (',', _) => Token::Comma,
+            (':', _) => Token::Colon,
+            ('@', _) => Token::Current,
+            (']', _) => Token::RBracket,
+            ('{', _) => Token::LBrace,
+            ('}', _) => Token::RBrace,
+            ('(', _) => Token::LParen,
+            (')', _) => Token::RParen,
+            ('[', Some(']')) => {
+                index += 1;
+                Token::Flatten
+            }
+            ('[', Some('?')) => {
+                index += 1;
+                Token::Filter
+            }
+            ('[', _) => Token::LBracket,
+            ('|', Some('|')) => {
+                index += 1;
+                Token::Or
+            }
+            ('|', _) => Token::Pipe,
+            ('&', Some('&')) => {
+                index += 1;
+                Token::And
+            }
+            ('&', _) => Token::Ampersand,
+            ('!', Some('=')) => {
+                index += 1;
+                Token::Compare(Comparator::Ne)
+            }
+            ('!', _) => Token::Not,
+            ('=', Some('=')) => {
+                index += 1;
+                Token::Compare(Comparator::Eq)
+            }
+            ('<', Some('=')) => {
+                index += 1;
+                Token::Compare(Comparator::Le)
+            }
+            ('<', _) => Token::Compare(Comparator::Lt),
+            ('>', Some('=')) => {
+                index += 1;
+                Token::Compare(Comparator::Ge)
+            }
+            ('>', _) => Token::Compare(Comparator::Gt),
+            ('"', _) => {
+                let text = delimited(&chars, &mut index, '"')?;
+                let name = serde_json::from_str(&format!("\"{text}\"")).map_err(|error| {
+                    CliError::Query(format!("invalid quoted identifier \"{text}\": {error}"))
+                })?;
+                Token::QuotedIdentifier(name)
+            }
+            ('\'', _) => {
+                let text = delimited(&chars, &mut index, '\'')?;
+                Token::Literal(Value::String(raw_string(&text)))
+            }
+            ('`', _) => {
+                let text = delimited(&chars, &mut index, '`')?.replace("\\`", "`");
+                let value = serde_json::from_str(text.trim()).map_err(|error| {
+                    CliError::Query(format!("invalid JSON literal `{text}`: {error}"))
+                })?;
+                Token::Literal(value)
+            }
+            ('-' | '0'..='9', _) => {
+                let start = index - 1;
+                while chars.get(index).is_some_and(char::is_ascii_digit) {
+                    index += 1;
+                }
+                let text: String = chars[start..index].iter().collect();
+                Token::Number(
+                    text.parse()
+                        .map_err(|_| CliError::Query(format!("invalid number `{text}`")))?,
+                )
+            }
+            (ch, _) if ch.is_ascii_alphabetic() || ch == '_' => {
+                let start = index - 1;
+                while chars
+                    .get(index)
+                    .is_some_and(|ch| ch.is_ascii_alphanumeric() || *ch == '_')
+                {
+                    index += 1;
+                }
+                Token::Identifier(chars[start..index].iter().collect())
+            }
+            (ch, _) => return Err(CliError::Query(format!("unexpected character `{ch}`"))),
+        };
+        tokens.push(token);
+    }
+    tokens.push(Token::Eof);
+    Ok(tokens)
+}
+
+/// The text up to the next unescaped `delimiter`, leaving `index` after it.
+/// Escapes are kept for the caller to interpret.
+fn delimited(chars: &[char], index: &mut usize, delimiter: char) -> Result<String, CliError> {
+    let start = *index;
+    while let Some(&ch) = chars.get(*index) {
+        if ch == delimiter {
+            let text = chars[start..*index].iter().collect();
+            *index += 1;
+            return Ok(text);
+        }
+        *index += if ch == '\\' { 2 } else { 1 };
+    }
+    Err(CliError::Query(format!("unterminated {delimiter}")))
+}
+
+/// A raw string's value: `\'` and `\\` are escapes, and any other
+/// backslash is kept as it is.
+fn raw_string(text: &str) -> String {
+    let mut value = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.peek()) {
+            ('\\', Some(&escaped @ ('\'' | '\\'))) => {
+                value.push(escaped);
+                chars.next();
+            }
+            _ => value.push(ch),
+        }
+    }
+    value
+}
+
+/// How tightly each token binds to the expression on its left, as in the
+/// reference JMESPath implementation.
+fn binding_power(token: &Token) -> u8 {
+    match token {
+        Token::Pipe => 1,
+        Token::Or => 2,
+        Token::And => 3,
+        Token::Compare(_) => 5,
+        Token::Flatten => 9,
+        Token::Star => 20,
+        Token::Filter => 21,
+        Token::Dot => 40,
+        Token::Not => 45,
+        Token::LBrace => 50,
+        Token::LBracket => 55,
+        Token::LParen => 60,
+        _ => 0,
+    }
+}
+
+/// Binding powers below this end a projection's right-hand side.
+const PROJECTION_STOP: u8 = 10;
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn current(&self) -> &Token {
+        self.peek(0)
+    }
+
+    fn peek(&self, offset: usize) -> &Token {
+        self.tokens
+            .get(self.position + offset)
+            .unwrap_or(&Token::Eof)
+    }
+
+    fn advance(&mut self) -> Token {
+        let token = self.current().clone();
+        self.position = (self.position + 1).min(self.tokens.len() - 1);
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), CliError> {
+        if *self.current() == expected {
+            self.advance();
+            Ok(())
+        } else {
+            Err(CliError::Query(format!(
+                "expected {expected}, found {}",
+                self.current()
+            )))
+        }
+    }
+
+    fn expression(&mut self, binding: u8) -> Result<Node, CliError> {
+        let token = self.advance();
+        let mut left = self.prefix(token)?;
+        while binding < binding_power(self.current()) {
+            let token = self.advance();
+            left = self.infix(token, left)?;
+        }
+        Ok(left)
+    }
+
+    fn prefix(&mut self, token: Token) -> Result<Node, CliError> {
+        Ok(match token {
+            Token::Literal(value) => Node::Literal(value),
+            Token::Identifier(name) => Node::Field(name),
+            Token::QuotedIdentifier(name) => {
+                if *self.current() == Token::LParen {
+                    return Err(CliError::Query(
+                        "function names cannot be quoted".to_owned(),
+                    ));
+                }
+                Node::Field(name)
+            }
+            Token::Star => {
+                let right = if *self.current() == Token::RBracket {
+                    Node::Identity
+                } else {
+                    self.projection_rhs(binding_power(&Token::Star))?
+                };
+                Node::ValueProjection(Box::new(Node::Identity), Box::new(right))
+            }
+            Token::Filter => self.filter(Node::Identity)?,
+            Token::LBrace => self.multi_select_hash()?,
+            Token::LParen => {
+                let inner = self.expression(0)?;
+                self.expect(Token::RParen)?;
+                inner
+            }
+            Token::Flatten => {
+                let right = self.projection_rhs(binding_power(&Token::Flatten))?;
+                Node::Projection(
+                    Box::new(Node::Flatten(Box::new(Node::Identity))),
+                    Box::new(right),
+                )
+            }
+            Token::Not => Node::Not(Box::new(self.expression(binding_power(&Token::Not))?)),
+            Token::LBracket => match self.current() {
+                Token::Number(_) | Token::Colon => {
+                    let right = self.index()?;
+                    self.project_if_slice(Node::Identity, right)?
+                }
+                Token::Star if *self.peek(1) == Token::RBracket => {
+                    self.advance();
+                    self.advance();
+                    let right = self.projection_rhs(binding_power(&Token::Star))?;
+                    Node::Projection(Box::new(Node::Identity), Box::new(right))
+                }
+                _ => self.multi_select_list()?,
+            },
+            Token::Current => Node::Identity,
+            Token::Ampersand => Node::ExpressionRef(Box::new(self.expression(0)?)),
+            token => return Err(unexpected(&token)),
+        })
+    }
+
+    fn infix(&mut self, token: Token, left: Node) -> Result<Node, CliError> {
+        let left = Box::new(left);
+        Ok(match token {
+            Token::Dot if *self.current() == Token::Star => {
+                self.advance();
+                let right = self.projection_rhs(binding_power(&Token::Dot))?;
+                Node::ValueProjection(left, Box::new(right))
+            }
+            Token::Dot => Node::Chain(left, Box::new(self.dot_rhs(binding_power(&Token::Dot))?)),
+            Token::Pipe => Node::Chain(left, Box::new(self.expression(binding_power(&token))?)),
+            Token::Or => Node::Or(left, Box::new(self.expression(binding_power(&token))?)),
+            Token::And => Node::And(left, Box::new(self.expression(binding_power(&token))?)),
+            Token::Compare(comparator) => Node::Compare(
+                comparator,
+                left,
+                Box::new(self.expression(binding_power(&token))?),
+            ),
+            Token::LParen => {
+                let Node::Field(name) = *left else {
+                    return Err(CliError::Query(
+                        "only a function name can be called".to_owned(),
+                    ));
+                };
+                let mut arguments = Vec::new();
+                if *self.current() != Token::RParen {
+                    loop {
+                        arguments.push(self.expression(0)?);
+                        if *self.current() == Token::RParen {
+                            break;
+                        }
+                        self.expect(Token::Comma)?;
+                    }
+                }
+                self.advance();
+                Node::Function(name, arguments)
+            }
+            Token::Filter => self.filter(*left)?,
+            Token::Flatten => {
+                let right = self.projection_rhs(binding_power(&Token::Flatten))?;
+                Node::Projection(Box::new(Node::Flatten(left)), Box::new(right))
+            }
+            Token::LBracket => match self.current() {
+                Token::Number(_) | Token::Colon => {
+                    let right = self.index()?;
+                    self.project_if_slice(*left, right)?
+                }
+                _ => {
+                    self.expect(Token::Star)?;
+                    self.expect(Token::RBracket)?;
+                    let right = self.projection_rhs(binding_power(&Token::Star))?;
+                    Node::Projection(left, Box::new(right))
+                }
+            },
+            token => return Err(unexpected(&token)),
+        })
+    }
+
+    /// `[number]` or a `[start:stop:step]` slice, after the `[`.
+    fn index(&mut self) -> Result<Node, CliError> {
+        if *self.current() != Token::Colon && *self.peek(1) != Token::Colon {
+            let Token::Number(index) = self.advance() else {
+                unreachable!("index() is only called before a number or colon");
+            };
+            self.expect(Token::RBracket)?;
+            return Ok(Node::Index(index));
+        }
+
+        let mut parts = [None; 3];
+        let mut part = 0;
+        loop {
+            match self.advance() {
+                Token::RBracket => break,
+                Token::Colon if part < 2 => part += 1,
+                Token::Number(number) if parts[part].is_none() => parts[part] = Some(number),
+                token => return Err(unexpected(&token)),
+            }
+        }
+        if parts[2] == Some(0) {
+            return Err(CliError::Query("slice step cannot be 0".to_owned()));
+        }
+        Ok(Node::Slice(parts))
+    }
+
+    fn project_if_slice(&mut self, left: Node, right: Node) -> Result<Node, CliError> {
+        let is_slice = matches!(right, Node::Slice(_));
+        let indexed = Node::Chain(Box::new(left), Box::new(right));
+        if !is_slice {
+            return Ok(indexed);
+        }
+        let right = self.projection_rhs(binding_power(&Token::Star))?;
+        Ok(Node::Projection(Box::new(indexed), Box::new(right)))
+    }
+
+    fn filter(&mut self, left: Node) -> Result<Node, CliError> {
+        let condition = self.expression(0)?;
+        self.expect(Token::RBracket)?;
+        let right = if *self.current() == Token::Flatten {
+            Node::Identity
+        } else {
+            self.projection_rhs(binding_power(&Token::Filter))?
+        };
+        Ok(Node::Filter {
+            left: Box::new(left),
+            right: Box::new(right),
+            condition: Box::new(condition),
+        })
+    }
+
+    fn projection_rhs(&mut self, binding: u8) -> Result<Node, CliError> {
+        if binding_power(self.current()) < PROJECTION_STOP {
+            return Ok(Node::Identity);
+        }
+        match self.current() {
+            Token::LBracket | Token::Filter => self.expression(binding),
+            Token::Dot => {
+                self.advance();
+                self.dot_rhs(binding)
+            }
+            token => Err(unexpected(token)),
+        }
+    }
+
+    fn dot_rhs(&mut self, binding: u8) -> Result<Node, CliError> {
+        match self.current() {
+            Token::Identifier(_) | Token::QuotedIdentifier(_) | Token::Star => {
+                self.expression(binding)
+            }
+            Token::LBracket => {
+                self.advance();
+                self.multi_select_list()
+            }
+            Token::LBrace => {
+                self.advance();
+                self.multi_select_hash()
+            }
+            token => Err(unexpected(token)),
+        }
+    }
+
+    /// `[a, b]`, after the `[`.
+    fn multi_select_list(&mut self) -> Result<Node, CliError> {
+        let mut items = Vec::new();
+        loop {
+            items.push(self.expression(0)?);
+            if *self.current() == Token::RBracket {
+                self.advance();
+                return Ok(Node::List(items));
+            }
+            self.expect(Token::Comma)?;
+        }
+    }
+
+    /// `{key: a, other: b}`, after the `{`.
+    fn multi_select_hash(&mut self) -> Result<Node, CliError> {
+        let mut pairs = Vec::new();
+        loop {
+            let key = match self.advance() {
+                Token::Identifier(key) | Token::QuotedIdentifier(key) => key,
+                token => return Err(unexpected(&token)),
+            };
+            self.expect(Token::Colon)?;
+            pairs.push((key, self.expression(0)?));
+            match self.advance() {
+                Token::Comma => {}
+                Token::RBrace => return Ok(Node::Hash(pairs)),
+                token => return Err(unexpected(&token)),
+            }
+        }
+    }
+}
+
+fn eval(node: &Node, value: &Value) -> Result<Value, CliError> {
+    Ok(match node {
+        Node::Identity => value.clone(),
+        Node::Field(name) => value
+            .as_object()
+            .and_then(|map| map.get(name))
+            .cloned()
+            .unwrap_or(Value::Null),
+        Node::Literal(literal) => literal.clone(),
+        Node::Index(index) => match value {
+            Value::Array(items) => {
+                let index = if *index < 0 {
+                    i64::try_from(items.len()).unwrap_or(i64::MAX) + index
+                } else {
+                    *index
+                };
+                usize::try_from(index)
+                    .ok()
+                    .and_then(|index| items.get(index))
+                    .cloned()
+                    .unwrap_or(Value::Null)
+            }
+            _ => Value::Null,
+        },
+        Node::Slice(parts) => match value {
+            Value::Array(items) => Value::Array(slice(items, *parts)),
+            _ => Value::Null,
+        },
+        Node::Chain(left, right) => eval(right, &eval(left, value)?)?,
+        Node::Projection(left, right) => match eval(left, value)? {
+            Value::Array(items) => project(items.iter(), right)?,
+            _ => Value::Null,
+        },
+        Node::ValueProjection(left, right) => match eval(left, value)? {
+            Value::Object(map) => project(map.values(), right)?,
+            _ => Value::Null,
+        },
+        Node::Filter {
+            left,
+            right,
+            condition,
+        } => match eval(left, value)? {
+            Value::Array(items) => {
+                let mut kept = Vec::new();
+                for item in items {
+                    if is_truthy(&eval(condition, &item)?) {
+                        kept.push(item);
+                    }
+                }
+                project(kept.iter(), right)?
+            }
+            _ => Value::Null,
+        },
+        Node::Flatten(inner) => match eval(inner, value)? {
+            Value::Array(items) => Value::Array(
+                items
+                    .into_iter()
+                    .flat_map(|item| match item {
+                        Value::Array(nested) => nested,
+                        item => vec![item],
+                    })
+                    .collect(),
+            ),
+            _ => Value::Null,
+        },
+        Node::Compare(comparator, left, right) => {
+            compare(*comparator, &eval(left, value)?, &eval(right, value)?)
+        }
+        Node::Or(left, right) => {
+            let left = eval(left, value)?;
+            if is_truthy(&left) {
+                left
+            } else {
+                eval(right, value)?
+            }
+        }
+        Node::And(left, right) => {
+            let left = eval(left, value)?;
+            if is_truthy(&left) {
+                eval(right, value)?
+            } else {
+                left
+            }
+        }
+        Node::Not(inner) => Value::Bool(!is_truthy(&eval(inner, value)?)),
+        Node::List(_) | Node::Hash(_) if value.is_null() => Value::Null,
+        Node::List(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| eval(item, value))
+                .collect::<Result<_, _>>()?,
+        ),
+        Node::Hash(pairs) => Value::Object(
+            pairs
+                .iter()
+                .map(|(key, item)| Ok((key.clone(), eval(item, value)?)))
+                .collect::<Result<Map<_, _>, CliError>>()?,
+        ),
+        Node::Function(name, arguments) => call(name, arguments, value)?,
+        Node::ExpressionRef(_) => {
+            return Err(CliError::Query(
+                "`&expression` is only allowed as a function argument".to_owned(),
+            ))
+        }
+    })
+}
+
+fn project<'a>(items: impl Iterator<Item = &'a Value>, right: &Node) -> Result<Value, CliError> {
+    let mut projected = Vec::new();
+    for item in items {
+        let value = eval(right, item)?;
+        if !value.is_null() {
+            projected.push(value);
+        }
+    }
+    Ok(Value::Array(projected))
+}
+
+/// Python-style slicing; the parser has already rejected a step of 0.
+fn slice(items: &[Value], [start, stop, step]: [Option<i64>; 3]) -> Vec<Value> {
+    let step = step.unwrap_or(1);
+    let len = i64::try_from(items.len()).unwrap_or(i64::MAX);
+    let bound = |index: Option<i64>, default: i64| match index {
+        None => default,
+        Some(index) if index < 0 => (len + index).max(if step < 0 { -1 } else { 0 }),
+        Some(index) => index.min(if step < 0 { len - 1 } else { len }),
+    };
+    let (mut index, stop) = if step > 0 {
+        (bound(start, 0), bound(stop, len))
+    } else {
+        (bound(start, len - 1), bound(stop, -1))
+    };
+
+    let mut sliced = Vec::new();
+    while (step > 0 && index < stop) || (step < 0 && index > stop) {
+        if let Some(item) = usize::try_from(index)
+            .ok()
+            .and_then(|index| items.get(index))
+        {
+            sliced.push(item.clone());
+        }
+        // A step past the end of `i64` ends the slice like one past `stop`.
+        let Some(next) = index.checked_add(step) else {
+            break;
+        };
+        index = next;
+    }
+    sliced
+}
+
+/// JMESPath truthiness: `null`, `false` and empty strings, arrays and
+/// objects are false.
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(flag) => *flag,
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+        Value::Number(_) => true,
+    }
+}
+
+/// Equality that treats `1` and `1.0` as the same number.
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64() == right.as_f64(),
+        (Value::Array(left), Value::Array(right)) => {
+            left.len() == right.len() && left.iter().zip(right).all(|(l, r)| equal(l, r))
+        }
+        (Value::Object(left), Value::Object(right)) => {
+            left.len() == right.len()
+                && left
+                    .iter()
+                    .all(|(key, l)| right.get(key).is_some_and(|r| equal(l, r)))
+        }
+        (left, right) => left == right,
+    }
+}
+
+/// Ordering comparisons are defined for numbers only; anything else is
+/// `null`.
+fn compare(comparator: Comparator, left: &Value, right: &Value) -> Value {
+    let ordering = match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => left.partial_cmp(&right),
+        _ => None,
+    };
+    match (comparator, ordering) {
+        (Comparator::Eq, _) => Value::Bool(equal(left, right)),
+        (Comparator::Ne, _) => Value::Bool(!equal(left, right)),
+        (_, None) => Value::Null,
+        (Comparator::Lt, Some(ordering)) => Value::Bool(ordering.is_lt()),
+        (Comparator::Le, Some(ordering)) => Value::Bool(ordering.is_le()),
+        (Comparator::Gt, Some(ordering)) => Value::Bool(ordering.is_gt()),
+        (Comparator::Ge, Some(ordering)) => Value::Bool(ordering.is_ge()),
+    }
+}
+
+/// A whole number as an integer, anything else as a float.
+fn number(value: f64) -> Value {
+    if value.fract() == 0.0 && value.abs() < 9_007_199_254_740_992.0 {
+        Value::from(value as i64)
+    } else {
+        serde_json::Number::from_f64(value)
+            .map(Value::Number)
+            .unwrap_or(Value::Null)
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Orders values that are all numbers or all strings, as `sort`, `max` and
+/// the `*_by` functions require.
+fn sort_order(name: &str, values: &[&Value]) -> Result<(), CliError> {
+    let numbers = values.iter().all(|value| value.is_number());
+    let strings = values.iter().all(|value| value.is_string());
+    if numbers || strings {
+        Ok(())
+    } else {
+        Err(CliError::Query(format!(
+            "{name}() needs all numbers or all strings"
+        )))
+    }
+}
+
+fn order(left: &Value, right: &Value) -> Ordering {
+    match (left, right) {
+        (Value::String(left), Value::String(right)) => left.cmp(right),
+        (left, right) => left
+            .as_f64()
+            .partial_cmp(&right.as_f64())
+            .unwrap_or(Ordering::Equal),
+    }
+}
+
+fn call(name: &str, arguments: &[Node], value: &Value) -> Result<Value, CliError> {
+    let invalid = |expected: &str| CliError::Query(format!("{name}() expects {expected}"));
+    let arity = |count: usize| {
+        if arguments.len() == count {
+            Ok(())
+        } else {
+            Err(CliError::Query(format!(
+                "{name}() takes {count} argument{}, got {}",
+                if count == 1 { "" } else { "s" },
+                arguments.len()
+            )))
+        }
+    };
+    let argument = |index: usize| eval(&arguments[index], value);
+    let expression = |index: usize| match &arguments[index] {
+        Node::ExpressionRef(expression) => Ok(&**expression),
+        _ => Err(invalid("an `&expression` argument")),
+    };
+    let array = |index: usize| match argument(index)? {
+        Value::Array(items) => Ok(items),
+        _ => Err(invalid("an array")),
+    };
+    let numbers = |index: usize| {
+        array(index)?
+            .iter()
+            .map(|item| item.as_f64().ok_or_else(|| invalid("an array of numbers")))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let string = |index: usize| match argument(index)? {
+        Value::String(text) => Ok(text),
+        _ => Err(invalid("a string")),
+    };
+    // Pairs each element of the first argument with its `&expression` key.
+    let keyed = || -> Result<Vec<(Value, Value)>, CliError> {
+        arity(2)?;
+        let key = expression(1)?;
+        let pairs = array(0)?
+            .into_iter()
+            .map(|item| Ok((eval(key, &item)?, item)))
+            .collect::<Result<Vec<_>, CliError>>()?;
+        sort_order(name, &pairs.iter().map(|(key, _)| key).collect::<Vec<_>>())?;
+        Ok(pairs)
+    };
+
+    Ok(match name {
+        "abs" | "ceil" | "floor" => {
+            arity(1)?;
+            let number_value = argument(0)?.as_f64().ok_or_else(|| invalid("a number"))?;
+            number(match name {
+                "abs" => number_value.abs(),
+                "ceil" => number_value.ceil(),
+                _ => number_value.floor(),
+            })
+        }
+        "avg" | "sum" => {
+            arity(1)?;
+            let numbers = numbers(0)?;
+            let total: f64 = numbers.iter().sum();
+            match (name, numbers.len()) {
+                ("sum", _) => number(total),
+                (_, 0) => Value::Null,
+                (_, count) => number(total / count as f64),
+            }
+        }
+        "contains" => {
+            arity(2)?;
+            let needle = argument(1)?;
+            match argument(0)? {
+                Value::Array(items) => Value::Bool(items.iter().any(|item| equal(item, &needle))),
+                Value::String(text) => {
+                    Value::Bool(needle.as_str().is_some_and(|needle| text.contains(needle)))
+                }
+                _ => return Err(invalid("an array or string")),
+            }
+        }
+        "starts_with" | "ends_with" => {
+            arity(2)?;
+            let (text, affix) = (string(0)?, string(1)?);
+            Value::Bool(if name == "starts_with" {
+                text.starts_with(&affix)
+            } else {
+                text.ends_with(&affix)
+            })
+        }
+        "join" => {
+            arity(2)?;
+            let separator = string(0)?;
+            let parts = array(1)?
+                .into_iter()
+                .map(|item| match item {
+                    Value::String(text) => Ok(text),
+                    _ => Err(invalid("an array of strings")),
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Value::String(parts.join(&separator))
+        }
+        "keys" | "values" => {
+            arity(1)?;
+            let Value::Object(map) = argument(0)? else {
+                return Err(invalid("an object"));
+            };
+            if name == "keys" {
+                map.keys().cloned().map(Value::String).collect()
+            } else {
+                map.values().cloned().collect()
+            }
+        }
+        "length" => {
+            arity(1)?;
+            Value::from(match argument(0)? {
+                Value::String(text) => text.chars().count(),
+                Value::Array(items) => items.len(),
+                Value::Object(map) => map.len(),
+                _ => return Err(invalid("a string, array or object")),
+            })
+        }
+        "max" | "min" => {
+            arity(1)?;
+            let items = array(0)?;
+            sort_order(name, &items.iter().collect::<Vec<_>>())?;
+            let best = if name == "max" {
+                items.into_iter().max_by(order)
+            } else {
+                items.into_iter().min_by(order)
+            };
+            best.unwrap_or(Value::Null)
+        }
+        "max_by" | "min_by" => {
+            let pairs = keyed()?;
+            let best = if name == "max_by" {
+                pairs.into_iter().max_by(|(a, _), (b, _)| order(a, b))
+            } else {
+                pairs.into_iter().min_by(|(a, _), (b, _)| order(a, b))
+            };
+            best.map(|(_, item)| item).unwrap_or(Value::Null)
+        }
+        "sort" => {
+            arity(1)?;
+            let mut items = array(0)?;
+            sort_order(name, &items.iter().collect::<Vec<_>>())?;
+            items.sort_by(order);
+            Value::Array(items)
+        }
+        "sort_by" => {
+            let mut pairs = keyed()?;
+            pairs.sort_by(|(a, _), (b, _)| order(a, b));
+            pairs.into_iter().map(|(_, item)| item).collect()
+        }
+        "map" => {
+            arity(2)?;
+            let expression = expression(0)?;
+            array(1)?
+                .iter()
+                .map(|item| eval(expression, item))
+                .collect::<Result<_, _>>()?
+        }
+        "merge" => {
+            let mut merged = Map::new();
+            for index in 0..arguments.len() {
+                let Value::Object(map) = argument(index)? else {
+                    return Err(invalid("objects"));
+                };
+                merged.extend(map);
+            }
+            Value::Object(merged)
+        }
+        "not_null" => {
+            for index in 0..arguments.len() {
+                let candidate = argument(index)?;
+                if !candidate.is_null() {
+                    return Ok(candidate);
+                }
+            }
+            Value::Null
+        }
+        "reverse" => {
+            arity(1)?;
+            match argument(0)? {
+                Value::Array(mut items) => {
+                    items.reverse();
+                    Value::Array(items)
+                }
+                Value::String(text) => Value::String(text.chars().rev().collect()),
+                _ => return Err(invalid("an array or string")),
+            }
+        }
+        "to_array" => {
+            arity(1)?;
+            match argument(0)? {
+                Value::Array(items) => Value::Array(items),
+                other => Value::Array(vec![other]),
+            }
+        }
+        "to_string" => {
+            arity(1)?;
+            match argument(0)? {
+                Value::String(text) => Value::String(text),
+                other => Value::String(other.to_string()),
+            }
+        }
+        "to_number" => {
+            arity(1)?;
+            match argument(0)? {
+                Value::Number(number) => Value::Number(number),
+                Value::String(text) => text.trim().parse().map(number).unwrap_or(Value::Null),
+                _ => Value::Null,
+            }
+        }
+        "type" => {
+            arity(1)?;
+            Value::from(type_name(&argument(0)?))
+        }
+        _ => return Err(CliError::Query(format!("unknown function {name}()"))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{CliError, Query};
+
+    fn search(expression: &str, value: &serde_json::Value) -> serde_json::Value {
+        Query::parse(expression)
+            .expect("query should parse")
+            .search(value)
+            .expect("query should evaluate")
+    }
+
+    #[test]
+    fn queries_select_project_filter_and_call_functions() {
+        let sessions = json!({
+            "sessions": [
+                { "id": "a", "state": "idle", "turns": 3, "tags": ["x", "y"] },
+                { "id": "b", "state": "busy", "turns": 10, "tags": ["z"] },
+                { "id": "c", "state": "idle", "turns": 1 }
+            ],
+            "server": { "version": "1.2.0" }
+        });
+
+        assert_eq!(search("server.version", &sessions), json!("1.2.0"));
+        assert_eq!(search("sessions[-1].id", &sessions), json!("c"));
+        assert_eq!(search("sessions[*].id", &sessions), json!(["a", "b", "c"]));
+        assert_eq!(search("sessions[1:].id", &sessions), json!(["b", "c"]));
+        assert_eq!(search("sessions[::-1].id | [0]", &sessions), json!("c"));
+        assert_eq!(
+            search("sessions[1::9223372036854775807].id", &sessions),
+            json!(["b"])
+        );
+        assert_eq!(
+            search("sessions[?state == 'idle' && turns > `2`].id", &sessions),
+            json!(["a"])
+        );
+        assert_eq!(
+            search("sessions[].tags[]", &sessions),
+            json!(["x", "y", "z"])
+        );
+        assert_eq!(
+            search(
+                "sessions[0].{name: id, \"tag count\": length(tags)}",
+                &sessions
+            ),
+            json!({ "name": "a", "tag count": 2 })
+        );
+        assert_eq!(
+            search("sort_by(sessions, &turns)[*].id", &sessions),
+            json!(["c", "a", "b"])
+        );
+        assert_eq!(search("max_by(sessions, &turns).id", &sessions), json!("b"));
+        assert_eq!(search("sum(sessions[*].turns)", &sessions), json!(14));
+        assert_eq!(
+            search("join(',', sessions[*].state)", &sessions),
+            json!("idle,busy,idle")
+        );
+        assert_eq!(search("missing.field", &sessions), json!(null));
+        assert_eq!(search("!sessions || `false`", &sessions), json!(false));
+    }
+
+    #[test]
+    fn malformed_queries_and_bad_arguments_are_query_errors() {
+        for expression in [
+            "",
+            "sessions[",
+            "a ==",
+            "foo(",
+            "'unterminated",
+            "a[::0]",
+            "not_null(n s)",
+            "merge(foo foo)",
+            "length(a,)",
+            "[a b]",
+        ] {
+            assert!(
+                matches!(Query::parse(expression), Err(CliError::Query(_))),
+                "{expression:?} should not parse"
+            );
+        }
+
+        let query = Query::parse("length(`1`)").expect("query should parse");
+        let error = query
+            .search(&json!({}))
+            .expect_err("length of a number fails");
+        assert!(matches!(error, CliError::Query(_)));
+        assert_eq!(error.exit_code(), 9);
+        assert!(matches!(
+            Query::parse("nope()").and_then(|query| query.search(&json!({}))),
+            Err(CliError::Query(_))
+        ));
+    }
+
+    #[test]
+    fn slices_clamp_their_bounds() {
+        let numbers = json!([0, 1, 2, 3, 4]);
+        assert_eq!(search("[-10:10]", &numbers), json!([0, 1, 2, 3, 4]));
+        assert_eq!(search("[10:]", &numbers), json!([]));
+        assert_eq!(search("[:-10]", &numbers), json!([]));
+        assert_eq!(search("[3:1]", &numbers), json!([]));
+        assert_eq!(search("[::-2]", &numbers), json!([4, 2, 0]));
+        assert_eq!(search("[10:1:-1]", &numbers), json!([4, 3, 2]));
+        assert_eq!(search("[-1:-10:-1]", &numbers), json!([4, 3, 2, 1, 0]));
+        assert_eq!(search("[:2]", &json!("text")), json!(null));
+    }
+
+    #[test]
+    fn literals_and_quoted_names_unescape_like_the_spec() {
+        let value = json!({ "a\"b": 1, "with space": 2 });
+        assert_eq!(search(r#""a\"b""#, &value), json!(1));
+        assert_eq!(search(r#""with space""#, &value), json!(2));
+        assert_eq!(search(r"'it\'s'", &value), json!("it's"));
+        assert_eq!(search(r"'back\\slash'", &value), json!(r"back\slash"));
+        assert_eq!(search(r"'kept\n'", &value), json!(r"kept\n"));
+        assert_eq!(search(r#"`"tick\`"`"#, &value), json!("tick`"));
+        assert_eq!(
+            search(r#"`{"k": [1, 2.5, null]}`"#, &value),
+            json!({ "k": [1, 2.5, null] })
+        );
+        assert!(matches!(
+            Query::parse("`{not json}`"),
+            Err(CliError::Query(_))
+        ));
+    }
+
+    #[test]
+    fn multi_selects_on_null_and_logic_short_circuit() {
+        let value = json!({ "ok": "yes", "empty": "", "zero": 0 });
+        assert_eq!(search("missing.[ok, empty]", &value), json!(null));
+        assert_eq!(search("missing.{a: ok}", &value), json!(null));
+        assert_eq!(search("[ok, missing]", &value), json!(["yes", null]));
+
+        // The right side would fail if it were evaluated.
+        assert_eq!(search("ok || length(`1`)", &value), json!("yes"));
+        assert_eq!(search("empty && length(`1`)", &value), json!(""));
+        assert_eq!(search("empty || missing || ok", &value), json!("yes"));
+        assert_eq!(search("zero && ok", &value), json!("yes"));
+        assert!(matches!(
+            Query::parse("empty || length(`1`)").and_then(|query| query.search(&value)),
+            Err(CliError::Query(_))
+        ));
+    }
+}
@@ -0,0 +1,338 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    protocol::glob_matches,
+    timeout::{Deadline, TimeoutPhase},
+    CliError, GatewayError,
+};
+
+/// How failed gateway calls are repeated.
+///
+/// `healthz`/`info` requests and failures to open a session are retried
+/// whenever the transport fails, as are `healthz`/`info` responses with
+/// status 429 or 5xx. An RPC that already reached the gateway is only
+/// repeated when its method matches `safe_methods` or the gateway marks the
+/// error as retryable. Protocol errors are never retried.
+///
+/// A `Retry-After` or `retryAfterMs` longer than the backoff stretches the
+/// delay, but one longer than `max_delay` ends the retries instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// Total attempts, including the first one. `1` disables retries.
+    pub max_attempts: u32,
+    /// Delay before the first retry; doubled for each further retry.
+    pub base_delay: Duration,
+    /// Upper bound for a single delay.
+    pub max_delay: Duration,
+    /// Fraction (0.0-1.0) of each delay that is randomly shaved off.
+    pub jitter: f64,
+    /// Glob patterns naming RPC methods that are safe to send twice.
+    pub safe_methods: Vec<String>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(250),
+            max_delay: Duration::from_secs(5),
+            jitter: 0.2,
+            safe_methods: Vec::new(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that makes exactly one attempt.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    pub fn is_safe_method(&self, method: &str) -> bool {
+        self.safe_methods
+            .iter()
+            .any(|pattern| glob_matches(pattern, method))
+    }
+
+    /// The wait before the retry after `failures` failures, or `None` when
+    /// the server asks for a longer wait than `max_delay`.
+    fn delay(&self, failures: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        let retry_after = retry_after.unwrap_or_default();
+        if retry_after > self.max_delay {
+            return None;
+        }
+        let exponent = failures.saturating_sub(1).min(31);
+        let backoff = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let jittered = backoff.mul_f64(1.0 - self.jitter.clamp(0.0, 1.0) * random_fraction());
+
+        Some(jittered.max(retry_after))
+    }
+}
+
+/// Whether a failed attempt may be repeated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Verdict {
+    Fatal,
+    Retry { after: Option<Duration> },
+}
+
+/// Classifies failures that happen before anything reaches the gateway, or
+/// on idempotent HTTP reads.
+pub(crate) fn connection_verdict(error: &CliError) -> Verdict {
+    match error {
+        CliError::Gateway(error) => gateway_verdict(error),
+        CliError::Transport(_)
+        | CliError::Timeout {
+            phase: TimeoutPhase::Connect | TimeoutPhase::Handshake,
+            ..
+        } => Verdict::Retry { after: None },
+        _ => Verdict::Fatal,
+    }
+}
+
+pub(crate) fn connection_failure(error: CliError) -> (CliError, Verdict) {
+    let verdict = connection_verdict(&error);
+    (error, verdict)
+}
+
+/// Classifies a request that may already have reached the gateway: it is
+/// repeated only when the gateway says so or the method is known to be safe.
+pub(crate) fn rpc_failure(error: CliError, safe_method: bool) -> (CliError, Verdict) {
+    let verdict = match &error {
+        CliError::Gateway(error) => gateway_verdict(error),
+        CliError::Transport(_)
+        | CliError::Timeout {
+            phase: TimeoutPhase::Request,
+            ..
+        } if safe_method => Verdict::Retry { after: None },
+        _ => Verdict::Fatal,
+    };
+
+    (error, verdict)
+}
+
+/// Reads a `Retry-After` header given in seconds. The HTTP-date form is
+/// ignored, leaving the normal backoff.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    value.trim().parse().ok().map(Duration::from_secs)
+}
+
+fn gateway_verdict(error: &GatewayError) -> Verdict {
+    if error.retryable {
+        Verdict::Retry {
+            after: error.retry_after_ms.map(Duration::from_millis),
+        }
+    } else {
+        Verdict::Fatal
+    }
+}
+
+/// Runs `attempt` until it succeeds, fails fatally, exhausts the policy or
+/// would sleep past the overall deadline. The last error is returned.
+pub(crate) fn run<T>(
+    policy: &RetryPolicy,
+    deadline: Deadline,
+    mut attempt: impl FnMut() -> Result<T, (CliError, Verdict)>,
+) -> Result<T, CliError> {
+    let mut failures = 0;
+    loop {
+        let (error, verdict) = match attempt() {
+            Ok(value) => return Ok(value),
+            Err(failure) => failure,
+        };
+        failures += 1;
+
+        let Verdict::Retry { after } = verdict else {
+            return Err(error);
+        };
+        if failures >= policy.max_attempts {
+            return Err(error);
+        }
+
+        let Some(delay) = policy.delay(failures, after) else {
+            return Err(error);
+        };
+        match deadline.limit(None, TimeoutPhase::Deadline) {
+            Ok(None) => {}
+            Ok(Some(limit)) if limit.remaining().is_ok_and(|left| left > delay) => {}
+            _ => return Err(error),
+        }
+        thread::sleep(delay);
+    }
+}
+
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish() >> 11;
+    bits as f64 / (1_u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, time::Duration};
+
+    use crate::{
+        retry::{connection_verdict, rpc_failure, run, RetryPolicy, Verdict},
+        timeout::{Deadline, TimeoutPhase},
+        CliError, GatewayError,
+    };
+
+    fn fast_policy() -> RetryPolicy {
+        RetryPolicy {
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(2),
+            ..RetryPolicy::default()
+        }
+    }
+
+    #[test]
+    fn delay_doubles_up_to_max_without_jitter() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            jitter: 0.0,
+            ..RetryPolicy::default()
+        };
+
+        assert_eq!(policy.delay(1, None), Some(Duration::from_millis(100)));
+        assert_eq!(policy.delay(2, None), Some(Duration::from_millis(200)));
+        assert_eq!(policy.delay(3, None), Some(Duration::from_millis(300)));
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_millis(250))),
+            Some(Duration::from_millis(250))
+        );
+        // A server asking for an hour is not waited for.
+        assert_eq!(policy.delay(1, Some(Duration::from_secs(3600))), None);
+    }
+
+    #[test]
+    fn jitter_only_shortens_delays() {
+        let policy = RetryPolicy {
+            base_delay: Duration::from_millis(100),
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+
+        for _ in 0..20 {
+            let delay = policy.delay(1, None).expect("delay should be allowed");
+            assert!(delay >= Duration::from_millis(50) && delay <= Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn run_retries_transient_failures_until_success() {
+        let attempts = Cell::new(0);
+        let result = run(&fast_policy(), Deadline::default(), || {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() < 3 {
+                let error = CliError::Transport("connection refused".to_owned());
+                let verdict = connection_verdict(&error);
+                Err((error, verdict))
+            } else {
+                Ok("ok")
+            }
+        });
+
+        assert_eq!(result.expect("third attempt should succeed"), "ok");
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn run_stops_on_fatal_errors_and_exhausted_attempts() {
+        let attempts = Cell::new(0);
+        let result: Result<(), CliError> = run(&fast_policy(), Deadline::default(), || {
+            attempts.set(attempts.get() + 1);
+            Err((CliError::Protocol("bad frame".to_owned()), Verdict::Fatal))
+        });
+        assert!(matches!(result, Err(CliError::Protocol(_))));
+        assert_eq!(attempts.get(), 1);
+
+        attempts.set(0);
+        let result: Result<(), CliError> = run(&fast_policy(), Deadline::default(), || {
+            attempts.set(attempts.get() + 1);
+            Err((
+                CliError::Transport("reset".to_owned()),
+                Verdict::Retry { after: None },
+            ))
+        });
+        assert!(matches!(result, Err(CliError::Transport(_))));
+        assert_eq!(attempts.get(), 3);
+    }
+
+    #[test]
+    fn connection_verdict_never_retries_protocol_errors() {
+        assert_eq!(
+            connection_verdict(&CliError::Protocol("nope".to_owned())),
+            Verdict::Fatal
+        );
+        assert_eq!(
+            connection_verdict(&CliError::Timeout {
+                phase: TimeoutPhase::Request,
+                after: Duration::from_secs(1),
+            }),
+            Verdict::Fatal
+        );
+        assert_eq!(
+            connection_verdict(&CliError::Timeout {
+                phase: TimeoutPhase::Connect,
+                after: Duration::from_secs(1),
+            }),
+            Verdict::Retry { after: None }
+        );
+    }
+
+    #[test]
+    fn rpc_failure_honours_gateway_hint_and_method_safety() {
+        let retryable = CliError::Gateway(GatewayError {
+            code: Some("UNAVAILABLE".to_owned()),
+            message: "restarting".to_owned(),
+            retryable: true,
+            retry_after_ms: Some(1500),
+            ..GatewayError::default()
+        });
+        let (error, verdict) = rpc_failure(retryable, false);
+        assert!(matches!(error, CliError::Gateway(_)));
+        assert_eq!(
+            verdict,
+            Verdict::Retry {
+                after: Some(Duration::from_millis(1500))
+            }
+        );
+
+        let dropped = || CliError::Transport("reset".to_owned());
+        assert_eq!(rpc_failure(dropped(), false).1, Verdict::Fatal);
+        assert_eq!(
+            rpc_failure(dropped(), true).1,
+            Verdict::Retry { after: None }
+        );
+
+        let rejected = CliError::Gateway(GatewayError {
+            code: Some("INVALID_REQUEST".to_owned()),
+            message: "bad params".to_owned(),
+            ..GatewayError::default()
+        });
+        assert_eq!(rpc_failure(rejected, true).1, Verdict::Fatal);
+    }
+
+    #[test]
+    fn safe_methods_match_globs() {
+        let policy = RetryPolicy {
+            safe_methods: vec!["status".to_owned(), "*.list".to_owned()],
+            ..RetryPolicy::default()
+        };
+
+        assert!(policy.is_safe_method("status"));
+        assert!(policy.is_safe_method("sessions.list"));
+        assert!(!policy.is_safe_method("sessions.delete"));
+    }
+}
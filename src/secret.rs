@@ -0,0 +1,106 @@
+use std::{
+    fs,
+    io::BufRead,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use crate::CliError;
+
+/// Reads a secret from the first line of a file.
+pub(crate) fn from_file(path: &Path) -> Result<String, CliError> {
+    let contents = fs::read_to_string(path).map_err(|error| {
+        CliError::InvalidAuth(format!("failed to read {}: {error}", path.display()))
+    })?;
+    first_line(&contents, &path.display().to_string())
+}
+
+/// Reads a secret from the first line of `input`, normally stdin.
+pub(crate) fn from_reader(input: &mut dyn BufRead) -> Result<String, CliError> {
+    let mut line = String::new();
+    input
+        .read_line(&mut line)
+        .map_err(|error| CliError::InvalidAuth(format!("failed to read stdin: {error}")))?;
+    first_line(&line, "stdin")
+}
+
+/// Runs `command` through the platform shell and reads the secret from the
+/// first line of its stdout. The helper's stderr is passed through so it
+/// can prompt or explain failures.
+pub(crate) fn from_command(command: &str) -> Result<String, CliError> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let output = shell
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|error| CliError::InvalidAuth(format!("failed to run auth command: {error}")))?;
+
+    if !output.status.success() {
+        return Err(CliError::InvalidAuth(format!(
+            "auth command exited with {}",
+            output.status
+        )));
+    }
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| CliError::InvalidAuth("auth command printed non-UTF-8 output".to_owned()))?;
+    first_line(&stdout, "auth command output")
+}
+
+fn first_line(text: &str, source: &str) -> Result<String, CliError> {
+    let secret = text.lines().next().unwrap_or_default().trim();
+    if secret.is_empty() {
+        return Err(CliError::InvalidAuth(format!("{source} is empty")));
+    }
+    Ok(secret.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        secret::{from_command, from_file, from_reader},
+        CliError,
+    };
+
+    #[test]
+    fn secrets_are_read_from_the_first_line() {
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let path = dir.path().join("secret");
+        fs::write(&path, "  file-token  \nsecond line\n").expect("secret file should be written");
+        assert_eq!(from_file(&path).expect("file should be read"), "file-token");
+
+        let mut stdin = "stdin-password\n".as_bytes();
+        assert_eq!(
+            from_reader(&mut stdin).expect("stdin should be read"),
+            "stdin-password"
+        );
+        let mut empty = "\n".as_bytes();
+        assert!(matches!(
+            from_reader(&mut empty),
+            Err(CliError::InvalidAuth(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn auth_command_output_becomes_the_secret() {
+        assert_eq!(
+            from_command("echo helper-token").expect("command should succeed"),
+            "helper-token"
+        );
+        assert!(matches!(
+            from_command("exit 3"),
+            Err(CliError::InvalidAuth(message)) if message.contains("exited")
+        ));
+    }
+}
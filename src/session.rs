@@ -0,0 +1,1322 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, ErrorKind, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+use tungstenite::{
+    client::IntoClientRequest,
+    client_tls_with_config,
+    handshake::HandshakeError,
+    http::{HeaderName, HeaderValue},
+    stream::MaybeTlsStream,
+    Connector, Message, WebSocket,
+};
+
+use crate::{
+    protocol::{
+        event_matches, protocol_mismatch, GatewayError, GatewayEvent, HelloOk, ProtocolRange,
+    },
+    timeout::{remaining, Deadline, Limit, TimeoutPhase},
+    CliError, ClientOptions, Proxy,
+};
+
+const CONNECT_REQUEST_ID: &str = "connect-1";
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long a client with a device identity waits for `connect.challenge`
+/// from a silent gateway before signing without a nonce.
+const CHALLENGE_GRACE: Duration = Duration::from_millis(750);
+
+type WsSocket = WebSocket<MaybeTlsStream<Transport>>;
+type ResponseSender = Sender<Result<Value, CliError>>;
+type EventSender = Sender<Result<GatewayEvent, CliError>>;
+
+/// A long-lived gateway WebSocket connection.
+///
+/// The `connect` handshake runs once when the session is opened. Afterwards a
+/// worker thread owns the socket, so any number of requests can be in flight
+/// at once; responses are routed back to their callers by request id.
+pub struct GatewaySession {
+    hello: HelloOk,
+    protocol: u64,
+    request_timeout: Option<Duration>,
+    deadline: Deadline,
+    outgoing: Sender<Outgoing>,
+    shared: Arc<Mutex<SharedState>>,
+    next_id: AtomicU64,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// A request that has been sent but whose response has not been awaited yet.
+/// Dropping it abandons the request; a late response is then discarded.
+pub struct PendingResponse {
+    id: String,
+    receiver: Receiver<Result<Value, CliError>>,
+    limit: Option<Limit>,
+    shared: Arc<Mutex<SharedState>>,
+}
+
+/// A stream of gateway `event` frames whose names match a set of glob
+/// patterns.
+pub struct EventSubscription {
+    receiver: Receiver<Result<GatewayEvent, CliError>>,
+    deadline: Deadline,
+    _session: Option<Arc<GatewaySession>>,
+}
+
+enum Outgoing {
+    Frame(String),
+    Close,
+}
+
+#[derive(Default)]
+struct SharedState {
+    closed: Option<String>,
+    waiters: HashMap<String, ResponseSender>,
+    subscribers: Vec<Subscriber>,
+}
+
+struct Subscriber {
+    patterns: Vec<String>,
+    sender: EventSender,
+}
+
+impl GatewaySession {
+    pub(crate) fn open(
+        ws_url: &str,
+        options: &ClientOptions,
+        deadline: Deadline,
+        mut connect_params: Value,
+    ) -> Result<Self, CliError> {
+        let protocol = options.protocol;
+        let (mut socket, handshake) = connect_websocket(ws_url, options, deadline)?;
+
+        let mut early = VecDeque::new();
+        if let Some(device) = &options.device {
+            let nonce = await_challenge(&mut socket, handshake, &mut early)?;
+            let block = device.connect_block(&connect_params, nonce.as_deref(), unix_millis());
+            if let Some(params) = connect_params.as_object_mut() {
+                params.insert("device".to_owned(), block);
+            }
+        }
+        if let Some(params) = connect_params.as_object_mut() {
+            params.insert("minProtocol".to_owned(), json!(protocol.min()));
+            params.insert("maxProtocol".to_owned(), json!(protocol.max()));
+        }
+        send_json(
+            &mut socket,
+            &json!({
+                "type": "req",
+                "id": CONNECT_REQUEST_ID,
+                "method": "connect",
+                "params": connect_params,
+            }),
+        )?;
+        let mut hello = HelloOk::from_payload(read_handshake_response(
+            &mut socket,
+            protocol,
+            handshake,
+            early,
+        )?)?;
+        let negotiated = hello.protocol.unwrap_or(protocol.max());
+        if !protocol.contains(negotiated) {
+            let _ = socket.close(None);
+            return Err(CliError::UnsupportedProtocol {
+                client: protocol,
+                server: Some(negotiated.to_string()),
+            });
+        }
+        // Callers (and `hello` output) always see the version in use.
+        hello.protocol = Some(negotiated);
+        set_read_timeout(&socket, Some(POLL_INTERVAL))?;
+
+        let shared = Arc::new(Mutex::new(SharedState::default()));
+        let (outgoing, outgoing_rx) = mpsc::channel();
+        let worker_shared = Arc::clone(&shared);
+        let worker = thread::Builder::new()
+            .name("reclaw-session".to_owned())
+            .spawn(move || run_worker(socket, outgoing_rx, worker_shared))
+            .map_err(|error| CliError::Transport(format!("failed to start session: {error}")))?;
+
+        Ok(Self {
+            hello,
+            protocol: negotiated,
+            request_timeout: options.timeouts.request,
+            deadline,
+            outgoing,
+            shared,
+            next_id: AtomicU64::new(1),
+            worker: Some(worker),
+        })
+    }
+
+    /// The `hello-ok` payload the gateway returned for this session's
+    /// `connect` handshake, with `protocol` set to [`Self::protocol`].
+    pub fn hello(&self) -> &HelloOk {
+        &self.hello
+    }
+
+    /// The protocol version the gateway selected from the offered range.
+    /// Gateways that omit it are assumed to speak the highest offered
+    /// version.
+    pub fn protocol(&self) -> u64 {
+        self.protocol
+    }
+
+    /// Sends a request and blocks until its response arrives.
+    pub fn request(&self, method: &str, params: Value) -> Result<Value, CliError> {
+        self.send(method, params)?.wait()
+    }
+
+    /// Sends a request without waiting, so several can be in flight at once.
+    pub fn send(&self, method: &str, params: Value) -> Result<PendingResponse, CliError> {
+        let limit = self
+            .deadline
+            .limit(self.request_timeout, TimeoutPhase::Request)?;
+        let id = format!("rpc-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let frame = json!({
+            "type": "req",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        let encoded = encode_frame(&frame)?;
+
+        let (sender, receiver) = mpsc::channel();
+        {
+            let mut shared = lock(&self.shared);
+            if let Some(reason) = &shared.closed {
+                return Err(CliError::Transport(reason.clone()));
+            }
+            shared.waiters.insert(id.clone(), sender);
+        }
+
+        if self.outgoing.send(Outgoing::Frame(encoded)).is_err() {
+            lock(&self.shared).waiters.remove(&id);
+            return Err(CliError::Transport("gateway session is closed".to_owned()));
+        }
+
+        Ok(PendingResponse {
+            id,
+            receiver,
+            limit,
+            shared: Arc::clone(&self.shared),
+        })
+    }
+
+    /// Subscribes to events whose names match any of `patterns` (`*` and `?`
+    /// globs). An empty pattern list receives every event.
+    pub fn subscribe(&self, patterns: &[String]) -> Result<EventSubscription, CliError> {
+        let (sender, receiver) = mpsc::channel();
+        let mut shared = lock(&self.shared);
+        if let Some(reason) = &shared.closed {
+            return Err(CliError::Transport(reason.clone()));
+        }
+        shared.subscribers.push(Subscriber {
+            patterns: patterns.to_vec(),
+            sender,
+        });
+
+        Ok(EventSubscription {
+            deadline: self.deadline,
+            ..EventSubscription::new(receiver)
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        lock(&self.shared).closed.is_some()
+    }
+
+    /// Closes the WebSocket and waits for the worker thread to finish.
+    pub fn close(mut self) {
+        self.shutdown();
+    }
+
+    fn shutdown(&mut self) {
+        let _ = self.outgoing.send(Outgoing::Close);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Drop for GatewaySession {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+impl PendingResponse {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Blocks until the response arrives, the request timeout or overall
+    /// deadline expires, or the session closes.
+    pub fn wait(self) -> Result<Value, CliError> {
+        let closed = || CliError::Transport("gateway session closed before response".to_owned());
+        let Some(limit) = self.limit else {
+            return self.receiver.recv().unwrap_or_else(|_| Err(closed()));
+        };
+
+        let timeout = limit.remaining()?;
+        match self.receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(limit.error()),
+            Err(RecvTimeoutError::Disconnected) => Err(closed()),
+        }
+    }
+}
+
+impl Drop for PendingResponse {
+    fn drop(&mut self) {
+        lock(&self.shared).waiters.remove(&self.id);
+    }
+}
+
+impl EventSubscription {
+    pub(crate) fn new(receiver: Receiver<Result<GatewayEvent, CliError>>) -> Self {
+        Self {
+            receiver,
+            deadline: Deadline::default(),
+            _session: None,
+        }
+    }
+
+    pub(crate) fn keep_alive(mut self, session: Arc<GatewaySession>) -> Self {
+        self._session = Some(session);
+        self
+    }
+
+    /// Blocks until the next matching event arrives, the session closes or
+    /// the overall deadline expires.
+    pub fn recv(&self) -> Result<GatewayEvent, CliError> {
+        let closed = || CliError::Transport("gateway event stream closed".to_owned());
+        let Some(limit) = self.deadline.limit(None, TimeoutPhase::Deadline)? else {
+            return self.receiver.recv().unwrap_or_else(|_| Err(closed()));
+        };
+
+        match self.receiver.recv_timeout(limit.remaining()?) {
+            Ok(result) => result,
+            Err(RecvTimeoutError::Timeout) => Err(limit.error()),
+            Err(RecvTimeoutError::Disconnected) => Err(closed()),
+        }
+    }
+}
+
+fn run_worker(mut socket: WsSocket, outgoing: Receiver<Outgoing>, shared: Arc<Mutex<SharedState>>) {
+    let reason = 'session: loop {
+        loop {
+            match outgoing.try_recv() {
+                Ok(Outgoing::Frame(text)) => {
+                    if let Err(error) = socket.send(Message::Text(text.into())) {
+                        break 'session format!("websocket send failed: {error}");
+                    }
+                }
+                Ok(Outgoing::Close) | Err(TryRecvError::Disconnected) => {
+                    let _ = socket.close(None);
+                    let _ = socket.flush();
+                    break 'session "gateway session is closed".to_owned();
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        match poll_json_frame(&mut socket) {
+            Ok(Some(frame)) => dispatch_frame(&shared, frame),
+            Ok(None) => {}
+            Err(CliError::Transport(message) | CliError::Protocol(message)) => break message,
+            Err(error) => break error.to_string(),
+        }
+    };
+
+    let (waiters, subscribers) = {
+        let mut shared = lock(&shared);
+        shared.closed = Some(reason.clone());
+        (
+            std::mem::take(&mut shared.waiters),
+            std::mem::take(&mut shared.subscribers),
+        )
+    };
+    for (_, waiter) in waiters {
+        let _ = waiter.send(Err(CliError::Transport(reason.clone())));
+    }
+    for subscriber in subscribers {
+        let _ = subscriber
+            .sender
+            .send(Err(CliError::Transport(reason.clone())));
+    }
+}
+
+fn dispatch_frame(shared: &Mutex<SharedState>, frame: Value) {
+    if let Some(event) = GatewayEvent::from_frame(&frame) {
+        lock(shared).subscribers.retain(|subscriber| {
+            !event_matches(&subscriber.patterns, &event.event)
+                || subscriber.sender.send(Ok(event.clone())).is_ok()
+        });
+        return;
+    }
+
+    if frame.get("type").and_then(Value::as_str) != Some("res") {
+        return;
+    }
+
+    let Some(id) = frame.get("id").and_then(Value::as_str) else {
+        return;
+    };
+    let Some(waiter) = lock(shared).waiters.remove(id) else {
+        return;
+    };
+
+    let _ = waiter.send(response_result(&frame));
+}
+
+fn response_result(frame: &Value) -> Result<Value, CliError> {
+    if frame.get("ok").and_then(Value::as_bool).unwrap_or(false) {
+        return Ok(frame.get("payload").cloned().unwrap_or(Value::Null));
+    }
+
+    Err(CliError::Gateway(GatewayError::from_frame(frame)))
+}
+
+fn lock(shared: &Mutex<SharedState>) -> MutexGuard<'_, SharedState> {
+    shared
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn encode_frame(payload: &Value) -> Result<String, CliError> {
+    serde_json::to_string(payload)
+        .map_err(|error| CliError::Protocol(format!("failed to encode websocket frame: {error}")))
+}
+
+fn send_json(socket: &mut WsSocket, payload: &Value) -> Result<(), CliError> {
+    let encoded = encode_frame(payload)?;
+    socket
+        .send(Message::Text(encoded.into()))
+        .map_err(|error| CliError::Transport(format!("websocket send failed: {error}")))
+}
+
+/// Dials the gateway and performs the WebSocket upgrade, bounding the TCP
+/// connect and the handshake separately by the connect timeout. Returns the
+/// handshake limit so the `connect` exchange stays within it.
+///
+/// A `unix://` URL names the gateway's socket; the upgrade over it requests
+/// `options.ws_url`, or `ws://localhost/ws` without one.
+fn connect_websocket(
+    ws_url: &str,
+    options: &ClientOptions,
+    deadline: Deadline,
+) -> Result<(WsSocket, Option<Limit>), CliError> {
+    let (ws_url, unix_socket) = match ws_url.strip_prefix("unix://") {
+        Some(path) => (
+            options.ws_url.as_deref().unwrap_or("ws://localhost/ws"),
+            Some(path),
+        ),
+        None => (ws_url, None),
+    };
+    let mut request = ws_url
+        .into_client_request()
+        .map_err(|error| CliError::InvalidServer(format!("invalid websocket URL: {error}")))?;
+    for (name, value) in &options.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| CliError::InvalidOption(format!("invalid header name `{name}`")))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| CliError::InvalidOption(format!("invalid value for header `{name}`")))?;
+        request.headers_mut().append(name, value);
+    }
+    let host = request
+        .uri()
+        .host()
+        .ok_or_else(|| CliError::InvalidServer(format!("websocket URL has no host: {ws_url}")))?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_owned();
+    let secure = request.uri().scheme_str() == Some("wss");
+    let port = request
+        .uri()
+        .port_u16()
+        .unwrap_or(if secure { 443 } else { 80 });
+
+    let connect_limit = deadline.limit(options.timeouts.connect, TimeoutPhase::Connect)?;
+    let stream = match (unix_socket, options.proxy.route(&host, secure)) {
+        (Some(path), _) => connect_unix(path)?,
+        (None, Some(proxy)) => {
+            Transport::Tcp(connect_via_proxy(&proxy, &host, port, connect_limit)?)
+        }
+        (None, None) => Transport::Tcp(connect_tcp(&host, port, connect_limit)?),
+    };
+
+    let handshake = deadline.limit(options.timeouts.connect, TimeoutPhase::Handshake)?;
+    let timeout = remaining(handshake)?;
+    stream.configure(timeout, timeout)?;
+
+    let connector = options
+        .tls
+        .as_ref()
+        .map(|tls| Connector::Rustls(tls.rustls()));
+    let (socket, _) =
+        client_tls_with_config(request, stream, None, connector).map_err(|error| {
+            match (error, handshake) {
+                (HandshakeError::Interrupted(_), Some(handshake)) => handshake.error(),
+                (HandshakeError::Failure(tungstenite::Error::Io(error)), Some(handshake))
+                    if is_timeout(&error) =>
+                {
+                    handshake.error()
+                }
+                (error, _) => CliError::Transport(format!("websocket connect failed: {error}")),
+            }
+        })?;
+    Ok((socket, handshake))
+}
+
+fn connect_tcp(host: &str, port: u16, limit: Option<Limit>) -> Result<TcpStream, CliError> {
+    let addrs = (host, port)
+        .to_socket_addrs()
+        .map_err(|error| CliError::Transport(format!("failed to resolve {host}: {error}")))?;
+
+    let mut last_error = CliError::Transport(format!("no addresses found for {host}"));
+    for addr in addrs {
+        let result = match remaining(limit)? {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        };
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(error) => {
+                last_error = match limit {
+                    Some(limit) if is_timeout(&error) => limit.error(),
+                    _ => CliError::Transport(format!("websocket connect failed: {error}")),
+                };
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+#[cfg(unix)]
+fn connect_unix(path: &str) -> Result<Transport, CliError> {
+    std::os::unix::net::UnixStream::connect(path)
+        .map(Transport::Unix)
+        .map_err(|error| CliError::Transport(format!("failed to connect to {path}: {error}")))
+}
+
+#[cfg(not(unix))]
+fn connect_unix(_path: &str) -> Result<Transport, CliError> {
+    Err(CliError::InvalidServer(
+        "unix sockets are not supported on this platform".to_owned(),
+    ))
+}
+
+/// Dials `proxy` and asks it for a tunnel to `host:port`, all within the
+/// connect limit.
+fn connect_via_proxy(
+    proxy: &Proxy,
+    host: &str,
+    port: u16,
+    limit: Option<Limit>,
+) -> Result<TcpStream, CliError> {
+    let mut stream = connect_tcp(proxy.host(), proxy.port(), limit)?;
+    let timeout = remaining(limit)?;
+    configure_stream(&stream, timeout, timeout)?;
+    proxy
+        .tunnel(&mut stream, host, port)
+        .map_err(|error| match limit {
+            Some(limit) if is_timeout(&error) => limit.error(),
+            _ => CliError::Transport(format!(
+                "proxy {}:{} failed: {error}",
+                proxy.host(),
+                proxy.port()
+            )),
+        })?;
+    Ok(stream)
+}
+
+fn is_timeout(error: &std::io::Error) -> bool {
+    matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+/// Reads until the `connect` response, starting with the `early` frames
+/// that arrived while waiting for a challenge.
+fn read_handshake_response(
+    socket: &mut WsSocket,
+    protocol: ProtocolRange,
+    limit: Option<Limit>,
+    mut early: VecDeque<Value>,
+) -> Result<Value, CliError> {
+    loop {
+        let Some(frame) = early
+            .pop_front()
+            .map_or_else(|| poll_json_frame(socket), |frame| Ok(Some(frame)))?
+        else {
+            if let Some(limit) = limit {
+                let timeout = limit.remaining()?;
+                set_read_timeout(socket, Some(timeout))?;
+            }
+            continue;
+        };
+
+        if frame.get("type").and_then(Value::as_str) != Some("res") {
+            continue;
+        }
+
+        if frame.get("id").and_then(Value::as_str) != Some(CONNECT_REQUEST_ID) {
+            continue;
+        }
+
+        return response_result(&frame).map_err(|error| match error {
+            CliError::Gateway(error) => match protocol_mismatch(&error) {
+                Some(server) => CliError::UnsupportedProtocol {
+                    client: protocol,
+                    server,
+                },
+                None => CliError::Gateway(error),
+            },
+            error => error,
+        });
+    }
+}
+
+/// Waits for the gateway's `connect.challenge` event and returns its nonce.
+/// A gateway that sends any other frame first, or nothing within
+/// [`CHALLENGE_GRACE`], does not challenge and gets `None`; the frame is
+/// kept in `early` for the handshake.
+fn await_challenge(
+    socket: &mut WsSocket,
+    limit: Option<Limit>,
+    early: &mut VecDeque<Value>,
+) -> Result<Option<String>, CliError> {
+    let grace = Instant::now() + CHALLENGE_GRACE;
+    let nonce = loop {
+        let mut wait = grace.saturating_duration_since(Instant::now());
+        if wait.is_zero() {
+            break None;
+        }
+        if let Some(limit) = limit {
+            wait = wait.min(limit.remaining()?);
+        }
+        set_read_timeout(socket, Some(wait))?;
+
+        let Some(frame) = poll_json_frame(socket)? else {
+            continue;
+        };
+        if frame.get("type").and_then(Value::as_str) == Some("event")
+            && frame.get("event").and_then(Value::as_str) == Some("connect.challenge")
+        {
+            let nonce = frame
+                .pointer("/payload/nonce")
+                .and_then(Value::as_str)
+                .ok_or_else(|| {
+                    CliError::Protocol("connect.challenge event has no nonce".to_owned())
+                })?;
+            break Some(nonce.to_owned());
+        }
+        early.push_back(frame);
+        break None;
+    };
+
+    set_read_timeout(socket, remaining(limit)?)?;
+    Ok(nonce)
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| {
+            u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX)
+        })
+}
+
+/// Reads the next JSON frame, returning `None` when the poll interval elapses.
+fn poll_json_frame(socket: &mut WsSocket) -> Result<Option<Value>, CliError> {
+    loop {
+        let message = match socket.read() {
+            Ok(message) => message,
+            Err(tungstenite::Error::Io(error)) if is_timeout(&error) => {
+                return Ok(None);
+            }
+            Err(error) => {
+                return Err(CliError::Transport(format!(
+                    "websocket read failed: {error}"
+                )));
+            }
+        };
+
+        match message {
+            Message::Text(text) => {
+                return serde_json::from_str(text.as_ref())
+                    .map(Some)
+                    .map_err(|error| {
+                        CliError::Protocol(format!("invalid websocket frame JSON: {error}"))
+                    });
+            }
+            Message::Binary(_) => {
+                return Err(CliError::Protocol(
+                    "unexpected binary websocket frame".to_owned(),
+                ));
+            }
+            Message::Ping(payload) => {
+                socket.send(Message::Pong(payload)).map_err(|error| {
+                    CliError::Transport(format!("websocket pong failed: {error}"))
+                })?;
+            }
+            Message::Pong(_) => continue,
+            // A gateway restarting mid-handshake closes the socket; that is
+            // a transport failure, so opening the session is retried.
+            Message::Close(_) => {
+                return Err(CliError::Transport(
+                    "websocket closed before response".to_owned(),
+                ));
+            }
+            Message::Frame(_) => continue,
+        }
+    }
+}
+
+fn set_read_timeout(socket: &WsSocket, timeout: Option<Duration>) -> Result<(), CliError> {
+    let stream = match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream,
+        MaybeTlsStream::Rustls(stream) => stream.get_ref(),
+        _ => return Ok(()),
+    };
+
+    stream.configure(timeout, None)
+}
+
+/// The connection under a session: TCP, or a Unix socket for `unix://`
+/// gateways.
+enum Transport {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixStream),
+}
+
+impl Transport {
+    fn configure(
+        &self,
+        read_timeout: Option<Duration>,
+        write_timeout: Option<Duration>,
+    ) -> Result<(), CliError> {
+        match self {
+            Self::Tcp(stream) => configure_stream(stream, read_timeout, write_timeout),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream
+                .set_read_timeout(read_timeout)
+                .and_then(|()| stream.set_write_timeout(write_timeout))
+                .map_err(|error| {
+                    CliError::Transport(format!("failed to configure socket: {error}"))
+                }),
+        }
+    }
+}
+
+impl Read for Transport {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Transport {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            Self::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+fn configure_stream(
+    stream: &TcpStream,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+) -> Result<(), CliError> {
+    stream
+        .set_read_timeout(read_timeout)
+        .and_then(|()| stream.set_write_timeout(write_timeout))
+        .map_err(|error| CliError::Transport(format!("failed to configure socket: {error}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use serde_json::{json, Value};
+    use tungstenite::{accept, Message};
+
+    use super::{lock, CHALLENGE_GRACE};
+    use crate::{
+        timeout::Deadline, CliError, ClientOptions, DeviceIdentity, GatewaySession, ProtocolRange,
+        TimeoutPhase, Timeouts,
+    };
+
+    #[test]
+    fn session_reuses_one_connection_for_many_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            accept_connect(&mut ws);
+
+            let mut ids = Vec::new();
+            for _ in 0..3 {
+                let frame = read_frame(&mut ws);
+                let id = frame["id"].as_str().expect("request id").to_owned();
+                send_response(&mut ws, &id, json!({ "method": frame["method"] }));
+                ids.push(id);
+            }
+            ids
+        });
+
+        let session = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &ClientOptions::default(),
+            Deadline::default(),
+            json!({}),
+        )
+        .expect("session should open");
+        assert_eq!(session.hello().protocol, Some(3));
+        assert_eq!(session.hello().server.version.as_deref(), Some("1.2.3"));
+        for method in ["health", "status", "sessions.list"] {
+            let payload = session
+                .request(method, json!({}))
+                .expect("rpc should succeed");
+            assert_eq!(payload["method"], method);
+        }
+        session.close();
+
+        let ids = server.join().expect("server should finish");
+        assert_eq!(ids, vec!["rpc-1", "rpc-2", "rpc-3"]);
+    }
+
+    #[test]
+    fn session_matches_out_of_order_responses_by_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            accept_connect(&mut ws);
+
+            let first = read_frame(&mut ws);
+            let second = read_frame(&mut ws);
+            for frame in [second, first] {
+                let id = frame["id"].as_str().expect("request id");
+                send_response(&mut ws, id, json!({ "echo": frame["params"]["n"] }));
+            }
+            let _ = ws.read();
+        });
+
+        let session = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &ClientOptions::default(),
+            Deadline::default(),
+            json!({}),
+        )
+        .expect("session should open");
+        let first = session
+            .send("echo", json!({ "n": 1 }))
+            .expect("first request should send");
+        let second = session
+            .send("echo", json!({ "n": 2 }))
+            .expect("second request should send");
+        assert_ne!(first.id(), second.id());
+
+        assert_eq!(second.wait().expect("second response")["echo"], 2);
+        assert_eq!(first.wait().expect("first response")["echo"], 1);
+        session.close();
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn session_fails_pending_requests_when_gateway_disconnects() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            accept_connect(&mut ws);
+            let _ = read_frame(&mut ws);
+            let _ = ws.close(None);
+            let _ = ws.flush();
+        });
+
+        let session = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &ClientOptions::default(),
+            Deadline::default(),
+            json!({}),
+        )
+        .expect("session should open");
+        let result = session.request("health", json!({}));
+        assert!(matches!(
+            result,
+            Err(CliError::Protocol(_) | CliError::Transport(_))
+        ));
+        assert!(session.is_closed());
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn abandoned_requests_release_their_waiters() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            accept_connect(&mut ws);
+            while ws.read().is_ok() {}
+        });
+
+        let options = ClientOptions {
+            timeouts: Timeouts {
+                request: Some(Duration::from_millis(50)),
+                ..Timeouts::default()
+            },
+            ..ClientOptions::default()
+        };
+        let session = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &options,
+            Deadline::default(),
+            json!({}),
+        )
+        .expect("session should open");
+        let pending = session
+            .send("health", json!({}))
+            .expect("request should be sent");
+        assert_eq!(lock(&session.shared).waiters.len(), 1);
+        drop(pending);
+        assert!(lock(&session.shared).waiters.is_empty());
+
+        assert!(matches!(
+            session.request("health", json!({})),
+            Err(CliError::Timeout { .. })
+        ));
+        assert!(lock(&session.shared).waiters.is_empty());
+        session.close();
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn session_delivers_matching_events_to_subscribers() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            accept_connect(&mut ws);
+
+            let frame = read_frame(&mut ws);
+            for (event, seq) in [("tick", 1), ("agent.delta", 2), ("presence", 3)] {
+                ws.send(Message::Text(
+                    json!({ "type": "event", "event": event, "payload": {}, "seq": seq })
+                        .to_string()
+                        .into(),
+                ))
+                .expect("event should be sent");
+            }
+            send_response(&mut ws, frame["id"].as_str().expect("id"), json!({}));
+            let _ = ws.close(None);
+            let _ = ws.flush();
+        });
+
+        let session = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &ClientOptions::default(),
+            Deadline::default(),
+            json!({}),
+        )
+        .expect("session should open");
+        let subscription = session
+            .subscribe(&["agent.*".to_owned(), "presence".to_owned()])
+            .expect("subscription should register");
+        session
+            .request("status", json!({}))
+            .expect("rpc should succeed");
+
+        let first = subscription.recv().expect("first event");
+        let second = subscription.recv().expect("second event");
+        assert_eq!((first.event.as_str(), first.seq), ("agent.delta", Some(2)));
+        assert_eq!((second.event.as_str(), second.seq), ("presence", Some(3)));
+        assert!(subscription.recv().is_err());
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn session_offers_range_and_records_negotiated_protocol() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            let connect_frame = read_frame(&mut ws);
+            assert_eq!(connect_frame["params"]["minProtocol"], 2);
+            assert_eq!(connect_frame["params"]["maxProtocol"], 4);
+            send_response(
+                &mut ws,
+                "connect-1",
+                json!({ "type": "hello-ok", "protocol": 2 }),
+            );
+            let _ = ws.read();
+        });
+
+        let options = ClientOptions {
+            protocol: ProtocolRange::new(2, 4).expect("range should build"),
+            ..ClientOptions::default()
+        };
+        let session = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &options,
+            Deadline::default(),
+            json!({}),
+        )
+        .expect("session should open");
+        assert_eq!(session.protocol(), 2);
+        assert_eq!(session.hello().protocol, Some(2));
+        session.close();
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn session_reports_protocol_mismatch_with_both_ranges() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            let _ = read_frame(&mut ws);
+            ws.send(Message::Text(
+                json!({
+                    "type": "res",
+                    "id": "connect-1",
+                    "ok": false,
+                    "error": {
+                        "code": "INVALID_REQUEST",
+                        "message": "protocol mismatch",
+                        "details": { "expectedProtocol": 3 }
+                    }
+                })
+                .to_string()
+                .into(),
+            ))
+            .expect("connect error should be sent");
+        });
+
+        let options = ClientOptions {
+            protocol: ProtocolRange::new(4, 5).expect("range should build"),
+            ..ClientOptions::default()
+        };
+        let result = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &options,
+            Deadline::default(),
+            json!({}),
+        );
+        match result {
+            Err(error @ CliError::UnsupportedProtocol { .. }) => {
+                let message = error.to_string();
+                assert!(message.contains("4-5"), "{message}");
+                assert!(message.contains("supports 3"), "{message}");
+            }
+            Err(other) => panic!("expected protocol mismatch, got {other:?}"),
+            Ok(_) => panic!("expected protocol mismatch, got a session"),
+        }
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn request_times_out_when_gateway_never_replies() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            accept_connect(&mut ws);
+            let _ = read_frame(&mut ws);
+            let _ = ws.read();
+        });
+
+        let options = ClientOptions {
+            timeouts: Timeouts {
+                request: Some(Duration::from_millis(50)),
+                ..Timeouts::default()
+            },
+            ..ClientOptions::default()
+        };
+        let session = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &options,
+            Deadline::default(),
+            json!({}),
+        )
+        .expect("session should open");
+        let result = session.request("health", json!({}));
+        assert!(matches!(
+            result,
+            Err(CliError::Timeout {
+                phase: TimeoutPhase::Request,
+                ..
+            })
+        ));
+        session.close();
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn handshake_times_out_when_connect_is_never_answered() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            let _ = read_frame(&mut ws);
+            let _ = ws.read();
+        });
+
+        let options = ClientOptions {
+            timeouts: Timeouts {
+                connect: Some(Duration::from_millis(100)),
+                ..Timeouts::default()
+            },
+            ..ClientOptions::default()
+        };
+        let result = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &options,
+            Deadline::default(),
+            json!({}),
+        );
+        assert!(matches!(
+            result,
+            Err(CliError::Timeout {
+                phase: TimeoutPhase::Handshake,
+                ..
+            })
+        ));
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn close_before_hello_ok_is_a_transport_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            let _ = read_frame(&mut ws);
+            ws.close(None).expect("close should be sent");
+            let _ = ws.read();
+        });
+
+        let result = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &ClientOptions::default(),
+            Deadline::default(),
+            json!({}),
+        );
+        assert!(matches!(result, Err(CliError::Transport(_))));
+
+        let _ = server.join();
+    }
+
+    #[test]
+    fn session_signs_connect_challenge_with_device_identity() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            ws.send(Message::Text(
+                json!({
+                    "type": "event",
+                    "event": "connect.challenge",
+                    "payload": { "nonce": "nonce-1", "ts": 1 }
+                })
+                .to_string()
+                .into(),
+            ))
+            .expect("challenge should be sent");
+
+            let connect_frame = read_frame(&mut ws);
+            send_response(
+                &mut ws,
+                "connect-1",
+                json!({ "type": "hello-ok", "protocol": 3 }),
+            );
+            let _ = ws.read();
+            connect_frame
+        });
+
+        let identity = DeviceIdentity::from_secret_key([3; 32]);
+        let options = ClientOptions {
+            device: Some(identity.clone()),
+            ..ClientOptions::default()
+        };
+        let session = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &options,
+            Deadline::default(),
+            json!({ "role": "operator", "client": { "id": "reclaw-cli", "mode": "operator" } }),
+        )
+        .expect("session should open");
+        session.close();
+
+        let connect_frame = server.join().expect("server should finish");
+        let device = &connect_frame["params"]["device"];
+        assert_eq!(device["id"], identity.id());
+        assert_eq!(device["publicKey"], identity.public_key());
+        assert_eq!(device["nonce"], "nonce-1");
+        assert!(device["signature"]
+            .as_str()
+            .is_some_and(|signature| !signature.is_empty()));
+    }
+
+    #[test]
+    fn session_signs_without_nonce_once_the_gateway_speaks_first() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let mut ws = accept(stream).expect("websocket handshake should succeed");
+            ws.send(Message::Text(
+                json!({ "type": "event", "event": "tick", "payload": {} })
+                    .to_string()
+                    .into(),
+            ))
+            .expect("event should be sent");
+
+            let connect_frame = read_frame(&mut ws);
+            send_response(
+                &mut ws,
+                "connect-1",
+                json!({ "type": "hello-ok", "protocol": 3 }),
+            );
+            let _ = ws.read();
+            connect_frame
+        });
+
+        let options = ClientOptions {
+            device: Some(DeviceIdentity::from_secret_key([3; 32])),
+            ..ClientOptions::default()
+        };
+        let started = Instant::now();
+        let session = GatewaySession::open(
+            &format!("ws://{addr}/ws"),
+            &options,
+            Deadline::default(),
+            json!({ "role": "operator", "client": { "id": "reclaw-cli", "mode": "operator" } }),
+        )
+        .expect("session should open");
+        assert!(started.elapsed() < CHALLENGE_GRACE);
+        session.close();
+
+        let connect_frame = server.join().expect("server should finish");
+        assert!(connect_frame["params"]["device"]["signature"].is_string());
+        assert!(connect_frame["params"]["device"].get("nonce").is_none());
+    }
+
+    fn accept_connect<S>(ws: &mut tungstenite::WebSocket<S>)
+    where
+        S: std::io::Read + std::io::Write,
+    {
+        let connect_frame = read_frame(ws);
+        assert_eq!(connect_frame["method"], "connect");
+        send_response(
+            ws,
+            "connect-1",
+            json!({ "type": "hello-ok", "protocol": 3, "server": { "version": "1.2.3" } }),
+        );
+    }
+
+    fn send_response<S>(ws: &mut tungstenite::WebSocket<S>, id: &str, payload: Value)
+    where
+        S: std::io::Read + std::io::Write,
+    {
+        ws.send(Message::Text(
+            json!({ "type": "res", "id": id, "ok": true, "payload": payload })
+                .to_string()
+                .into(),
+        ))
+        .expect("response should be sent");
+    }
+
+    fn read_frame<S>(socket: &mut tungstenite::WebSocket<S>) -> Value
+    where
+        S: std::io::Read + std::io::Write,
+    {
+        let message = socket.read().expect("frame should arrive");
+        let text = message.into_text().expect("frame should be text");
+        serde_json::from_str(text.as_ref()).expect("frame JSON should parse")
+    }
+}
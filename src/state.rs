@@ -0,0 +1,113 @@
+use std::{
+    fs,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::CliError;
+
+const APP_DIR: &str = "reclaw-cli";
+
+/// The directory holding the CLI's persistent local state, such as the
+/// device identity.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateDir {
+    root: PathBuf,
+}
+
+impl StateDir {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// `$XDG_STATE_HOME/reclaw-cli` on Linux, the local application data
+    /// directory elsewhere.
+    pub fn default_location() -> Result<Self, CliError> {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|dir| Self::new(dir.join(APP_DIR)))
+            .ok_or_else(|| {
+                CliError::State("cannot determine a state directory; pass --state-dir".to_owned())
+            })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Reads a state file, returning `None` if it does not exist yet (or
+    /// cannot, because part of the path is a regular file).
+    pub(crate) fn read(&self, name: &str) -> Result<Option<Vec<u8>>, CliError> {
+        let path = self.root.join(name);
+        match fs::read(&path) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(error)
+                if matches!(error.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) =>
+            {
+                Ok(None)
+            }
+            Err(error) => Err(CliError::State(format!(
+                "failed to read {}: {error}",
+                path.display()
+            ))),
+        }
+    }
+
+    /// Atomically replaces a state file with one only the current user can
+    /// read.
+    pub(crate) fn write_private(&self, name: &str, contents: &[u8]) -> Result<(), CliError> {
+        let path = self.root.join(name);
+        write_private_file(&path, contents).map_err(|error| {
+            CliError::State(format!("failed to write {}: {error}", path.display()))
+        })
+    }
+}
+
+/// Atomically replaces `path` with a file only the current user can read,
+/// creating its parent directory if needed.
+pub(crate) fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    create_private_dir(dir)?;
+    let staging = dir.join(format!(".{name}.tmp"));
+    let mut file = private_file(&staging)?;
+    file.write_all(contents)
+        .and_then(|()| file.sync_all())
+        .and_then(|()| fs::rename(&staging, path))
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+#[cfg(unix)]
+fn private_file(path: &Path) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+}
+
+#[cfg(not(unix))]
+fn private_file(path: &Path) -> std::io::Result<fs::File> {
+    fs::File::create(path)
+}
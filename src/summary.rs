@@ -0,0 +1,149 @@
+use serde_json::{Map, Value};
+
+const BOLD: &str = "\x1b[1m";
+const DIM: &str = "\x1b[2m";
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+/// Labels shown in the headline or the fixed lines rather than as fields.
+const HEADLINE_KEYS: &[&str] = &[
+    "ok",
+    "runtime",
+    "version",
+    "server.version",
+    "build.version",
+];
+const UPTIME_MS_KEYS: &[&str] = &["uptimeMs", "uptime_ms"];
+const UPTIME_SECONDS_KEYS: &[&str] = &["uptimeSeconds", "uptime_seconds", "uptime"];
+
+/// `health`: a healthy/unhealthy status line, then the version, uptime and
+/// the payload's other fields.
+pub(crate) fn health(payload: &Value, color: bool) -> String {
+    let ok = payload.get("ok").and_then(Value::as_bool).unwrap_or(false);
+    let (state, tint) = if ok {
+        ("healthy", GREEN)
+    } else {
+        ("unhealthy", RED)
+    };
+    let headline = format!("Gateway {}", paint(state, &[BOLD, tint], color));
+    summary(headline, payload, true, color)
+}
+
+/// `info`: the runtime and version as a headline, then the uptime and the
+/// payload's other fields.
+pub(crate) fn info(payload: &Value, color: bool) -> String {
+    let runtime = payload
+        .get("runtime")
+        .and_then(Value::as_str)
+        .unwrap_or("gateway");
+    let headline = match version(payload) {
+        Some(version) => format!("{runtime} {version}"),
+        None => runtime.to_owned(),
+    };
+    summary(paint(&headline, &[BOLD], color), payload, false, color)
+}
+
+fn summary(headline: String, payload: &Value, with_version: bool, color: bool) -> String {
+    let mut fields = Vec::new();
+    if let Some(version) = version(payload).filter(|_| with_version) {
+        fields.push(("version".to_owned(), version));
+    }
+    if let Some(uptime) = uptime(payload) {
+        fields.push(("uptime".to_owned(), uptime));
+    }
+    if let Value::Object(map) = payload {
+        flatten(&mut fields, "", map, true);
+    }
+
+    let label_width = fields
+        .iter()
+        .map(|(label, _)| label.chars().count())
+        .max()
+        .unwrap_or(0);
+    let mut lines = vec![headline];
+    for (label, value) in fields {
+        let label = format!("{label:<label_width$}");
+        lines.push(format!("  {}  {value}", paint(&label, &[DIM], color)));
+    }
+    lines.join("\n")
+}
+
+/// Adds the scalar fields of `map`, one level of nested objects as dotted
+/// labels, and anything deeper as JSON.
+fn flatten(fields: &mut Vec<(String, String)>, prefix: &str, map: &Map<String, Value>, top: bool) {
+    for (key, value) in map {
+        let label = format!("{prefix}{key}");
+        let uptime = UPTIME_MS_KEYS.contains(&label.as_str())
+            || UPTIME_SECONDS_KEYS.contains(&label.as_str());
+        if HEADLINE_KEYS.contains(&label.as_str()) || (uptime && value.is_number()) {
+            continue;
+        }
+        match value {
+            Value::Object(nested) if top && !nested.is_empty() => {
+                flatten(fields, &format!("{label}."), nested, false);
+            }
+            value => fields.push((label, field(value))),
+        }
+    }
+}
+
+fn field(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => "-".to_owned(),
+        Value::Array(items) if items.iter().all(|item| item.is_string()) => items
+            .iter()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>()
+            .join(", "),
+        value => value.to_string(),
+    }
+}
+
+/// `version`, or the `version` of a `server` or `build` object.
+fn version(payload: &Value) -> Option<String> {
+    ["/version", "/server/version", "/build/version"]
+        .iter()
+        .find_map(|pointer| payload.pointer(pointer))
+        .map(field)
+}
+
+/// The uptime from `uptimeMs` (milliseconds) or `uptimeSeconds`/`uptime`
+/// (seconds), as e.g. `3d 4h 12m`.
+fn uptime(payload: &Value) -> Option<String> {
+    let seconds = UPTIME_MS_KEYS
+        .iter()
+        .find_map(|key| payload.get(key)?.as_f64())
+        .map(|millis| millis / 1000.0)
+        .or_else(|| {
+            UPTIME_SECONDS_KEYS
+                .iter()
+                .find_map(|key| payload.get(key)?.as_f64())
+        })?;
+    if !seconds.is_finite() || seconds < 0.0 {
+        return None;
+    }
+
+    let total = seconds as u64;
+    let (days, hours, minutes, seconds) = (
+        total / 86_400,
+        total / 3_600 % 24,
+        total / 60 % 60,
+        total % 60,
+    );
+    Some(match (days, hours, minutes) {
+        (0, 0, 0) => format!("{seconds}s"),
+        (0, 0, _) => format!("{minutes}m {seconds}s"),
+        (0, _, _) => format!("{hours}h {minutes}m"),
+        _ => format!("{days}d {hours}h {minutes}m"),
+    })
+}
+
+fn paint(text: &str, styles: &[&str], color: bool) -> String {
+    if color {
+        format!("{}{text}{RESET}", styles.concat())
+    } else {
+        text.to_owned()
+    }
+}
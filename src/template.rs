@@ -0,0 +1,998 @@
+use serde_json::Value;
+
+use crate::CliError;
+
+/// A `--template`: a subset of Go's `text/template` over a JSON value.
+///
+/// Actions are `{{pipeline}}`, `{{if}}`/`{{else if}}`/`{{else}}`/`{{end}}`,
+/// `{{range}}` (with optional `$index, $element :=`), `{{with}}` and
+/// `{{/* comments */}}`; `{{-` and `-}}` trim the whitespace beside them.
+/// A pipeline is `|`-separated commands: a value (`.`, `.a.b`, `$`, `$x.a`,
+/// a string, number, `true`, `false`, `nil` or a parenthesized pipeline) or
+/// a function call, which gets the previous command's result as its last
+/// argument.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    nodes: Vec<Node>,
+}
+
+const FUNCTIONS: &[&str] = &[
+    "and", "default", "eq", "ge", "gt", "index", "join", "json", "le", "len", "lower", "lt", "ne",
+    "not", "or", "trim", "upper",
+];
+
+impl Template {
+    pub fn parse(source: &str) -> Result<Self, CliError> {
+        let mut parser = Parser {
+            items: split(source)?,
+            position: 0,
+        };
+        let (nodes, end) = parser.block()?;
+        match end {
+            Closer::Eof => Ok(Self { nodes }),
+            Closer::End => Err(template_error("unexpected {{end}}")),
+            Closer::Else(_) => Err(template_error("unexpected {{else}}")),
+        }
+    }
+
+    /// Renders `value` as the template's dot (and `$`).
+    pub fn render(&self, value: &Value) -> Result<String, CliError> {
+        let mut scope = Scope {
+            root: value,
+            variables: Vec::new(),
+        };
+        let mut out = String::new();
+        render_nodes(&self.nodes, value, &mut scope, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn template_error(message: impl Into<String>) -> CliError {
+    CliError::Template(message.into())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Text(String),
+    Print(Pipeline),
+    If {
+        condition: Pipeline,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    With {
+        value: Pipeline,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    Range {
+        variables: Vec<String>,
+        items: Pipeline,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// Commands joined by `|`, with the action's text for error messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pipeline {
+    source: String,
+    commands: Vec<Command>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Command {
+    Value(Operand),
+    Call(String, Vec<Operand>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Operand {
+    /// `.a.b` (no segments for `.`), with its text for error messages.
+    Field(String, Vec<String>),
+    /// `$name.a.b`; the name is empty for `$`.
+    Variable(String, Vec<String>),
+    Literal(Value),
+    Pipeline(Box<Pipeline>),
+}
+
+/// A piece of the template before parsing: literal text or the inside of
+/// an action.
+enum Item {
+    Text(String),
+    Action(String),
+}
+
+/// Splits `source` into text and actions, applying `{{-`/`-}}` trimming
+/// and dropping comments.
+fn split(source: &str) -> Result<Vec<Item>, CliError> {
+    let mut items = Vec::new();
+    let mut rest = source;
+    let mut trim_next = false;
+    while let Some(start) = rest.find("{{") {
+        let mut text = &rest[..start];
+        if trim_next {
+            text = text.trim_start();
+        }
+        let mut inner = &rest[start + 2..];
+        if let Some(stripped) = inner
+            .strip_prefix('-')
+            .filter(|s| s.starts_with(char::is_whitespace))
+        {
+            text = text.trim_end();
+            inner = stripped;
+        }
+        if !text.is_empty() {
+            items.push(Item::Text(text.to_owned()));
+        }
+
+        let end = action_end(inner)
+            .ok_or_else(|| template_error(format!("unclosed action `{{{{{}`", inner.trim_end())))?;
+        let mut action = &inner[..end];
+        rest = &inner[end + 2..];
+        trim_next = false;
+        if let Some(stripped) = action
+            .strip_suffix('-')
+            .filter(|s| s.ends_with(char::is_whitespace))
+        {
+            action = stripped;
+            trim_next = true;
+        }
+
+        let action = action.trim();
+        if action.starts_with("/*") {
+            if !action.ends_with("*/") {
+                return Err(template_error(format!(
+                    "unclosed comment `{{{{{action}}}}}`"
+                )));
+            }
+            continue;
+        }
+        items.push(Item::Action(action.to_owned()));
+    }
+    let text = if trim_next { rest.trim_start() } else { rest };
+    if !text.is_empty() {
+        items.push(Item::Text(text.to_owned()));
+    }
+    Ok(items)
+}
+
+/// The offset of the `}}` closing an action, skipping over quoted strings.
+fn action_end(inner: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, ch) in inner.char_indices() {
+        match quote {
+            Some(_) if escaped => escaped = false,
+            Some('"') if ch == '\\' => escaped = true,
+            Some(open) if ch == open => quote = None,
+            Some(_) => {}
+            None if ch == '"' || ch == '`' => quote = Some(ch),
+            None if inner[index..].starts_with("}}") => return Some(index),
+            None => {}
+        }
+    }
+    None
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Field(String),
+    Variable(String),
+    Literal(Value),
+    Identifier(String),
+    Pipe,
+    LParen,
+    RParen,
+    Comma,
+    Declare,
+}
+
+fn tokenize(action: &str) -> Result<Vec<Token>, CliError> {
+    let chars: Vec<char> = action.chars().collect();
+    let mut tokens = Vec::new();
+    let mut index = 0;
+    let word = |index: &mut usize, allow_dots: bool| {
+        let start = *index;
+        while chars
+            .get(*index)
+            .is_some_and(|ch| ch.is_alphanumeric() || *ch == '_' || (allow_dots && *ch == '.'))
+        {
+            *index += 1;
+        }
+        chars[start..*index].iter().collect::<String>()
+    };
+    while let Some(&ch) = chars.get(index) {
+        match ch {
+            ch if ch.is_whitespace() => index += 1,
+            '|' => {
+                tokens.push(Token::Pipe);
+                index += 1;
+            }
+            '(' => {
+                tokens.push(Token::LParen);
+                index += 1;
+            }
+            ')' => {
+                tokens.push(Token::RParen);
+                index += 1;
+            }
+            ',' => {
+                tokens.push(Token::Comma);
+                index += 1;
+            }
+            ':' if chars.get(index + 1) == Some(&'=') => {
+                tokens.push(Token::Declare);
+                index += 2;
+            }
+            '.' => {
+                index += 1;
+                let path = word(&mut index, true);
+                tokens.push(Token::Field(format!(".{path}")));
+            }
+            '$' => {
+                index += 1;
+                let name = word(&mut index, true);
+                tokens.push(Token::Variable(format!("${name}")));
+            }
+            '"' | '`' => {
+                let start = index;
+                index += 1;
+                while chars.get(index).is_some_and(|next| *next != ch) {
+                    index += if ch == '"' && chars[index] == '\\' {
+                        2
+                    } else {
+                        1
+                    };
+                }
+                if index >= chars.len() {
+                    return Err(template_error(format!(
+                        "unterminated string in `{{{{{action}}}}}`"
+                    )));
+                }
+                index += 1;
+                let text: String = chars[start..index].iter().collect();
+                let value = if ch == '"' {
+                    serde_json::from_str(&text).map_err(|error| {
+                        template_error(format!("invalid string {text}: {error}"))
+                    })?
+                } else {
+                    Value::String(text[1..text.len() - 1].to_owned())
+                };
+                tokens.push(Token::Literal(value));
+            }
+            ch if ch == '-' || ch.is_ascii_digit() => {
+                let start = index;
+                index += 1;
+                while chars
+                    .get(index)
+                    .is_some_and(|ch| ch.is_ascii_digit() || *ch == '.')
+                {
+                    index += 1;
+                }
+                let text: String = chars[start..index].iter().collect();
+                let number: serde_json::Number = text
+                    .parse()
+                    .map_err(|_| template_error(format!("invalid number `{text}`")))?;
+                tokens.push(Token::Literal(Value::Number(number)));
+            }
+            ch if ch.is_alphabetic() || ch == '_' => {
+                let name = word(&mut index, false);
+                tokens.push(match name.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "nil" => Token::Literal(Value::Null),
+                    _ => Token::Identifier(name),
+                });
+            }
+            ch => {
+                return Err(template_error(format!(
+                    "unexpected `{ch}` in `{{{{{action}}}}}`"
+                )))
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// What ended a block of nodes.
+enum Closer {
+    Eof,
+    End,
+    /// `{{else}}`, or `{{else if ...}}` with the tokens after `else`.
+    Else(Option<(String, Vec<Token>)>),
+}
+
+struct Parser {
+    items: Vec<Item>,
+    position: usize,
+}
+
+impl Parser {
+    /// Parses nodes up to the end of the template or an `{{else}}`/`{{end}}`.
+    fn block(&mut self) -> Result<(Vec<Node>, Closer), CliError> {
+        let mut nodes = Vec::new();
+        while let Some(item) = self.items.get(self.position) {
+            self.position += 1;
+            let action = match item {
+                Item::Text(text) => {
+                    nodes.push(Node::Text(text.clone()));
+                    continue;
+                }
+                Item::Action(action) => action.clone(),
+            };
+
+            let tokens = tokenize(&action)?;
+            let keyword = match tokens.first() {
+                Some(Token::Identifier(name)) => name.as_str(),
+                _ => "",
+            };
+            let rest = || tokens[1..].to_vec();
+            let source = || action[keyword.len()..].trim().to_owned();
+            match keyword {
+                "end" if tokens.len() == 1 => return Ok((nodes, Closer::End)),
+                "else" if tokens.len() == 1 => return Ok((nodes, Closer::Else(None))),
+                "else" => return Ok((nodes, Closer::Else(Some((source(), rest()))))),
+                "if" => nodes.push(self.conditional(&action, source(), rest())?),
+                "with" => {
+                    let value = pipeline(source(), &rest())?;
+                    let (body, otherwise) = self.body(&action)?;
+                    nodes.push(Node::With {
+                        value,
+                        body,
+                        otherwise,
+                    });
+                }
+                "range" => {
+                    let mut tokens = rest();
+                    let mut variables = Vec::new();
+                    if let Some(declare) = tokens.iter().position(|token| *token == Token::Declare)
+                    {
+                        for token in tokens.drain(..=declare) {
+                            match token {
+                                Token::Variable(name) if !name.contains('.') => {
+                                    variables.push(name)
+                                }
+                                Token::Comma | Token::Declare => {}
+                                _ => {
+                                    return Err(template_error(format!(
+                                        "invalid range variables in `{{{{{action}}}}}`"
+                                    )))
+                                }
+                            }
+                        }
+                        if variables.is_empty() || variables.len() > 2 {
+                            return Err(template_error(format!(
+                                "range takes one or two variables in `{{{{{action}}}}}`"
+                            )));
+                        }
+                    }
+                    let source = source();
+                    let source = source
+                        .split_once(":=")
+                        .map_or(source.as_str(), |(_, rest)| rest.trim())
+                        .to_owned();
+                    let items = pipeline(source, &tokens)?;
+                    let (body, otherwise) = self.body(&action)?;
+                    nodes.push(Node::Range {
+                        variables,
+                        items,
+                        body,
+                        otherwise,
+                    });
+                }
+                _ => nodes.push(Node::Print(pipeline(action.clone(), &tokens)?)),
+            }
+        }
+        Ok((nodes, Closer::Eof))
+    }
+
+    /// `{{if}}` after its keyword; an `{{else if}}` becomes a nested `If`
+    /// that shares the closing `{{end}}`.
+    fn conditional(
+        &mut self,
+        action: &str,
+        source: String,
+        tokens: Vec<Token>,
+    ) -> Result<Node, CliError> {
+        let condition = pipeline(source, &tokens)?;
+        let (body, end) = self.block()?;
+        let otherwise = match end {
+            Closer::End => Vec::new(),
+            Closer::Else(None) => {
+                let (otherwise, end) = self.block()?;
+                if !matches!(end, Closer::End) {
+                    return Err(unclosed(action));
+                }
+                otherwise
+            }
+            Closer::Else(Some((source, tokens))) => match tokens.split_first() {
+                Some((Token::Identifier(keyword), rest)) if keyword == "if" => {
+                    let source = source["if".len()..].trim().to_owned();
+                    vec![self.conditional(action, source, rest.to_vec())?]
+                }
+                _ => {
+                    return Err(template_error(format!(
+                        "unexpected `{{{{else {source}}}}}`"
+                    )))
+                }
+            },
+            Closer::Eof => return Err(unclosed(action)),
+        };
+        Ok(Node::If {
+            condition,
+            body,
+            otherwise,
+        })
+    }
+
+    /// The body and optional `{{else}}` part of a `with` or `range`.
+    fn body(&mut self, action: &str) -> Result<(Vec<Node>, Vec<Node>), CliError> {
+        let (body, end) = self.block()?;
+        match end {
+            Closer::End => Ok((body, Vec::new())),
+            Closer::Else(None) => {
+                let (otherwise, end) = self.block()?;
+                match end {
+                    Closer::End => Ok((body, otherwise)),
+                    _ => Err(unclosed(action)),
+                }
+            }
+            Closer::Else(Some((source, _))) => Err(template_error(format!(
+                "unexpected `{{{{else {source}}}}}`"
+            ))),
+            Closer::Eof => Err(unclosed(action)),
+        }
+    }
+}
+
+fn unclosed(action: &str) -> CliError {
+    template_error(format!("missing {{{{end}}}} for `{{{{{action}}}}}`"))
+}
+
+fn pipeline(source: String, tokens: &[Token]) -> Result<Pipeline, CliError> {
+    if tokens.is_empty() {
+        return Err(template_error(format!(
+            "missing value in `{{{{{source}}}}}`"
+        )));
+    }
+
+    let mut commands = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::LParen => depth += 1,
+            Token::RParen if depth == 0 => {
+                return Err(template_error(format!(
+                    "unexpected `)` in `{{{{{source}}}}}`"
+                )))
+            }
+            Token::RParen => depth -= 1,
+            Token::Pipe if depth == 0 => {
+                commands.push(command(&source, &tokens[start..index])?);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    if depth != 0 {
+        return Err(template_error(format!(
+            "unclosed `(` in `{{{{{source}}}}}`"
+        )));
+    }
+    commands.push(command(&source, &tokens[start..])?);
+    Ok(Pipeline { source, commands })
+}
+
+fn command(source: &str, tokens: &[Token]) -> Result<Command, CliError> {
+    let mut operands = Vec::new();
+    let mut index = 0;
+    let name = match tokens.first() {
+        Some(Token::Identifier(name)) => {
+            if !FUNCTIONS.contains(&name.as_str()) {
+                return Err(template_error(format!("unknown function `{name}`")));
+            }
+            index = 1;
+            Some(name.clone())
+        }
+        _ => None,
+    };
+    while index < tokens.len() {
+        let operand = match &tokens[index] {
+            Token::Field(path) => Operand::Field(path.clone(), segments(path)),
+            Token::Variable(path) => {
+                let mut parts = path.splitn(2, '.');
+                let name = parts.next().unwrap_or_default().to_owned();
+                let rest = parts.next().map(|rest| format!(".{rest}")).unwrap_or_default();
+                Operand::Variable(name, segments(&rest))
+            }
+            Token::Literal(value) => Operand::Literal(value.clone()),
+            Token::LParen => {
+                let mut depth = 0;
+                let close = tokens[index..]
+                    .iter()
+                    .position(|token| {
+                        match token {
+                            Token::LParen => depth += 1,
+                            Token::RParen => depth -= 1,
+                            _ => {}
+                        }
+                        depth == 0
+                    })
+                    .map(|offset| index + offset)
+                    .ok_or_else(|| template_error(format!("unclosed `(` in `{{{{{source}}}}}`")))?;
+                let inner = pipeline(source.to_owned(), &tokens[index + 1..close])?;
+                index = close;
+                Operand::Pipeline(Box::new(inner))
+            }
+            Token::Identifier(name) => {
+                return Err(template_error(format!(
+                    "function `{name}` must come first in a command; use parentheses in `{{{{{source}}}}}`"
+                )))
+            }
+            _ => {
+                return Err(template_error(format!(
+                    "unexpected token in `{{{{{source}}}}}`"
+                )))
+            }
+        };
+        operands.push(operand);
+        index += 1;
+    }
+
+    match name {
+        Some(name) => Ok(Command::Call(name, operands)),
+        None if operands.len() == 1 => Ok(Command::Value(operands.remove(0))),
+        None => Err(template_error(format!(
+            "several values without a function in `{{{{{source}}}}}`"
+        ))),
+    }
+}
+
+fn segments(path: &str) -> Vec<String> {
+    path.split('.')
+        .filter(|segment| !segment.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+struct Scope<'a> {
+    root: &'a Value,
+    variables: Vec<(String, Value)>,
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    dot: &Value,
+    scope: &mut Scope<'_>,
+    out: &mut String,
+) -> Result<(), CliError> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Print(pipeline) => out.push_str(&print(&eval(pipeline, dot, scope)?)),
+            Node::If {
+                condition,
+                body,
+                otherwise,
+            } => {
+                let branch = if is_truthy(&eval(condition, dot, scope)?) {
+                    body
+                } else {
+                    otherwise
+                };
+                render_nodes(branch, dot, scope, out)?;
+            }
+            Node::With {
+                value,
+                body,
+                otherwise,
+            } => {
+                let value = eval(value, dot, scope)?;
+                if is_truthy(&value) {
+                    render_nodes(body, &value, scope, out)?;
+                } else {
+                    render_nodes(otherwise, dot, scope, out)?;
+                }
+            }
+            Node::Range {
+                variables,
+                items,
+                body,
+                otherwise,
+            } => {
+                let entries: Vec<(Value, Value)> = match eval(items, dot, scope)? {
+                    Value::Array(elements) => elements
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, element)| (Value::from(index), element))
+                        .collect(),
+                    Value::Object(map) => map
+                        .into_iter()
+                        .map(|(key, element)| (Value::String(key), element))
+                        .collect(),
+                    Value::Null => Vec::new(),
+                    other => {
+                        return Err(template_error(format!(
+                            "cannot range over `{}`: it is a {}",
+                            items.source,
+                            type_name(&other)
+                        )))
+                    }
+                };
+                if entries.is_empty() {
+                    render_nodes(otherwise, dot, scope, out)?;
+                }
+                for (key, element) in entries {
+                    let depth = scope.variables.len();
+                    match variables.as_slice() {
+                        [value] => scope.variables.push((value.clone(), element.clone())),
+                        [index, value] => {
+                            scope.variables.push((index.clone(), key));
+                            scope.variables.push((value.clone(), element.clone()));
+                        }
+                        _ => {}
+                    }
+                    let result = render_nodes(body, &element, scope, out);
+                    scope.variables.truncate(depth);
+                    result?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn eval(pipeline: &Pipeline, dot: &Value, scope: &Scope<'_>) -> Result<Value, CliError> {
+    let mut piped = None;
+    for command in &pipeline.commands {
+        let value = match command {
+            Command::Value(operand) => {
+                if piped.is_some() {
+                    return Err(template_error(format!(
+                        "only functions can follow `|` in `{{{{{}}}}}`",
+                        pipeline.source
+                    )));
+                }
+                operand_value(operand, dot, scope)?
+            }
+            Command::Call(name, operands) => {
+                let mut arguments = operands
+                    .iter()
+                    .map(|operand| operand_value(operand, dot, scope))
+                    .collect::<Result<Vec<_>, _>>()?;
+                arguments.extend(piped.take());
+                call(name, arguments)?
+            }
+        };
+        piped = Some(value);
+    }
+    Ok(piped.unwrap_or(Value::Null))
+}
+
+fn operand_value(operand: &Operand, dot: &Value, scope: &Scope<'_>) -> Result<Value, CliError> {
+    match operand {
+        Operand::Field(source, path) => lookup(dot, path, source, "."),
+        Operand::Variable(name, path) => {
+            let base = if name == "$" {
+                scope.root
+            } else {
+                scope
+                    .variables
+                    .iter()
+                    .rev()
+                    .find(|(variable, _)| variable == name)
+                    .map(|(_, value)| value)
+                    .ok_or_else(|| template_error(format!("undefined variable `{name}`")))?
+            };
+            let source = format!("{name}.{}", path.join("."));
+            lookup(base, path, &source, name)
+        }
+        Operand::Literal(value) => Ok(value.clone()),
+        Operand::Pipeline(pipeline) => eval(pipeline, dot, scope),
+    }
+}
+
+/// Follows `path` from `base`. A missing key is `null`; reading a field of
+/// a string, number or boolean is an error naming the field.
+fn lookup(base: &Value, path: &[String], source: &str, root: &str) -> Result<Value, CliError> {
+    let mut value = base;
+    for (depth, segment) in path.iter().enumerate() {
+        value = match value {
+            Value::Object(map) => map.get(segment).unwrap_or(&Value::Null),
+            Value::Array(items) => match segment.parse::<usize>() {
+                Ok(index) => items.get(index).unwrap_or(&Value::Null),
+                Err(_) => return Err(not_an_object(source, root, &path[..depth], value)),
+            },
+            Value::Null => &Value::Null,
+            other => return Err(not_an_object(source, root, &path[..depth], other)),
+        };
+    }
+    Ok(value.clone())
+}
+
+fn not_an_object(source: &str, root: &str, parent: &[String], value: &Value) -> CliError {
+    let parent = match (root, parent) {
+        (".", []) => ".".to_owned(),
+        (".", parent) => format!(".{}", parent.join(".")),
+        (root, []) => root.to_owned(),
+        (root, parent) => format!("{root}.{}", parent.join(".")),
+    };
+    template_error(format!(
+        "cannot read `{source}`: `{parent}` is a {}",
+        type_name(value)
+    ))
+}
+
+/// Go's truthiness: `false`, `0`, `nil` and empty strings, lists and maps
+/// are false.
+fn is_truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(flag) => *flag,
+        Value::Number(number) => number.as_f64() != Some(0.0),
+        Value::String(text) => !text.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+/// How an action prints a value: strings bare, `nil` as nothing, lists and
+/// maps as JSON.
+fn print(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "nil",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "list",
+        Value::Object(_) => "map",
+    }
+}
+
+fn call(name: &str, arguments: Vec<Value>) -> Result<Value, CliError> {
+    let count = |expected: usize| {
+        if arguments.len() == expected {
+            Ok(())
+        } else {
+            Err(template_error(format!(
+                "{name} takes {expected} argument{}, got {}",
+                if expected == 1 { "" } else { "s" },
+                arguments.len()
+            )))
+        }
+    };
+    let ordering = |left: &Value, right: &Value| match (left, right) {
+        (Value::Number(left), Value::Number(right)) => left.as_f64().partial_cmp(&right.as_f64()),
+        (Value::String(left), Value::String(right)) => Some(left.cmp(right)),
+        _ => None,
+    };
+
+    Ok(match name {
+        // `default FALLBACK VALUE`, usually as `VALUE | default FALLBACK`.
+        "default" => {
+            count(2)?;
+            let [fallback, value] = <[Value; 2]>::try_from(arguments).unwrap_or_default();
+            if is_truthy(&value) {
+                value
+            } else {
+                fallback
+            }
+        }
+        "and" | "or" => {
+            if arguments.is_empty() {
+                return Err(template_error(format!(
+                    "{name} needs at least one argument"
+                )));
+            }
+            let stop = |value: &Value| is_truthy(value) == (name == "or");
+            let last = arguments.len() - 1;
+            arguments
+                .into_iter()
+                .enumerate()
+                .find(|(index, value)| stop(value) || *index == last)
+                .map(|(_, value)| value)
+                .unwrap_or(Value::Null)
+        }
+        "not" => {
+            count(1)?;
+            Value::Bool(!is_truthy(&arguments[0]))
+        }
+        "eq" | "ne" => {
+            count(2)?;
+            let equal = match (&arguments[0], &arguments[1]) {
+                (Value::Number(left), Value::Number(right)) => left.as_f64() == right.as_f64(),
+                (left, right) => left == right,
+            };
+            Value::Bool(equal == (name == "eq"))
+        }
+        "lt" | "le" | "gt" | "ge" => {
+            count(2)?;
+            let ordering = ordering(&arguments[0], &arguments[1]).ok_or_else(|| {
+                template_error(format!(
+                    "{name} compares two numbers or two strings, got {} and {}",
+                    type_name(&arguments[0]),
+                    type_name(&arguments[1])
+                ))
+            })?;
+            Value::Bool(match name {
+                "lt" => ordering.is_lt(),
+                "le" => ordering.is_le(),
+                "gt" => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            })
+        }
+        "len" => {
+            count(1)?;
+            Value::from(match &arguments[0] {
+                Value::String(text) => text.chars().count(),
+                Value::Array(items) => items.len(),
+                Value::Object(map) => map.len(),
+                Value::Null => 0,
+                other => {
+                    return Err(template_error(format!(
+                        "len of a {} is undefined",
+                        type_name(other)
+                    )))
+                }
+            })
+        }
+        // `join SEPARATOR LIST`, usually as `LIST | join SEPARATOR`.
+        "join" => {
+            count(2)?;
+            let items = match (&arguments[0], &arguments[1]) {
+                (Value::String(_), Value::Array(items)) => items.as_slice(),
+                (Value::String(_), Value::Null) => &[],
+                (separator, items) => {
+                    return Err(template_error(format!(
+                        "join takes a separator and a list, got {} and {}",
+                        type_name(separator),
+                        type_name(items)
+                    )))
+                }
+            };
+            let separator = print(&arguments[0]);
+            Value::String(items.iter().map(print).collect::<Vec<_>>().join(&separator))
+        }
+        "index" => {
+            let Some((base, keys)) = arguments.split_first() else {
+                return Err(template_error("index needs a value"));
+            };
+            let mut value = base.clone();
+            for key in keys {
+                value = match (&value, key) {
+                    (Value::Object(map), Value::String(key)) => {
+                        map.get(key).cloned().unwrap_or(Value::Null)
+                    }
+                    (Value::Array(items), Value::Number(index)) => index
+                        .as_u64()
+                        .and_then(|index| items.get(usize::try_from(index).ok()?))
+                        .cloned()
+                        .unwrap_or(Value::Null),
+                    (Value::Null, _) => Value::Null,
+                    (value, key) => {
+                        return Err(template_error(format!(
+                            "cannot index a {} with {key}",
+                            type_name(value)
+                        )))
+                    }
+                };
+            }
+            value
+        }
+        "json" => {
+            count(1)?;
+            Value::String(arguments[0].to_string())
+        }
+        "upper" | "lower" | "trim" => {
+            count(1)?;
+            let text = print(&arguments[0]);
+            Value::String(match name {
+                "upper" => text.to_uppercase(),
+                "lower" => text.to_lowercase(),
+                _ => text.trim().to_owned(),
+            })
+        }
+        _ => return Err(template_error(format!("unknown function `{name}`"))),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::{CliError, Template};
+
+    fn render(template: &str, value: &serde_json::Value) -> String {
+        Template::parse(template)
+            .expect("template should parse")
+            .render(value)
+            .expect("template should render")
+    }
+
+    #[test]
+    fn templates_print_fields_with_range_if_and_default() {
+        let status = json!({
+            "sessionKey": "main",
+            "updatedAt": "2026-10-16T09:00:00Z",
+            "sessions": [
+                { "id": "a", "state": "idle", "tags": ["x", "y"] },
+                { "id": "b", "state": "busy", "model": null }
+            ],
+            "counts": { "idle": 1, "busy": 1 }
+        });
+
+        assert_eq!(
+            render("{{.sessionKey}} {{.updatedAt}}", &status),
+            "main 2026-10-16T09:00:00Z"
+        );
+        assert_eq!(
+            render(
+                "{{range $i, $s := .sessions}}{{if $i}}, {{end}}{{.id}}={{.state | upper}}{{end}}",
+                &status
+            ),
+            "a=IDLE, b=BUSY"
+        );
+        assert_eq!(
+            render(
+                "{{range .sessions -}}\n  {{.model | default \"auto\"}} [{{join \",\" .tags}}]\n{{- end}}",
+                &status
+            ),
+            "auto [x,y]auto []"
+        );
+        assert_eq!(
+            render(
+                "{{if eq (len .sessions) 0}}none{{else if gt .counts.busy 0}}busy{{else}}idle{{end}}",
+                &status
+            ),
+            "busy"
+        );
+        assert_eq!(
+            render(
+                "{{with .missing}}{{.}}{{else}}-{{end}} {{/* note */}}{{$.sessionKey}}",
+                &status
+            ),
+            "- main"
+        );
+        assert_eq!(
+            render("{{range $k, $v := .counts}}{{$k}}:{{$v}} {{end}}", &status),
+            "busy:1 idle:1 "
+        );
+    }
+
+    #[test]
+    fn template_errors_name_the_offending_field() {
+        let value = json!({ "sessionKey": "main", "sessions": 3 });
+        let error = |template: &str| match Template::parse(template)
+            .and_then(|template| template.render(&value))
+        {
+            Err(CliError::Template(message)) => message,
+            other => panic!("{template:?} should fail, got {other:?}"),
+        };
+
+        assert_eq!(
+            error("{{.sessionKey.name}}"),
+            "cannot read `.sessionKey.name`: `.sessionKey` is a string"
+        );
+        assert_eq!(
+            error("{{range .sessions}}{{end}}"),
+            "cannot range over `.sessions`: it is a number"
+        );
+        assert_eq!(error("{{.sessionKey"), "unclosed action `{{.sessionKey`");
+        assert_eq!(
+            error("{{if .sessions}}yes"),
+            "missing {{end}} for `{{if .sessions}}`"
+        );
+        assert_eq!(error("{{shout .sessionKey}}"), "unknown function `shout`");
+        assert_eq!(error("{{end}}"), "unexpected {{end}}");
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+use crate::CliError;
+
+/// The stage of a gateway call that ran out of time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    /// Opening the TCP connection.
+    Connect,
+    /// The WebSocket upgrade and the `connect` handshake.
+    Handshake,
+    /// Waiting for an HTTP response or an RPC reply.
+    Request,
+    /// The overall `--deadline` for the invocation.
+    Deadline,
+}
+
+impl fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Connect => "connect",
+            Self::Handshake => "handshake",
+            Self::Request => "request",
+            Self::Deadline => "overall deadline",
+        })
+    }
+}
+
+/// Per-phase timeouts plus an overall deadline. `None` means unbounded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    /// Bounds the TCP connect and, separately, the WebSocket and `connect`
+    /// handshake.
+    pub connect: Option<Duration>,
+    /// Bounds each HTTP request and each RPC reply.
+    pub request: Option<Duration>,
+    /// Bounds everything a client does, measured from its construction.
+    pub deadline: Option<Duration>,
+}
+
+/// The instant at which the overall deadline expires.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Deadline {
+    expires: Option<(Instant, Duration)>,
+}
+
+/// The tighter of a phase timeout and the overall deadline.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Limit {
+    expires: Instant,
+    after: Duration,
+    phase: TimeoutPhase,
+}
+
+impl Deadline {
+    pub(crate) fn start(total: Option<Duration>) -> Self {
+        Self {
+            expires: total.map(|total| (Instant::now() + total, total)),
+        }
+    }
+
+    /// Combines `timeout` for `phase` with the overall deadline, failing if
+    /// the deadline has already passed.
+    pub(crate) fn limit(
+        &self,
+        timeout: Option<Duration>,
+        phase: TimeoutPhase,
+    ) -> Result<Option<Limit>, CliError> {
+        let now = Instant::now();
+        let phase_limit = timeout.map(|after| Limit {
+            expires: now + after,
+            after,
+            phase,
+        });
+        let overall = self.expires.map(|(expires, after)| Limit {
+            expires,
+            after,
+            phase: TimeoutPhase::Deadline,
+        });
+
+        if let Some(overall) = overall.filter(|overall| overall.expires <= now) {
+            return Err(overall.error());
+        }
+
+        Ok(match (phase_limit, overall) {
+            (Some(phase_limit), Some(overall)) if overall.expires < phase_limit.expires => {
+                Some(overall)
+            }
+            (phase_limit, overall) => phase_limit.or(overall),
+        })
+    }
+}
+
+impl Limit {
+    /// Time left before this limit expires; an error once it has.
+    pub(crate) fn remaining(&self) -> Result<Duration, CliError> {
+        let remaining = self.expires.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            Err(self.error())
+        } else {
+            Ok(remaining)
+        }
+    }
+
+    pub(crate) fn error(&self) -> CliError {
+        CliError::Timeout {
+            phase: self.phase,
+            after: self.after,
+        }
+    }
+}
+
+pub(crate) fn remaining(limit: Option<Limit>) -> Result<Option<Duration>, CliError> {
+    limit.map(|limit| limit.remaining()).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        timeout::{Deadline, TimeoutPhase},
+        CliError,
+    };
+
+    #[test]
+    fn limit_prefers_phase_timeout_when_tighter() {
+        let deadline = Deadline::start(Some(Duration::from_secs(60)));
+        let limit = deadline
+            .limit(Some(Duration::from_secs(5)), TimeoutPhase::Request)
+            .expect("deadline should not have expired")
+            .expect("limit should be set");
+
+        assert!(matches!(
+            limit.error(),
+            CliError::Timeout {
+                phase: TimeoutPhase::Request,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn limit_reports_deadline_when_it_expires_first() {
+        let deadline = Deadline::start(Some(Duration::from_secs(1)));
+        let limit = deadline
+            .limit(Some(Duration::from_secs(30)), TimeoutPhase::Connect)
+            .expect("deadline should not have expired")
+            .expect("limit should be set");
+
+        assert!(matches!(
+            limit.error(),
+            CliError::Timeout {
+                phase: TimeoutPhase::Deadline,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn expired_deadline_fails_immediately() {
+        let deadline = Deadline::start(Some(Duration::ZERO));
+        let result = deadline.limit(None, TimeoutPhase::Request);
+        assert!(matches!(
+            result,
+            Err(CliError::Timeout {
+                phase: TimeoutPhase::Deadline,
+                ..
+            })
+        ));
+
+        let unbounded = Deadline::default()
+            .limit(None, TimeoutPhase::Request)
+            .expect("no deadline configured");
+        assert!(unbounded.is_none());
+    }
+}
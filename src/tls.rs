@@ -0,0 +1,351 @@
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::ring,
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use sha2::{Digest, Sha256};
+
+use crate::CliError;
+
+/// Where the TLS trust anchors, client identity and pins come from.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TlsOptions {
+    /// PEM files whose certificates replace the built-in web PKI roots.
+    pub ca_certs: Vec<PathBuf>,
+    /// PEM certificate chain and private key presented for mutual TLS.
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// SHA-256 fingerprints of the server's leaf certificate, in hex with
+    /// optional `:` separators. The certificate must still chain to a
+    /// trusted root and must match one of them.
+    pub pins: Vec<String>,
+}
+
+/// A TLS client configuration built once and shared by the HTTP `GET`s and
+/// the WebSocket session, so both paths trust and present the same things.
+#[derive(Clone)]
+pub struct TlsConfig(Arc<ClientConfig>);
+
+impl TlsConfig {
+    pub fn new(options: &TlsOptions) -> Result<Self, CliError> {
+        let provider = Arc::new(ring::default_provider());
+        let mut roots = RootCertStore::empty();
+        if options.ca_certs.is_empty() {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        }
+        for path in &options.ca_certs {
+            for cert in read_certs(path, "--ca-cert")? {
+                roots.add(cert).map_err(|error| {
+                    invalid("--ca-cert", path, &format!("unusable certificate: {error}"))
+                })?;
+            }
+        }
+
+        let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+            .build()
+            .map_err(|error| CliError::InvalidOption(format!("--ca-cert: {error}")))?;
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|error| CliError::InvalidOption(format!("TLS setup failed: {error}")))?;
+        let builder = if options.pins.is_empty() {
+            builder.with_webpki_verifier(webpki)
+        } else {
+            let pins = options
+                .pins
+                .iter()
+                .map(|pin| parse_pin(pin))
+                .collect::<Result<_, _>>()?;
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedVerifier {
+                    inner: webpki,
+                    pins,
+                }))
+        };
+
+        let config = match (&options.client_cert, &options.client_key) {
+            (Some(cert), Some(key)) => {
+                let chain = read_certs(cert, "--client-cert")?;
+                let key = PrivateKeyDer::from_pem_file(key)
+                    .map_err(|error| invalid("--client-key", key, &error.to_string()))?;
+                builder
+                    .with_client_auth_cert(chain, key)
+                    .map_err(|error| CliError::InvalidOption(format!("--client-key: {error}")))?
+            }
+            (None, None) => builder.with_no_client_auth(),
+            _ => {
+                return Err(CliError::InvalidOption(
+                    "--client-cert and --client-key must be given together".to_owned(),
+                ))
+            }
+        };
+        Ok(Self(Arc::new(config)))
+    }
+
+    pub(crate) fn rustls(&self) -> Arc<ClientConfig> {
+        Arc::clone(&self.0)
+    }
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig").finish_non_exhaustive()
+    }
+}
+
+/// Verifies the chain as usual, then requires the leaf certificate's
+/// SHA-256 fingerprint to be one of `pins`.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let fingerprint: [u8; 32] = Sha256::digest(end_entity.as_ref()).into();
+        if self.pins.contains(&fingerprint) {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General(
+                "server certificate does not match any --pin-sha256".to_owned(),
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+fn read_certs(path: &Path, flag: &str) -> Result<Vec<CertificateDer<'static>>, CliError> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|error| invalid(flag, path, &error.to_string()))?;
+    if certs.is_empty() {
+        return Err(invalid(flag, path, "no PEM certificates found"));
+    }
+    Ok(certs)
+}
+
+fn parse_pin(pin: &str) -> Result<[u8; 32], CliError> {
+    let hex: String = pin.chars().filter(|c| *c != ':').collect();
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|start| {
+            hex.get(start..start + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>();
+    bytes
+        .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| {
+            CliError::InvalidOption(format!(
+                "--pin-sha256 `{pin}` is not a hex SHA-256 fingerprint"
+            ))
+        })
+}
+
+fn invalid(flag: &str, path: &Path, reason: &str) -> CliError {
+    CliError::InvalidOption(format!("{flag} {}: {reason}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        path::PathBuf,
+        sync::Arc,
+        thread,
+    };
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, Issuer, KeyPair};
+    use rustls::{
+        pki_types::{CertificateDer, PrivateKeyDer, ServerName},
+        ClientConnection, ServerConfig, ServerConnection, StreamOwned,
+    };
+    use sha2::{Digest, Sha256};
+    use tempfile::TempDir;
+
+    use crate::{
+        tls::{parse_pin, TlsConfig, TlsOptions},
+        CliError,
+    };
+
+    struct Pki {
+        _dir: TempDir,
+        ca_pem: PathBuf,
+        server: Arc<ServerConfig>,
+        leaf_fingerprint: String,
+    }
+
+    fn private_pki() -> Pki {
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = dir.path();
+
+        let ca_key = KeyPair::generate().expect("CA key should generate");
+        let mut ca_params = CertificateParams::new(Vec::new()).expect("CA params should build");
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).expect("CA should self-sign");
+        let ca_pem = root.join("ca.pem");
+        fs::write(&ca_pem, ca.pem()).expect("CA should be written");
+        let issuer = Issuer::new(ca_params, ca_key);
+
+        let leaf_key = KeyPair::generate().expect("leaf key should generate");
+        let leaf = CertificateParams::new(vec!["localhost".to_owned()])
+            .expect("leaf params should build")
+            .signed_by(&leaf_key, &issuer)
+            .expect("leaf should be signed");
+        let leaf_fingerprint = Sha256::digest(leaf.der())
+            .iter()
+            .map(|byte| format!("{byte:02X}"))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let server =
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .expect("protocol versions should be supported")
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![CertificateDer::from(leaf.der().to_vec())],
+                    PrivateKeyDer::try_from(leaf_key.serialize_der()).expect("key should convert"),
+                )
+                .expect("server config should build");
+
+        Pki {
+            _dir: dir,
+            ca_pem,
+            server: Arc::new(server),
+            leaf_fingerprint,
+        }
+    }
+
+    /// Runs one TLS handshake against a server presenting the test leaf and
+    /// reports whether the client accepted it.
+    fn handshake(pki: &Pki, config: &TlsConfig) -> bool {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("listener should bind");
+        let addr = listener
+            .local_addr()
+            .expect("listener should expose local addr");
+        let server_config = Arc::clone(&pki.server);
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("connection should arrive");
+            let connection = ServerConnection::new(server_config).expect("server should start");
+            let mut tls = StreamOwned::new(connection, stream);
+            let mut byte = [0_u8; 1];
+            if tls.read_exact(&mut byte).is_ok() {
+                let _ = tls.write_all(&byte);
+            }
+        });
+
+        let connection = ClientConnection::new(
+            config.rustls(),
+            ServerName::try_from("localhost").expect("name should parse"),
+        )
+        .expect("client should start");
+        let stream = TcpStream::connect(addr).expect("client should connect");
+        let mut tls = StreamOwned::new(connection, stream);
+        let mut byte = [0_u8; 1];
+        let accepted = tls
+            .write_all(b"x")
+            .and_then(|()| tls.read_exact(&mut byte))
+            .is_ok();
+        drop(tls);
+        let _ = server.join();
+        accepted
+    }
+
+    #[test]
+    fn private_ca_and_pins_gate_the_handshake() {
+        let pki = private_pki();
+
+        let default_roots = TlsConfig::new(&TlsOptions::default()).expect("config should build");
+        assert!(!handshake(&pki, &default_roots));
+
+        let private_ca = TlsOptions {
+            ca_certs: vec![pki.ca_pem.clone()],
+            ..TlsOptions::default()
+        };
+        let trusted = TlsConfig::new(&private_ca).expect("config should build");
+        assert!(handshake(&pki, &trusted));
+
+        let pinned = TlsConfig::new(&TlsOptions {
+            pins: vec![pki.leaf_fingerprint.clone()],
+            ..private_ca.clone()
+        })
+        .expect("config should build");
+        assert!(handshake(&pki, &pinned));
+
+        let mispinned = TlsConfig::new(&TlsOptions {
+            pins: vec!["00".repeat(32)],
+            ..private_ca
+        })
+        .expect("config should build");
+        assert!(!handshake(&pki, &mispinned));
+    }
+
+    #[test]
+    fn invalid_tls_options_are_rejected() {
+        assert!(parse_pin(&"ab".repeat(32)).is_ok());
+        assert!(matches!(parse_pin("abc"), Err(CliError::InvalidOption(_))));
+        assert!(matches!(
+            TlsConfig::new(&TlsOptions {
+                ca_certs: vec![PathBuf::from("/nonexistent/ca.pem")],
+                ..TlsOptions::default()
+            }),
+            Err(CliError::InvalidOption(_))
+        ));
+        assert!(matches!(
+            TlsConfig::new(&TlsOptions {
+                client_cert: Some(PathBuf::from("/nonexistent/client.pem")),
+                ..TlsOptions::default()
+            }),
+            Err(CliError::InvalidOption(_))
+        ));
+    }
+}
@@ -0,0 +1,161 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{CliError, StateDir};
+
+const TOKENS_FILE: &str = "tokens.json";
+const TOKENS_VERSION: u32 = 1;
+
+/// A device token a gateway issued in `hello-ok` after pairing.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeviceToken {
+    /// The normalized base URL of the gateway that issued the token.
+    pub server: String,
+    pub device_id: String,
+    pub token: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issued_at_ms: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredTokens {
+    version: u32,
+    #[serde(default)]
+    tokens: Vec<DeviceToken>,
+}
+
+/// Device tokens persisted in the state directory, one per server and
+/// device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TokenStore {
+    state: StateDir,
+}
+
+impl TokenStore {
+    pub fn new(state: StateDir) -> Self {
+        Self { state }
+    }
+
+    pub fn list(&self) -> Result<Vec<DeviceToken>, CliError> {
+        let Some(contents) = self.state.read(TOKENS_FILE)? else {
+            return Ok(Vec::new());
+        };
+
+        serde_json::from_slice::<StoredTokens>(&contents)
+            .map(|stored| stored.tokens)
+            .map_err(|error| {
+                CliError::State(format!(
+                    "invalid token store {}: {error}",
+                    self.state.root().join(TOKENS_FILE).display()
+                ))
+            })
+    }
+
+    pub fn find(&self, server: &str, device_id: &str) -> Result<Option<DeviceToken>, CliError> {
+        Ok(self
+            .list()?
+            .into_iter()
+            .find(|token| token.server == server && token.device_id == device_id))
+    }
+
+    /// Stores `token`, replacing any earlier token for the same server and
+    /// device.
+    pub fn save(&self, token: DeviceToken) -> Result<(), CliError> {
+        let mut tokens = self.list()?;
+        tokens
+            .retain(|stored| stored.server != token.server || stored.device_id != token.device_id);
+        tokens.push(token);
+        self.write(tokens)
+    }
+
+    /// Forgets the tokens for `server`, or for every server when `None`.
+    /// Returns how many were removed.
+    pub fn forget(&self, server: Option<&str>) -> Result<usize, CliError> {
+        let mut tokens = self.list()?;
+        let before = tokens.len();
+        tokens.retain(|token| server.is_some_and(|server| token.server != server));
+        let removed = before - tokens.len();
+        if removed > 0 {
+            self.write(tokens)?;
+        }
+        Ok(removed)
+    }
+
+    pub(crate) fn remove(&self, server: &str, device_id: &str) -> Result<(), CliError> {
+        let mut tokens = self.list()?;
+        let before = tokens.len();
+        tokens.retain(|token| token.server != server || token.device_id != device_id);
+        if tokens.len() != before {
+            self.write(tokens)?;
+        }
+        Ok(())
+    }
+
+    fn write(&self, tokens: Vec<DeviceToken>) -> Result<(), CliError> {
+        let stored = StoredTokens {
+            version: TOKENS_VERSION,
+            tokens,
+        };
+        let contents = serde_json::to_vec_pretty(&stored)
+            .map_err(|error| CliError::State(format!("failed to encode token store: {error}")))?;
+        self.state.write_private(TOKENS_FILE, &contents)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DeviceToken, StateDir, TokenStore};
+
+    fn token(server: &str, value: &str) -> DeviceToken {
+        DeviceToken {
+            server: server.to_owned(),
+            device_id: "device-1".to_owned(),
+            token: value.to_owned(),
+            role: Some("operator".to_owned()),
+            scopes: Vec::new(),
+            issued_at_ms: None,
+        }
+    }
+
+    #[test]
+    fn tokens_are_replaced_per_server_and_forgotten() {
+        let dir = tempfile::tempdir().expect("temp dir should be created");
+        let root = dir.path();
+        let store = TokenStore::new(StateDir::new(root));
+        assert!(store.list().expect("empty store should list").is_empty());
+
+        store
+            .save(token("http://a", "old"))
+            .expect("token should save");
+        store
+            .save(token("http://a", "new"))
+            .expect("token should save");
+        store
+            .save(token("http://b", "other"))
+            .expect("token should save");
+
+        let found = store
+            .find("http://a", "device-1")
+            .expect("store should read")
+            .expect("token should be stored");
+        assert_eq!(found.token, "new");
+        assert_eq!(store.list().expect("store should read").len(), 2);
+
+        assert_eq!(
+            store
+                .forget(Some("http://a"))
+                .expect("forget should succeed"),
+            1
+        );
+        assert!(store
+            .find("http://a", "device-1")
+            .expect("store should read")
+            .is_none());
+        assert_eq!(store.forget(None).expect("forget should succeed"), 1);
+        assert!(store.list().expect("store should read").is_empty());
+    }
+}